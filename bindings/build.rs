@@ -1,7 +1,34 @@
 fn main() {
     windows::build!(
         Windows::Win32::Foundation::POINT,
+        Windows::Win32::Graphics::Gdi::{
+            BitBlt,
+            BITMAPINFO,
+            BITMAPINFOHEADER,
+            CreateCompatibleBitmap,
+            CreateCompatibleDC,
+            DeleteDC,
+            DeleteObject,
+            GetDC,
+            GetDIBits,
+            GetPixel,
+            ReleaseDC,
+            SelectObject,
+        },
+        Windows::Win32::System::Console::{
+            SetConsoleCtrlHandler,
+        },
+        Windows::Win32::System::DataExchange::{
+            CloseClipboard,
+            GetClipboardData,
+            OpenClipboard,
+        },
+        Windows::Win32::System::Memory::{
+            GlobalLock,
+            GlobalUnlock,
+        },
         Windows::Win32::UI::KeyboardAndMouseInput::{
+            GetAsyncKeyState,
             GetKeyState,
             GetKeyboardState,
             INPUT,
@@ -13,7 +40,11 @@ fn main() {
             SendInput,
         },
         Windows::Win32::UI::WindowsAndMessaging::{
+            FindWindowW,
             GetCursorPos,
+            GetForegroundWindow,
+            GetWindowTextW,
+            GetSystemMetrics,
             SetCursorPos,
         },
     );
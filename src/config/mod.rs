@@ -1,7 +1,7 @@
-use std::{fs::File, io::BufReader, path::PathBuf, time::SystemTime};
+use std::{fs, path::{Path, PathBuf}, sync::Arc, time::SystemTime};
 
 use clap::Clap;
-use crate::replay::Commands;
+use crate::{hardware::{Desktop, ImageScreen, Screen}, replay::{self, Anchor, Assignment, Commands, Diagnostics, Dialect, Format, Journal, Position, Program, ReplayOptions, Report, Source}};
 
 #[derive(Clap)]
 #[clap(
@@ -12,6 +12,12 @@ use crate::replay::Commands;
 )]
 pub struct Configuration {
 
+    #[clap(
+        about = "disables aborting by moving the cursor into a screen corner",
+        long,
+    )]
+    pub no_panic_corner: bool,
+
     #[clap(subcommand)]
     pub subcommand: SubCommand,
 }
@@ -24,7 +30,38 @@ pub enum SubCommand {
     
     Record(RecordCommand),
 
+    Recover(RecoverCommand),
+
     Replay(ReplayCommand),
+
+    Sample(SampleCommand),
+
+    Schema(SchemaCommand),
+
+    Migrate(MigrateCommand),
+
+    Lint(LintCommand),
+
+    Convert(ConvertCommand),
+
+    Export(ExportCommand),
+
+    #[cfg(feature = "scripting")]
+    Script(ScriptCommand),
+}
+
+impl SubCommand {
+
+    /// Whether the subcommand works with the live desktop and has to listen for keys and the panic corner.
+    pub fn is_live(&self) -> bool {
+        match self {
+            SubCommand::Click | SubCommand::Record(_) | SubCommand::Sample(_) => true,
+            SubCommand::Replay(config) => !config.dry_run,
+            #[cfg(feature = "scripting")]
+            SubCommand::Script(_) => true,
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug, Clap)]
@@ -38,6 +75,12 @@ pub struct RecordCommand {
         validator = is_valid_out_file,
     )]
     out_file: Option<PathBuf>,
+
+    #[clap(
+        about = "the format of the records (yaml, json, toml, ron, macro or binary), detected by the file extension by default",
+        long,
+    )]
+    format: Option<Format>,
 }
 
 impl RecordCommand {
@@ -45,12 +88,59 @@ impl RecordCommand {
     pub fn out_file(&self) -> PathBuf {
         self.out_file
             .as_ref()
-            .map_or_else(Self::default_filename, |path| path.clone())
+            .map_or_else(|| self.default_filename(), |path| path.clone())
     }
 
-    fn default_filename() -> PathBuf {
+    pub fn format(&self) -> Format {
+        Format::resolve(self.format, &self.out_file())
+    }
+
+    fn default_filename(&self) -> PathBuf {
         let timestamp = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
-        PathBuf::from(format!("{}.yaml", timestamp.as_secs()))
+        PathBuf::from(format!("{}.{}", timestamp.as_secs(), self.format.unwrap_or(Format::Yaml).extension()))
+    }
+}
+
+#[derive(Clap)]
+#[clap(about = "rebuilds a recording from the journal left by a recorder which did not finish")]
+pub struct RecoverCommand {
+
+    #[clap(
+        about = "the journal, named like the out file of the recording with .journal appended",
+        validator = is_existing_file,
+    )]
+    journal: PathBuf,
+
+    #[clap(
+        about = "the path of the recording, the out file the journal belongs to by default",
+        short = 'o',
+        long,
+        validator = is_valid_out_file,
+    )]
+    out_file: Option<PathBuf>,
+
+    #[clap(
+        about = "the format of the recording (yaml, json, toml, ron, macro or binary), detected by the file extension by default",
+        long,
+    )]
+    format: Option<Format>,
+}
+
+impl RecoverCommand {
+
+    /// Writes the recording and returns the path written to together with the warnings about every command which
+    /// could not be read, the journal is only removed if there are none.
+    pub fn recover(&self) -> Result<(PathBuf, Vec<String>), String> {
+        let out_file = match &self.out_file {
+            Some(path) => path.clone(),
+            None => Journal::out_file(&self.journal)
+                .filter(|path| !path.exists())
+                .ok_or_else(|| String::from("the recording cannot be named after the journal, choose its path with -o"))?,
+        };
+        let (commands, warnings) = replay::recover(&self.journal)?;
+        commands.save(&out_file, Format::resolve(self.format, &out_file))?;
+        replay::retire(&self.journal, &warnings)?;
+        Ok((out_file, warnings))
     }
 }
 
@@ -62,20 +152,371 @@ pub struct ReplayCommand {
         about = "the file containing the replay",
         short = 'f',
         long,
-        validator = is_valid_replay_file,
+        validator = is_existing_file,
     )]
     file: PathBuf,
+
+    #[clap(
+        about = "the format of the replay (yaml, json, toml, ron, macro or binary), detected by the file extension by default",
+        long,
+    )]
+    format: Option<Format>,
+
+    #[clap(
+        about = "enables stepping through the replay with RIGHT (execute) and LEFT (skip) while paused",
+        short = 'd',
+        long,
+    )]
+    debug: bool,
+
+    #[clap(
+        about = "prints a timeline of every action and the estimated run time instead of replaying, replays reading the screen require --screen-image while the other conditions check the live system",
+        long,
+    )]
+    pub dry_run: bool,
+
+    #[clap(
+        about = "the index of the top level command or the label the replay starts at, nested commands are not counted",
+        long,
+    )]
+    from: Option<Anchor>,
+
+    #[clap(
+        about = "the index of the last top level command or its label replayed in every loop, nested commands are not counted",
+        long,
+    )]
+    to: Option<Anchor>,
+
+    #[clap(
+        about = "the file the current position is saved to whenever the replay gets paused",
+        long,
+        validator = is_valid_overwritable_file,
+    )]
+    checkpoint: Option<PathBuf>,
+
+    #[clap(
+        about = "resumes the replay at the position saved in the given checkpoint file",
+        long,
+        validator = is_valid_checkpoint_file,
+    )]
+    resume: Option<PathBuf>,
+
+    #[clap(
+        about = "overrides a parameter declared by the script, given as name=value",
+        long = "param",
+        value_name = "NAME=VALUE",
+        multiple_occurrences = true,
+    )]
+    params: Vec<Assignment>,
+
+    #[clap(
+        about = "reads pixels and screenshots from this PNG file instead of the screen",
+        long,
+        validator = is_valid_image_file,
+    )]
+    screen_image: Option<PathBuf>,
+
+    #[clap(
+        about = "the directory a screenshot is saved to whenever a command or assertion fails",
+        long,
+        value_name = "DIRECTORY",
+        validator = is_valid_screenshot_directory,
+    )]
+    screenshot_on_failure: Option<PathBuf>,
+
+    #[clap(
+        about = "writes the assertion results as JUnit XML to the given file",
+        long,
+        value_name = "FILE",
+        validator = is_valid_overwritable_file,
+    )]
+    junit: Option<PathBuf>,
 }
 
 impl ReplayCommand {
 
-    pub fn load_replay(&self) -> Commands {
-        let file = File::open(&self.file).expect("valid replay file");
-        let reader = BufReader::new(file);
-        serde_yaml::from_reader(reader).expect("valid replay content")
+    /// Writes the JUnit report if requested, the test suite is named after the replay file.
+    pub fn save_report(&self, report: &Report) -> Result<(), String> {
+        match &self.junit {
+            Some(path) => {
+                let suite = self.file.file_stem().map_or_else(|| String::from("replay"), |stem| stem.to_string_lossy().into_owned());
+                report.save_junit(&suite, path)
+            },
+            None => Ok(()),
+        }
+    }
+
+    pub fn options(&self, program: &Program) -> Result<ReplayOptions, String> {
+        let from = self.from.as_ref().map_or(Ok(0), |anchor| anchor.resolve(program))?;
+        let to = self.to.as_ref().map(|anchor| anchor.resolve(program)).transpose()?;
+        if let Some(to) = to.filter(|&to| to < from) {
+            return Err(format!("the replay range ends at #{} before it starts at #{}", to, from));
+        }
+        // the replay works on instructions, a range covers the nested blocks of its top level commands
+        let from = program.span(from).map_or(0, |(first, _)| first);
+        let to = to.and_then(|to| program.span(to)).map(|(_, last)| last);
+        let resume = self.resume.as_ref().map(|path| Position::load(path)).transpose()?;
+        let last = to.or_else(|| program.len().checked_sub(1));
+        if let Some(position) = resume.as_ref().filter(|p| p.entry() < from || Some(p.entry()) > last) {
+            return Err(format!("resume position #{} is outside of the replayed commands", position.entry()));
+        }
+        Ok(ReplayOptions {
+            debug: self.debug,
+            from,
+            to,
+            resume,
+            checkpoint: self.checkpoint.clone(),
+            failure_screenshots: self.screenshot_on_failure.clone(),
+        })
+    }
+
+    /// A dry run must not depend on whatever the desktop shows, so it reads the screen only from `--screen-image`.
+    pub fn screen(&self, program: &Program) -> Result<Arc<dyn Screen>, String> {
+        match &self.screen_image {
+            Some(path) => Ok(Arc::new(ImageScreen::load(path)?)),
+            None if self.dry_run && program.reads_screen() => Err(String::from("a dry run of a replay which reads the screen requires --screen-image")),
+            None => Ok(Arc::new(Desktop)),
+        }
+    }
+
+    pub fn load_replay(&self) -> Result<Program, String> {
+        let format = Format::resolve(self.format, &self.file);
+        check_replay(&self.file, format)?.iter().for_each(|warning| warn!("{}", warning));
+        let mut commands = Commands::load(&self.file, format)?;
+        commands.bind(&self.params)?;
+        commands.scope()?;
+        Program::compile(&commands)
+    }
+}
+
+#[derive(Clap)]
+#[clap(about = "prints the color under the cursor")]
+pub struct SampleCommand {
+
+    #[clap(
+        about = "keeps printing whenever the cursor position or color changes, stop with DOWN",
+        short = 'w',
+        long,
+    )]
+    pub watch: bool,
+}
+
+#[derive(Clap)]
+#[clap(about = "prints the JSON Schema of replay files for editor completion and validation")]
+pub struct SchemaCommand {
+
+    #[clap(
+        about = "the file the schema is written to instead of printing it",
+        short = 'o',
+        long,
+        validator = is_valid_overwritable_file,
+    )]
+    out_file: Option<PathBuf>,
+}
+
+impl SchemaCommand {
+
+    pub fn write(&self) -> Result<(), String> {
+        let schema = replay::schema()?;
+        match &self.out_file {
+            Some(path) => fs::write(path, schema).map_err(|e| format!("failed to write '{}' because '{:?}'", path.display(), e.kind())),
+            None => {
+                println!("{}", schema);
+                Ok(())
+            },
+        }
+    }
+}
+
+#[derive(Clap)]
+#[clap(about = "upgrades replay files to the current format version, the originals are kept as <file>.v<version>.bak")]
+pub struct MigrateCommand {
+
+    #[clap(
+        about = "the replay files to upgrade",
+        required = true,
+        validator = is_existing_file,
+    )]
+    files: Vec<PathBuf>,
+
+    #[clap(
+        about = "the format of the replays (yaml, json, toml, ron, macro or binary), detected by the file extension by default",
+        long,
+    )]
+    format: Option<Format>,
+}
+
+impl MigrateCommand {
+
+    pub fn files(&self) -> Vec<(&Path, Format)> {
+        self.files.iter().map(|file| (file.as_path(), Format::resolve(self.format, file))).collect()
+    }
+}
+
+#[derive(Clap)]
+#[clap(about = "checks a replay for errors and for suspicious constructs like unreachable commands or hotkey presses")]
+pub struct LintCommand {
+
+    #[clap(
+        about = "the file containing the replay",
+        short = 'f',
+        long,
+        validator = is_existing_file,
+    )]
+    file: PathBuf,
+
+    #[clap(
+        about = "the format of the replay (yaml, json, toml, ron, macro or binary), detected by the file extension by default",
+        long,
+    )]
+    format: Option<Format>,
+
+    #[clap(
+        about = "checks coordinates against the size of this PNG file instead of the screen",
+        long,
+        validator = is_valid_image_file,
+    )]
+    screen_image: Option<PathBuf>,
+
+    #[clap(
+        about = "fails on warnings too",
+        long,
+    )]
+    pub deny_warnings: bool,
+}
+
+impl LintCommand {
+
+    /// Coordinates are not checked if the size of the screen is unknown.
+    pub fn lint(&self) -> Result<Diagnostics, String> {
+        let size = match &self.screen_image {
+            Some(path) => ImageScreen::load(path)?.size(),
+            None => Desktop.size(),
+        };
+        let screen = size.map_err(|e| warn!("coordinates are not checked: {}", e)).ok();
+        Diagnostics::lint(&self.file, Format::resolve(self.format, &self.file), screen)
+    }
+}
+
+#[derive(Clap)]
+#[clap(about = "converts an AutoHotkey script, xdotool commands or a replay in another format into a replay")]
+pub struct ConvertCommand {
+
+    #[clap(
+        about = "the script or replay to convert",
+        validator = is_existing_file,
+    )]
+    file: PathBuf,
+
+    #[clap(
+        about = "the language of the script (ahk or xdotool) or the format of the replay, detected by the file extension by default",
+        long,
+    )]
+    from: Option<Source>,
+
+    #[clap(
+        about = "the path of the replay file, the replay is printed if omitted",
+        short = 'o',
+        long,
+        validator = is_valid_out_file,
+    )]
+    out_file: Option<PathBuf>,
+
+    #[clap(
+        about = "the format of the replay (yaml, json, toml, ron, macro or binary), detected by the file extension by default",
+        long,
+    )]
+    format: Option<Format>,
+}
+
+impl ConvertCommand {
+
+    /// Writes or prints the replay and returns the warnings about everything left out.
+    pub fn convert(&self) -> Result<Vec<String>, String> {
+        let (commands, warnings) = match self.from.or_else(|| Source::from_path(&self.file)) {
+            Some(Source::Script(dialect)) => {
+                let source = fs::read_to_string(&self.file).map_err(|e| format!("failed to open '{}' because '{:?}'", self.file.display(), e.kind()))?;
+                replay::import(&source, dialect).map_err(|e| format!("failed to convert '{}': {}", self.file.display(), e))?
+            },
+            // includes stay references to their files
+            Some(Source::Replay(format)) => (Commands::read(&self.file, format)?, Vec::new()),
+            None => return Err(format!("the language or format of '{}' is unknown, choose it with --from", self.file.display())),
+        };
+        match &self.out_file {
+            Some(path) => commands.save(path, Format::resolve(self.format, path))?,
+            None => println!("{}", self.format.unwrap_or(Format::Yaml).render(&commands)?),
+        }
+        Ok(warnings)
+    }
+}
+
+#[derive(Clap)]
+#[clap(about = "exports a replay as AutoHotkey v2 or xdotool shell script")]
+pub struct ExportCommand {
+
+    #[clap(
+        about = "the file containing the replay",
+        short = 'f',
+        long,
+        validator = is_existing_file,
+    )]
+    file: PathBuf,
+
+    #[clap(
+        about = "the format of the replay (yaml, json, toml, ron, macro or binary), detected by the file extension by default",
+        long,
+    )]
+    format: Option<Format>,
+
+    #[clap(
+        about = "the language of the script (ahk or xdotool), detected by the extension of the out file by default",
+        long,
+    )]
+    to: Option<Dialect>,
+
+    #[clap(
+        about = "the path of the script, the script is printed if omitted",
+        short = 'o',
+        long,
+        validator = is_valid_out_file,
+    )]
+    out_file: Option<PathBuf>,
+}
+
+impl ExportCommand {
+
+    /// Writes or prints the script and returns the warnings about everything left out.
+    pub fn export(&self) -> Result<Vec<String>, String> {
+        let dialect = self.to
+            .or_else(|| self.out_file.as_deref().and_then(Dialect::from_path))
+            .ok_or_else(|| String::from("the language of the script is unknown, choose it with --to"))?;
+        let format = Format::resolve(self.format, &self.file);
+        check_replay(&self.file, format)?.iter().for_each(|warning| warn!("{}", warning));
+        let commands = Commands::load(&self.file, format)?;
+        commands.validate()?;
+        let (script, warnings) = replay::export(&commands, dialect);
+        match &self.out_file {
+            Some(path) => fs::write(path, script).map_err(|e| format!("failed to write '{}' because '{:?}'", path.display(), e.kind()))?,
+            None => print!("{}", script),
+        }
+        Ok(warnings)
     }
 }
 
+#[cfg(feature = "scripting")]
+#[derive(Clap)]
+#[clap(about = "runs a rhai script, start/pause with UP")]
+pub struct ScriptCommand {
+
+    #[clap(
+        about = "the file containing the script",
+        short = 'f',
+        long,
+        validator = is_valid_script_file,
+    )]
+    pub file: PathBuf,
+}
+
 fn is_valid_out_file(s: &str) -> Result<(), String> {
     let path = PathBuf::from(s);
     if path.is_dir() {
@@ -95,31 +536,73 @@ fn is_valid_out_file(s: &str) -> Result<(), String> {
     }
 }
 
-fn is_valid_replay_file(s: &str) -> Result<(), String> {
-    let path = PathBuf::from(s);
-    match path.is_file() {
-        false => Err(format!("replay file '{}' does not exist", s)),
-        true => {
-            File::open(path)
-                .map_err(|e| format!("failed to open '{}' because '{:?}'", s, e.kind()))
-                .map(|file| BufReader::new(file))
-                .and_then(|reader| 
-                    serde_yaml::from_reader::<_, Commands>(reader).map_err(|e| format!("'{}' contains an invalid replay: {}", s, e))
-                )
-                .and_then(|commands| 
-                    match commands.iter_commands().find(|c| !c.is_valid()) {
-                        Some(c) => Err(format!("invalid command {:?} in repaly '{}'", c, s)),
-                        None => Ok(()),
-                    }
-                )
-        }
+/// Fails with all diagnostics if the replay has errors and returns the rendered warnings otherwise, replays are only
+/// checked where they get loaded.
+fn check_replay(path: &Path, format: Format) -> Result<Vec<String>, String> {
+    let diagnostics = Diagnostics::check(path, format)?;
+    match diagnostics.is_ok() {
+        true => Ok(diagnostics.iter().map(|warning| diagnostics.render_item(warning)).collect()),
+        false => Err(format!("'{}' has {} error(s) and {} warning(s)\n\n{}", path.display(), diagnostics.errors(), diagnostics.warnings(), diagnostics.render())),
+    }
+}
+
+#[cfg(feature = "scripting")]
+fn is_valid_script_file(s: &str) -> Result<(), String> {
+    crate::script::compile(&PathBuf::from(s)).map(|_| ())
+}
+
+fn is_existing_file(s: &str) -> Result<(), String> {
+    match PathBuf::from(s).is_file() {
+        true => Ok(()),
+        false => Err(format!("file '{}' does not exist", s)),
+    }
+}
+
+fn is_valid_image_file(s: &str) -> Result<(), String> {
+    ImageScreen::load(&PathBuf::from(s)).map(|_| ())
+}
+
+fn is_valid_overwritable_file(s: &str) -> Result<(), String> {
+    match PathBuf::from(s).is_dir() {
+        true => Err(format!("'{}' is a directory", s)),
+        false => Ok(()),
+    }
+}
+
+fn is_valid_screenshot_directory(s: &str) -> Result<(), String> {
+    match PathBuf::from(s).is_file() {
+        true => Err(format!("screenshot directory '{}' is a file", s)),
+        false => Ok(()),
     }
 }
+
+fn is_valid_checkpoint_file(s: &str) -> Result<(), String> {
+    Position::load(&PathBuf::from(s)).map(|_| ())
+}
 #[cfg(test)]
 mod tests {
-    use crate::config::is_valid_out_file;
+    use std::path::PathBuf;
+
+    use crate::{config::is_valid_out_file, replay::Anchor};
+
+    use super::{Format, RecordCommand, ReplayCommand};
 
-    use super::RecordCommand;
+    fn replay_command(from: Option<Anchor>, to: Option<Anchor>) -> ReplayCommand {
+        ReplayCommand {
+            file: PathBuf::from("./test/commands.yaml"),
+            format: None,
+            debug: false,
+            dry_run: false,
+            from,
+            to,
+            checkpoint: None,
+            resume: None,
+            params: Vec::new(),
+            screen_image: None,
+            screenshot_on_failure: None,
+            junit: None,
+        }
+    }
     
     #[test]
     fn valid_out_files() {
@@ -128,7 +611,7 @@ mod tests {
             "test/test.yaml"
         ]
         .into_iter()
-        .map(|path| is_valid_out_file(path))
+        .map(is_valid_out_file)
         .enumerate()
         .for_each(|(case, result)| assert!(result.is_ok(), "case {} - {}", case, result.err().unwrap()));
     }
@@ -142,32 +625,118 @@ mod tests {
             "test",
         ]
         .into_iter()
-        .map(|path| is_valid_out_file(path))
+        .map(is_valid_out_file)
         .enumerate()
         .for_each(|(case, result)| assert!(result.is_err(), "case {}", case));
     }
 
     #[test]
     fn default_filename() {
-        let filename = RecordCommand::default_filename();
+        let filename = RecordCommand { out_file: None, format: None }.default_filename();
         let s = filename.to_string_lossy();
         assert!(s.ends_with(".yaml"), "{} does not end with .yaml", filename.to_string_lossy());
         assert!(!s.starts_with(".yaml"), "{} has no epoch seconds", filename.to_string_lossy());
+        let command = RecordCommand { out_file: None, format: Some(Format::Json) };
+        assert!(command.default_filename().to_string_lossy().ends_with(".json"));
+        assert_eq!(Format::Json, command.format());
+        assert_eq!(Format::Toml, RecordCommand { out_file: Some("records.toml".into()), format: None }.format());
+    }
+
+    #[test]
+    fn valid_replays() {
+        vec![
+            "./test/commands.yaml",
+            "./test/variables.yaml",
+            "./test/control_flow.yaml",
+            "./test/procedures.yaml",
+            "./test/parameters.yaml",
+            "./test/wait.yaml",
+            "./test/assertions.yaml",
+            "./test/generated.json",
+            "./test/generated.ron",
+            "./test/commands.macro",
+        ]
+        .into_iter()
+        .map(PathBuf::from)
+        .for_each(|path| assert!(super::check_replay(&path, Format::resolve(None, &path)).is_ok(), "{}", path.display()));
+    }
+
+    #[test]
+    fn invalid_replays() {
+        assert!(super::is_existing_file("./test").is_err());
+        vec![
+            "./test/faulty.yaml",
+            "./test/cycle.yaml",
+            "./test/diagnostics.yaml",
+            "./test/invalid.yaml",
+            "./test/structure.yaml",
+        ]
+        .into_iter()
+        .map(PathBuf::from)
+        .for_each(|path| assert!(super::check_replay(&path, Format::Yaml).is_err(), "{}", path.display()));
+    }
+
+    #[test]
+    fn options() {
+        let command = replay_command(None, None);
+        let program = command.load_replay().expect("valid replay");
+        let options = command.options(&program).expect("valid options");
+        assert_eq!((0, None), (options.from, options.to));
+        let options = replay_command(Some(Anchor::Index(1)), Some(Anchor::Index(2))).options(&program).expect("valid options");
+        assert_eq!((1, Some(2)), (options.from, options.to));
     }
 
     #[test]
-    fn is_valid_replay_file() {
-        let result = super::is_valid_replay_file("./test/commands.yaml");
-        assert!(result.is_ok());
+    fn invalid_options() {
+        let program = replay_command(None, None).load_replay().expect("valid replay");
+        vec![
+            replay_command(Some(Anchor::Index(3)), None),
+            replay_command(None, Some(Anchor::Label("missing".into()))),
+            replay_command(Some(Anchor::Index(2)), Some(Anchor::Index(1))),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, command)| assert!(command.options(&program).is_err(), "case {}", case));
     }
 
     #[test]
-    fn is_invalid_replay_file() {
+    fn dry_run_screen() {
+        let command = |file: &str, screen_image: Option<&str>| ReplayCommand {
+            file: PathBuf::from(file),
+            dry_run: true,
+            screen_image: screen_image.map(PathBuf::from),
+            ..replay_command(None, None)
+        };
         vec![
-            super::is_valid_replay_file("./test"),
-            super::is_valid_replay_file("./test/faulty.yaml"),
+            (true, command("./test/commands.yaml", None)),
+            (true, command("./test/assertions.yaml", Some("./test/screen.png"))),
+            (false, command("./test/assertions.yaml", None)),
         ]
         .into_iter()
-        .for_each(|result| assert!(result.is_err()));
+        .enumerate()
+        .for_each(|(case, (expected, command))| {
+            let program = command.load_replay().expect("valid replay");
+            assert_eq!(expected, command.screen(&program).is_ok(), "case {}", case);
+        });
+    }
+
+    #[test]
+    fn load_replay_with_params() {
+        let command = |params: Vec<&str>| ReplayCommand {
+            file: PathBuf::from("./test/parameters.yaml"),
+            params: params.into_iter().map(|param| param.parse().expect("valid assignment")).collect(),
+            ..replay_command(None, None)
+        };
+        vec![
+            (true, vec!["target=point(10, 20)"]),
+            (true, vec!["target=point(10, 20)", "count=5", "title=main menu"]),
+            (false, vec![]),
+            (false, vec!["target=10"]),
+            (false, vec!["target=point(10, 20)", "count=many"]),
+            (false, vec!["target=point(10, 20)", "speed=3"]),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, (expected, params))| assert_eq!(expected, command(params).load_replay().is_ok(), "case {}", case));
     }
 }
@@ -2,7 +2,7 @@ use std::{convert::TryFrom, sync::mpsc::Sender, thread, time::Duration};
 
 use bindings::Windows::Win32::{Foundation::BOOL, UI::KeyboardAndMouseInput::{GetKeyState, GetKeyboardState}};
 
-use crate::hardware::Key;
+use crate::{hardware::Key, lifecycle::Lifecycle};
 
 pub use self::signal::Signal;
 
@@ -22,10 +22,10 @@ impl EventGrid {
         }
     }
 
-    pub fn start(mut self) {
-        thread::spawn(move || unsafe {
+    pub fn start(mut self, lifecycle: &mut Lifecycle) {
+        lifecycle.spawn("eventgrid", move |token| unsafe {
             let inputs = &mut [0u8; 256];
-            loop {
+            while !token.is_cancelled() {
                 let _ = GetKeyState(0);               
                 match GetKeyboardState(inputs.as_mut_ptr()) {
                     BOOL(0) => error!("failed to retrieve keyboard state"),
@@ -41,7 +41,10 @@ impl EventGrid {
                                     let signal = key.into();
                                     match self.tx.send(signal) {
                                         Ok(_) => info!("published {:?} on eventgrid", signal),
-                                        Err(_) => return error!("broken signal sender, prepare shutdown..."),
+                                        Err(_) => {
+                                            error!("broken signal sender, prepare shutdown...");
+                                            token.cancel();
+                                        },
                                     }
                                     self.memory[key as usize] = true;
                                 } else if state <= 1 {
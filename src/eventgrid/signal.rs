@@ -5,6 +5,7 @@ pub enum Signal {
     Input(Key),
     Pause,
     Shutdown,
+    Finished,
 }
 
 impl From<Key> for Signal {
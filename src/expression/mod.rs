@@ -0,0 +1,241 @@
+use std::{collections::HashMap, fmt::{self, Debug, Display}, ops::Deref, sync::OnceLock};
+
+use schemars::{JsonSchema, gen::SchemaGenerator, schema::Schema};
+use serde::{Deserialize, Serialize};
+
+pub use self::parser::Expression;
+pub use self::template::Template;
+pub use self::value::Value;
+
+mod parser;
+mod template;
+mod value;
+
+#[derive(Clone, Debug, Default)]
+pub struct Scope {
+    variables: HashMap<String, Value>,
+}
+
+impl Scope {
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.variables.get(name)
+    }
+
+    pub fn set(&mut self, name: &str, value: Value) {
+        self.variables.insert(name.into(), value);
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Value> {
+        self.variables.remove(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.variables.iter()
+    }
+}
+
+/// A command field given either as plain number or as expression evaluated at replay time.
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Argument {
+    Literal(i64),
+    Expression(Source),
+}
+
+impl Argument {
+
+    pub fn validate(&self) -> Result<(), String> {
+        self.expression().map(|_| ())
+    }
+
+    pub fn expression(&self) -> Result<Option<Expression>, String> {
+        match self {
+            Argument::Literal(_) => Ok(None),
+            Argument::Expression(source) => source.parsed().cloned().map(Some),
+        }
+    }
+
+    pub fn evaluate(&self, scope: &Scope) -> Result<Value, String> {
+        match self {
+            Argument::Literal(n) => Ok(Value::from(*n)),
+            Argument::Expression(source) => source
+                .parsed()?
+                .evaluate(scope)
+                .map_err(|e| format!("failed to evaluate '{}': {}", self, e)),
+        }
+    }
+
+    pub fn literal(&self) -> Option<i64> {
+        match self {
+            Argument::Literal(n) => Some(*n),
+            Argument::Expression(_) => None,
+        }
+    }
+}
+
+impl Display for Argument {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Argument::Literal(n) => write!(f, "{}", n),
+            Argument::Expression(source) => write!(f, "{}", source),
+        }
+    }
+}
+
+/// The text of an expression argument, parsed only once when it is first validated or evaluated.
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(from = "String", into = "String")]
+pub struct Source {
+    text: String,
+    parsed: OnceLock<Result<Expression, String>>,
+}
+
+impl Source {
+
+    fn parsed(&self) -> Result<&Expression, String> {
+        self.parsed
+            .get_or_init(|| Expression::parse(&self.text).map_err(|e| format!("invalid expression '{}': {}", self.text, e)))
+            .as_ref()
+            .map_err(String::clone)
+    }
+}
+
+impl Deref for Source {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.text
+    }
+}
+
+impl PartialEq for Source {
+
+    fn eq(&self, other: &Self) -> bool {
+        self.text == other.text
+    }
+}
+
+impl PartialEq<str> for Source {
+
+    fn eq(&self, other: &str) -> bool {
+        self.text == other
+    }
+}
+
+impl Debug for Source {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&self.text, f)
+    }
+}
+
+impl Display for Source {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Display::fmt(&self.text, f)
+    }
+}
+
+impl JsonSchema for Source {
+
+    fn schema_name() -> String {
+        String::schema_name()
+    }
+
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        String::json_schema(generator)
+    }
+}
+
+impl From<String> for Source {
+
+    fn from(text: String) -> Self {
+        Self { text, parsed: OnceLock::new() }
+    }
+}
+
+impl From<&str> for Source {
+
+    fn from(text: &str) -> Self {
+        Self::from(String::from(text))
+    }
+}
+
+impl From<Source> for String {
+
+    fn from(source: Source) -> Self {
+        source.text
+    }
+}
+
+impl From<i32> for Argument {
+
+    fn from(n: i32) -> Self {
+        Argument::Literal(n.into())
+    }
+}
+
+impl From<usize> for Argument {
+
+    fn from(n: usize) -> Self {
+        Argument::Literal(n as i64)
+    }
+}
+
+impl From<&str> for Argument {
+
+    fn from(source: &str) -> Self {
+        Argument::Expression(source.into())
+    }
+}
+
+impl From<u64> for Argument {
+
+    fn from(n: u64) -> Self {
+        Argument::Literal(n as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Argument, Scope, Value};
+
+    #[test]
+    fn deserialize_argument() {
+        assert_eq!(Ok(Argument::Literal(12)), serde_yaml::from_str::<Argument>("12").map_err(|e| e.to_string()));
+        assert_eq!(Ok(Argument::Expression("x + 1".into())), serde_yaml::from_str::<Argument>("x + 1").map_err(|e| e.to_string()));
+        assert_eq!("---\n12\n", serde_yaml::to_string(&Argument::Literal(12)).unwrap());
+    }
+
+    #[test]
+    fn evaluate_argument() {
+        let mut scope = Scope::default();
+        scope.set("step", Value::Number(20.0));
+        assert_eq!(Ok(Value::Number(5.0)), Argument::Literal(5).evaluate(&scope));
+        assert_eq!(Ok(Value::Number(140.0)), Argument::Expression("100 + step * 2".into()).evaluate(&scope));
+        assert!(Argument::Expression("100 +".into()).validate().is_err());
+        assert!(Argument::Expression("missing".into()).evaluate(&scope).is_err());
+    }
+
+    #[test]
+    fn parse_once() {
+        let argument = Argument::Expression("step * 2".into());
+        let mut scope = Scope::default();
+        (1..4).for_each(|step| {
+            scope.set("step", Value::Number(step as f64));
+            assert_eq!(Ok(Value::Number(step as f64 * 2.0)), argument.evaluate(&scope));
+        });
+        match &argument {
+            Argument::Expression(source) => assert!(source.parsed.get().is_some_and(Result::is_ok)),
+            Argument::Literal(_) => unreachable!(),
+        }
+        assert_eq!(Argument::Expression("step * 2".into()), argument);
+        assert_eq!("Expression(\"step * 2\")", format!("{:?}", argument));
+    }
+}
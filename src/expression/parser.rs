@@ -0,0 +1,401 @@
+use std::{cmp::Ordering, iter::Peekable, str::CharIndices};
+
+use super::{Scope, Value};
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Text(String),
+    Identifier(String),
+    Operator(&'static str),
+}
+
+const OPERATORS: [&str; 18] = [
+    "==", "!=", "<=", ">=", "&&", "||",
+    "+", "-", "*", "/", "%", "<", ">", "!", "(", ")", ",", ".",
+];
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, String> {
+    let mut tokens = Vec::new();
+    let mut chars: Peekable<CharIndices> = source.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                match c.is_ascii_digit() || c == '.' && source[i + 1..].starts_with(|n: char| n.is_ascii_digit()) {
+                    true => {
+                        end = i + c.len_utf8();
+                        chars.next();
+                    },
+                    false => break,
+                }
+            }
+            let number = source[start..end].parse().map_err(|_| format!("invalid number '{}' at column {}", &source[start..end], start + 1))?;
+            tokens.push((start, Token::Number(number)));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                match c.is_alphanumeric() || c == '_' {
+                    true => {
+                        end = i + c.len_utf8();
+                        chars.next();
+                    },
+                    false => break,
+                }
+            }
+            tokens.push((start, Token::Identifier(source[start..end].into())));
+        } else if c == '\'' || c == '"' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, q)) if q == c => break,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, escaped)) => text.push(escaped),
+                        None => return Err(format!("unterminated string at column {}", start + 1)),
+                    },
+                    Some((_, other)) => text.push(other),
+                    None => return Err(format!("unterminated string at column {}", start + 1)),
+                }
+            }
+            tokens.push((start, Token::Text(text)));
+        } else {
+            let operator = OPERATORS
+                .iter()
+                .find(|operator| source[start..].starts_with(*operator))
+                .ok_or_else(|| format!("unexpected '{}' at column {}", c, start + 1))?;
+            operator.chars().for_each(|_| { chars.next(); });
+            tokens.push((start, Token::Operator(operator)));
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BinaryOperator {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+impl BinaryOperator {
+
+    fn of(operator: &str) -> Option<(Self, u8)> {
+        match operator {
+            "||" => Some((Self::Or, 1)),
+            "&&" => Some((Self::And, 2)),
+            "==" => Some((Self::Equal, 3)),
+            "!=" => Some((Self::NotEqual, 3)),
+            "<" => Some((Self::Less, 4)),
+            "<=" => Some((Self::LessEqual, 4)),
+            ">" => Some((Self::Greater, 4)),
+            ">=" => Some((Self::GreaterEqual, 4)),
+            "+" => Some((Self::Add, 5)),
+            "-" => Some((Self::Subtract, 5)),
+            "*" => Some((Self::Multiply, 6)),
+            "/" => Some((Self::Divide, 6)),
+            "%" => Some((Self::Remainder, 6)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Literal(Value),
+    Variable(String),
+    Negate(Box<Node>),
+    Not(Box<Node>),
+    Binary(BinaryOperator, Box<Node>, Box<Node>),
+    Call(String, Vec<Node>),
+    Member(Box<Node>, String),
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    cursor: usize,
+    length: usize,
+}
+
+impl Parser {
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.cursor).map(|(_, token)| token)
+    }
+
+    fn column(&self) -> usize {
+        self.tokens.get(self.cursor).map_or(self.length, |(column, _)| *column) + 1
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.cursor).map(|(_, token)| token.clone());
+        self.cursor += 1;
+        token
+    }
+
+    fn expect(&mut self, operator: &str) -> Result<(), String> {
+        let column = self.column();
+        match self.next() {
+            Some(Token::Operator(o)) if o == operator => Ok(()),
+            Some(other) => Err(format!("expected '{}' but found {:?} at column {}", operator, other, column)),
+            None => Err(format!("expected '{}' but the expression ended", operator)),
+        }
+    }
+
+    fn binary(&mut self, min_precedence: u8) -> Result<Node, String> {
+        let mut left = self.unary()?;
+        while let Some(Token::Operator(o)) = self.peek() {
+            match BinaryOperator::of(o) {
+                Some((operator, precedence)) if precedence >= min_precedence => {
+                    self.next();
+                    let right = self.binary(precedence + 1)?;
+                    left = Node::Binary(operator, Box::new(left), Box::new(right));
+                },
+                _ => break,
+            }
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        match self.peek() {
+            Some(Token::Operator("-")) => {
+                self.next();
+                Ok(Node::Negate(Box::new(self.unary()?)))
+            },
+            Some(Token::Operator("!")) => {
+                self.next();
+                Ok(Node::Not(Box::new(self.unary()?)))
+            },
+            _ => self.postfix(),
+        }
+    }
+
+    fn postfix(&mut self) -> Result<Node, String> {
+        let mut node = self.primary()?;
+        while let Some(Token::Operator(".")) = self.peek() {
+            self.next();
+            let column = self.column();
+            match self.next() {
+                Some(Token::Identifier(member)) => node = Node::Member(Box::new(node), member),
+                _ => return Err(format!("expected a member name at column {}", column)),
+            }
+        }
+        Ok(node)
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        let column = self.column();
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Node::Literal(Value::Number(n))),
+            Some(Token::Text(s)) => Ok(Node::Literal(Value::Text(s))),
+            Some(Token::Identifier(name)) if name == "true" => Ok(Node::Literal(Value::Bool(true))),
+            Some(Token::Identifier(name)) if name == "false" => Ok(Node::Literal(Value::Bool(false))),
+            Some(Token::Identifier(name)) => match self.peek() {
+                Some(Token::Operator("(")) => {
+                    self.next();
+                    let mut arguments = Vec::new();
+                    if self.peek() != Some(&Token::Operator(")")) {
+                        arguments.push(self.binary(0)?);
+                        while self.peek() == Some(&Token::Operator(",")) {
+                            self.next();
+                            arguments.push(self.binary(0)?);
+                        }
+                    }
+                    self.expect(")")?;
+                    Ok(Node::Call(name, arguments))
+                },
+                _ => Ok(Node::Variable(name)),
+            },
+            Some(Token::Operator("(")) => {
+                let node = self.binary(0)?;
+                self.expect(")")?;
+                Ok(node)
+            },
+            Some(other) => Err(format!("unexpected {:?} at column {}", other, column)),
+            None => Err(String::from("unexpected end of the expression")),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expression {
+    root: Node,
+}
+
+impl Expression {
+
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            cursor: 0,
+            length: source.len(),
+        };
+        let root = parser.binary(0)?;
+        match parser.peek() {
+            None => Ok(Self { root }),
+            Some(token) => Err(format!("unexpected {:?} at column {}", token, parser.column())),
+        }
+    }
+
+    pub fn evaluate(&self, scope: &Scope) -> Result<Value, String> {
+        evaluate(&self.root, scope)
+    }
+
+    pub fn variables(&self) -> Vec<&str> {
+        let mut variables = Vec::new();
+        collect_variables(&self.root, &mut variables);
+        variables
+    }
+}
+
+fn collect_variables<'a>(node: &'a Node, variables: &mut Vec<&'a str>) {
+    match node {
+        Node::Literal(_) => (),
+        Node::Variable(name) => variables.push(name),
+        Node::Negate(inner) | Node::Not(inner) | Node::Member(inner, _) => collect_variables(inner, variables),
+        Node::Binary(_, left, right) => {
+            collect_variables(left, variables);
+            collect_variables(right, variables);
+        },
+        Node::Call(_, arguments) => arguments.iter().for_each(|argument| collect_variables(argument, variables)),
+    }
+}
+
+fn evaluate(node: &Node, scope: &Scope) -> Result<Value, String> {
+    match node {
+        Node::Literal(value) => Ok(value.clone()),
+        Node::Variable(name) => scope.get(name).cloned().ok_or_else(|| format!("unknown variable '{}'", name)),
+        Node::Negate(inner) => evaluate(inner, scope)?.negate(),
+        Node::Not(inner) => evaluate(inner, scope)?.not(),
+        Node::Member(inner, member) => evaluate(inner, scope)?.member(member),
+        Node::Binary(BinaryOperator::And, left, right) => match evaluate(left, scope)?.as_bool()? {
+            true => evaluate(right, scope)?.as_bool().map(Value::Bool),
+            false => Ok(Value::Bool(false)),
+        },
+        Node::Binary(BinaryOperator::Or, left, right) => match evaluate(left, scope)?.as_bool()? {
+            true => Ok(Value::Bool(true)),
+            false => evaluate(right, scope)?.as_bool().map(Value::Bool),
+        },
+        Node::Binary(operator, left, right) => {
+            let (left, right) = (evaluate(left, scope)?, evaluate(right, scope)?);
+            match operator {
+                BinaryOperator::Equal => Ok(Value::Bool(left == right)),
+                BinaryOperator::NotEqual => Ok(Value::Bool(left != right)),
+                BinaryOperator::Less => left.compare(&right).map(|o| Value::Bool(o == Ordering::Less)),
+                BinaryOperator::LessEqual => left.compare(&right).map(|o| Value::Bool(o != Ordering::Greater)),
+                BinaryOperator::Greater => left.compare(&right).map(|o| Value::Bool(o == Ordering::Greater)),
+                BinaryOperator::GreaterEqual => left.compare(&right).map(|o| Value::Bool(o != Ordering::Less)),
+                BinaryOperator::Add => left.add(&right),
+                BinaryOperator::Subtract => left.sub(&right),
+                BinaryOperator::Multiply => left.mul(&right),
+                BinaryOperator::Divide => left.div(&right),
+                BinaryOperator::Remainder => left.rem(&right),
+                BinaryOperator::And | BinaryOperator::Or => unreachable!("handled lazily"),
+            }
+        },
+        Node::Call(function, arguments) => {
+            let arguments = arguments
+                .iter()
+                .map(|argument| evaluate(argument, scope))
+                .collect::<Result<Vec<_>, _>>()?;
+            call(function, &arguments)
+        },
+    }
+}
+
+fn call(function: &str, arguments: &[Value]) -> Result<Value, String> {
+    match (function, arguments) {
+        ("point", [x, y]) => Ok(Value::Point(x.as_number()?, y.as_number()?)),
+        ("min", [a, b]) => Ok(Value::Number(a.as_number()?.min(b.as_number()?))),
+        ("max", [a, b]) => Ok(Value::Number(a.as_number()?.max(b.as_number()?))),
+        ("abs", [n]) => Ok(Value::Number(n.as_number()?.abs())),
+        ("round", [n]) => Ok(Value::Number(n.as_number()?.round())),
+        ("floor", [n]) => Ok(Value::Number(n.as_number()?.floor())),
+        ("str", [value]) => Ok(Value::Text(value.to_string())),
+        ("point", _) | ("min", _) | ("max", _) | ("abs", _) | ("round", _) | ("floor", _) | ("str", _) =>
+            Err(format!("wrong number of arguments for '{}'", function)),
+        _ => Err(format!("unknown function '{}'", function)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::expression::{Scope, Value};
+
+    use super::Expression;
+
+    fn scope() -> Scope {
+        let mut scope = Scope::default();
+        scope.set("x", Value::Number(100.0));
+        scope.set("origin", Value::Point(10.0, 20.0));
+        scope.set("name", Value::Text("grid".into()));
+        scope
+    }
+
+    fn evaluate(source: &str) -> Result<Value, String> {
+        Expression::parse(source).and_then(|expression| expression.evaluate(&scope()))
+    }
+
+    #[test]
+    fn evaluation() {
+        vec![
+            (Value::Number(7.0), "1 + 2 * 3"),
+            (Value::Number(9.0), "(1 + 2) * 3"),
+            (Value::Number(-5.0), "-(2 + 3)"),
+            (Value::Number(1.0), "10 % 3"),
+            (Value::Number(2.5), "5 / 2"),
+            (Value::Number(120.0), "x + 20"),
+            (Value::Number(30.0), "origin.x + origin.y"),
+            (Value::Point(30.0, 20.0), "origin + point(20, 0)"),
+            (Value::Point(20.0, 40.0), "origin * 2"),
+            (Value::Text("grid-100".into()), "name + '-' + x"),
+            (Value::Text("a\"b".into()), "\"a\\\"b\""),
+            (Value::Bool(true), "x > 50 && x <= 100"),
+            (Value::Bool(true), "x == 1 || !(x != 100)"),
+            (Value::Bool(false), "false && unknown"),
+            (Value::Number(3.0), "max(min(3, 5), abs(-2))"),
+            (Value::Number(4.0), "name.length"),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, (expected, source))| assert_eq!(Ok(expected), evaluate(source), "case {} - {}", case, source));
+    }
+
+    #[test]
+    fn errors() {
+        vec![
+            "",
+            "1 +",
+            "(1",
+            "1 2",
+            "'open",
+            "x $ 2",
+            "unknown + 1",
+            "point(1)",
+            "nope(1)",
+            "origin.z",
+            "x && true",
+        ]
+        .into_iter()
+        .for_each(|source| assert!(evaluate(source).is_err(), "{} should fail", source));
+    }
+
+    #[test]
+    fn variables() {
+        let expression = Expression::parse("x + point(y, 2).x * z").expect("valid expression");
+        assert_eq!(vec!["x", "y", "z"], expression.variables());
+    }
+}
@@ -0,0 +1,129 @@
+use std::{convert::TryFrom, time::SystemTime};
+
+use schemars::{JsonSchema, gen::SchemaGenerator, schema::Schema};
+use serde::{Deserialize, Serialize};
+
+use super::{Argument, Scope};
+
+/// A text with `{expression}` placeholders, `{timestamp}` stands for the seconds since the UNIX epoch.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Template {
+    source: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Text(String),
+    Placeholder(Argument),
+    Timestamp,
+}
+
+impl Template {
+
+    pub fn render(&self, scope: &Scope) -> Result<String, String> {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => Ok(text.clone()),
+                Part::Placeholder(argument) => argument.evaluate(scope).map(|value| value.to_string()),
+                Part::Timestamp => Ok(SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs().to_string()),
+            })
+            .collect()
+    }
+
+    pub fn arguments(&self) -> Vec<&Argument> {
+        self.parts
+            .iter()
+            .filter_map(|part| match part {
+                Part::Placeholder(argument) => Some(argument),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl TryFrom<String> for Template {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        let mut parts = Vec::new();
+        let mut rest = source.as_str();
+        while let Some(start) = rest.find(['{', '}']) {
+            if rest[start..].starts_with('}') {
+                return Err(format!("unmatched '}}' in '{}'", source));
+            }
+            let end = rest[start..].find('}').map(|end| start + end).ok_or_else(|| format!("unclosed '{{' in '{}'", source))?;
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].into()));
+            }
+            parts.push(match rest[start + 1..end].trim() {
+                "timestamp" => Part::Timestamp,
+                expression => {
+                    let argument = Argument::Expression(expression.into());
+                    argument.validate()?;
+                    Part::Placeholder(argument)
+                },
+            });
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.into()));
+        }
+        Ok(Self { source, parts })
+    }
+}
+
+impl JsonSchema for Template {
+
+    fn schema_name() -> String {
+        String::from("Template")
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        String::json_schema(generator)
+    }
+}
+
+impl From<Template> for String {
+
+    fn from(template: Template) -> Self {
+        template.source
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use crate::expression::{Scope, Value};
+
+    use super::Template;
+
+    #[test]
+    fn render() {
+        let mut scope = Scope::default();
+        scope.set("iteration", Value::Number(3.0));
+        scope.set("name", Value::Text("login".into()));
+        vec![
+            ("shot.png", "shot.png"),
+            ("shots/{name}-{iteration + 1}.png", "shots/login-4.png"),
+            ("{ iteration }", "3"),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, (source, expected))| assert_eq!(Ok(expected.into()), Template::try_from(String::from(source)).and_then(|t| t.render(&scope)), "case {}", case));
+        let timestamped = Template::try_from(String::from("{timestamp}.png")).unwrap().render(&scope).unwrap();
+        assert!(timestamped.trim_end_matches(".png").parse::<u64>().is_ok());
+        assert!(Template::try_from(String::from("{missing}")).unwrap().render(&scope).is_err());
+    }
+
+    #[test]
+    fn invalid() {
+        vec!["{iteration", "iteration}", "{1 +}", "{}"]
+            .into_iter()
+            .enumerate()
+            .for_each(|(case, source)| assert!(Template::try_from(String::from(source)).is_err(), "case {}", case));
+    }
+}
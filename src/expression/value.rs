@@ -0,0 +1,200 @@
+use std::{cmp::Ordering, convert::TryFrom, fmt::{self, Display}};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Value {
+    Number(f64),
+    Text(String),
+    Point(f64, f64),
+    Bool(bool),
+}
+
+impl Value {
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::Text(_) => "string",
+            Value::Point(..) => "point",
+            Value::Bool(_) => "bool",
+        }
+    }
+
+    pub fn as_number(&self) -> Result<f64, String> {
+        match self {
+            Value::Number(n) => Ok(*n),
+            other => Err(format!("expected a number but got {} '{}'", other.type_name(), other)),
+        }
+    }
+
+    pub fn as_bool(&self) -> Result<bool, String> {
+        match self {
+            Value::Bool(b) => Ok(*b),
+            other => Err(format!("expected a bool but got {} '{}'", other.type_name(), other)),
+        }
+    }
+
+    pub fn as_i32(&self) -> Result<i32, String> {
+        let n = self.as_number()?.round();
+        match n >= i32::MIN as f64 && n <= i32::MAX as f64 {
+            true => Ok(n as i32),
+            false => Err(format!("{} is out of range", n)),
+        }
+    }
+
+    pub fn as_u64(&self) -> Result<u64, String> {
+        let n = self.as_number()?.round();
+        match n >= 0.0 && n <= u64::MAX as f64 {
+            true => Ok(n as u64),
+            false => Err(format!("{} is not a positive number", n)),
+        }
+    }
+
+    pub fn as_u32(&self) -> Result<u32, String> {
+        let n = self.as_u64()?;
+        u32::try_from(n).map_err(|_| format!("{} is out of range", n))
+    }
+
+    pub fn as_usize(&self) -> Result<usize, String> {
+        self.as_u64().map(|n| n as usize)
+    }
+
+    pub fn negate(&self) -> Result<Value, String> {
+        match self {
+            Value::Number(n) => Ok(Value::Number(-n)),
+            Value::Point(x, y) => Ok(Value::Point(-x, -y)),
+            other => Err(format!("cannot negate {}", other.type_name())),
+        }
+    }
+
+    pub fn not(&self) -> Result<Value, String> {
+        self.as_bool().map(|b| Value::Bool(!b))
+    }
+
+    pub fn add(&self, other: &Value) -> Result<Value, String> {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a + b)),
+            (Value::Point(ax, ay), Value::Point(bx, by)) => Ok(Value::Point(ax + bx, ay + by)),
+            (Value::Text(a), b) => Ok(Value::Text(format!("{}{}", a, b))),
+            (a, Value::Text(b)) => Ok(Value::Text(format!("{}{}", a, b))),
+            (a, b) => Err(format!("cannot add {} and {}", a.type_name(), b.type_name())),
+        }
+    }
+
+    pub fn sub(&self, other: &Value) -> Result<Value, String> {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a - b)),
+            (Value::Point(ax, ay), Value::Point(bx, by)) => Ok(Value::Point(ax - bx, ay - by)),
+            (a, b) => Err(format!("cannot subtract {} from {}", b.type_name(), a.type_name())),
+        }
+    }
+
+    pub fn mul(&self, other: &Value) -> Result<Value, String> {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a * b)),
+            (Value::Point(x, y), Value::Number(n)) | (Value::Number(n), Value::Point(x, y)) => Ok(Value::Point(x * n, y * n)),
+            (a, b) => Err(format!("cannot multiply {} with {}", a.type_name(), b.type_name())),
+        }
+    }
+
+    pub fn div(&self, other: &Value) -> Result<Value, String> {
+        match (self, other) {
+            (_, Value::Number(b)) if *b == 0.0 => Err(String::from("division by zero")),
+            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a / b)),
+            (Value::Point(x, y), Value::Number(n)) => Ok(Value::Point(x / n, y / n)),
+            (a, b) => Err(format!("cannot divide {} by {}", a.type_name(), b.type_name())),
+        }
+    }
+
+    pub fn rem(&self, other: &Value) -> Result<Value, String> {
+        match (self, other) {
+            (_, Value::Number(b)) if *b == 0.0 => Err(String::from("division by zero")),
+            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a % b)),
+            (a, b) => Err(format!("cannot take the remainder of {} by {}", a.type_name(), b.type_name())),
+        }
+    }
+
+    pub fn compare(&self, other: &Value) -> Result<Ordering, String> {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => a.partial_cmp(b).ok_or_else(|| String::from("cannot compare NaN")),
+            (Value::Text(a), Value::Text(b)) => Ok(a.cmp(b)),
+            (a, b) => Err(format!("cannot compare {} with {}", a.type_name(), b.type_name())),
+        }
+    }
+
+    pub fn member(&self, name: &str) -> Result<Value, String> {
+        match (self, name) {
+            (Value::Point(x, _), "x") => Ok(Value::Number(*x)),
+            (Value::Point(_, y), "y") => Ok(Value::Number(*y)),
+            (Value::Text(s), "length") => Ok(Value::Number(s.chars().count() as f64)),
+            (value, _) => Err(format!("{} has no member '{}'", value.type_name(), name)),
+        }
+    }
+}
+
+impl Display for Value {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Text(s) => write!(f, "{}", s),
+            Value::Point(x, y) => write!(f, "({}|{})", x, y),
+            Value::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+impl From<i64> for Value {
+
+    fn from(n: i64) -> Self {
+        Value::Number(n as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use super::Value;
+
+    #[test]
+    fn arithmetic() {
+        vec![
+            (Ok(Value::Number(5.0)), Value::Number(2.0).add(&Value::Number(3.0))),
+            (Ok(Value::Point(3.0, 5.0)), Value::Point(1.0, 2.0).add(&Value::Point(2.0, 3.0))),
+            (Ok(Value::Text("x3".into())), Value::Text("x".into()).add(&Value::Number(3.0))),
+            (Ok(Value::Point(4.0, 6.0)), Value::Number(2.0).mul(&Value::Point(2.0, 3.0))),
+            (Ok(Value::Number(1.0)), Value::Number(7.0).rem(&Value::Number(3.0))),
+            (Ok(Value::Point(-1.0, 2.0)), Value::Point(1.0, -2.0).negate()),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, (expected, result))| assert_eq!(expected, result, "case {}", case));
+    }
+
+    #[test]
+    fn arithmetic_errors() {
+        vec![
+            Value::Number(1.0).div(&Value::Number(0.0)),
+            Value::Bool(true).add(&Value::Number(1.0)),
+            Value::Point(1.0, 1.0).mul(&Value::Point(1.0, 1.0)),
+            Value::Text("a".into()).negate(),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, result)| assert!(result.is_err(), "case {}", case));
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(Ok(3), Value::Number(2.6).as_i32());
+        assert_eq!(Ok(250), Value::Number(250.0).as_u64());
+        assert!(Value::Number(-1.0).as_u64().is_err());
+        assert!(Value::Number(5_000_000_000.0).as_u32().is_err());
+        assert!(Value::Text("1".into()).as_i32().is_err());
+        assert_eq!(Ok(Ordering::Less), Value::Number(1.0).compare(&Value::Number(2.0)));
+        assert_eq!("(10|20)", Value::Point(10.0, 20.0).to_string());
+        assert_eq!("7", Value::Number(7.0).to_string());
+    }
+}
@@ -0,0 +1,133 @@
+use std::{panic, sync::{Mutex, OnceLock, mpsc::Sender}, time::Duration};
+
+use bindings::Windows::Win32::{Foundation::{BOOL, POINT}, System::Console::SetConsoleCtrlHandler, UI::WindowsAndMessaging::{GetSystemMetrics, SM_CXSCREEN, SM_CYSCREEN}};
+
+use crate::{eventgrid::Signal, hardware::{self, Mouse}, lifecycle::Lifecycle};
+
+const CTRL_C_EVENT: u32 = 0;
+const CTRL_BREAK_EVENT: u32 = 1;
+const CORNER_MARGIN: i32 = 2;
+const CORNER_POLLING: Duration = Duration::from_millis(50);
+
+static SHUTDOWN: OnceLock<Mutex<Sender<Signal>>> = OnceLock::new();
+
+/// Releases every held input on panics and console signals like Ctrl+C or closing the window.
+pub fn install(tx: Sender<Signal>) {
+    if SHUTDOWN.set(Mutex::new(tx)).is_err() {
+        return warn!("failsafe already installed");
+    }
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        hardware::release_all();
+        previous(info);
+    }));
+    match unsafe { SetConsoleCtrlHandler(Some(on_console_signal), true) } {
+        BOOL(0) => error!("failed to register console signal handler"),
+        BOOL(_) => info!("failsafe installed"),
+    }
+}
+
+unsafe extern "system" fn on_console_signal(signal: u32) -> BOOL {
+    warn!("received console signal {}, releasing all inputs", signal);
+    hardware::release_all();
+    let published = match SHUTDOWN.get().map(Mutex::lock) {
+        Some(Ok(tx)) => tx.send(Signal::Shutdown).is_ok(),
+        _ => false,
+    };
+    match signal {
+        CTRL_C_EVENT | CTRL_BREAK_EVENT if published => BOOL(1),
+        _ => BOOL(0),
+    }
+}
+
+pub struct PanicCorner {
+    tx: Sender<Signal>,
+}
+
+impl PanicCorner {
+
+    pub fn new(tx: Sender<Signal>) -> Self {
+        Self {
+            tx,
+        }
+    }
+
+    pub fn start(self, lifecycle: &mut Lifecycle) {
+        info!("panic corner armed, move the cursor into a screen corner to abort");
+        lifecycle.spawn("panic-corner", move |token| {
+            let (width, height) = unsafe { (GetSystemMetrics(SM_CXSCREEN), GetSystemMetrics(SM_CYSCREEN)) };
+            while token.sleep(CORNER_POLLING) {
+                match Mouse::current_position() {
+                    Ok(point) if is_panic(&point, Mouse::injected_position().as_ref(), width, height) => {
+                        warn!("cursor hit the panic corner at ({}|{}), aborting", point.x, point.y);
+                        hardware::release_all();
+                        if self.tx.send(Signal::Shutdown).is_err() {
+                            token.cancel();
+                        }
+                        break;
+                    },
+                    Ok(_) => (),
+                    Err(_) => warn!("failed to retrieve mouse position"),
+                }
+            }
+        });
+    }
+}
+
+/// Only the user moving the cursor into a corner aborts, not a replay clicking there.
+fn is_panic(point: &POINT, injected: Option<&POINT>, width: i32, height: i32) -> bool {
+    let moved_by_bot = injected.is_some_and(|injected| injected.x == point.x && injected.y == point.y);
+    is_in_corner(point, width, height) && !moved_by_bot
+}
+
+fn is_in_corner(point: &POINT, width: i32, height: i32) -> bool {
+    let horizontal = point.x < CORNER_MARGIN || point.x >= width - CORNER_MARGIN;
+    let vertical = point.y < CORNER_MARGIN || point.y >= height - CORNER_MARGIN;
+    horizontal && vertical
+}
+
+#[cfg(test)]
+mod tests {
+    use bindings::Windows::Win32::Foundation::POINT;
+
+    #[test]
+    fn is_in_corner() {
+        vec![
+            (true, 0, 0),
+            (true, 1919, 0),
+            (true, 0, 1079),
+            (true, 1919, 1079),
+            (true, 1, 1078),
+            (false, 0, 500),
+            (false, 960, 0),
+            (false, 960, 540),
+            (false, 1900, 1079),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, (expected, x, y))|
+            assert_eq!(expected, super::is_in_corner(&POINT { x, y }, 1920, 1080), "case {}", case)
+        );
+    }
+
+    #[test]
+    fn is_panic() {
+        vec![
+            (true, (0, 0), None),
+            (true, (0, 0), Some((500, 500))),
+            // the replay clicked the corner itself
+            (false, (0, 0), Some((0, 0))),
+            (false, (1919, 1079), Some((1919, 1079))),
+            // the user moved the cursor on after the replay clicked a corner
+            (true, (1919, 0), Some((0, 0))),
+            (true, (1, 0), Some((0, 0))),
+            (false, (500, 500), None),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, (expected, (x, y), injected))| {
+            let injected = injected.map(|(x, y)| POINT { x, y });
+            assert_eq!(expected, super::is_panic(&POINT { x, y }, injected.as_ref(), 1920, 1080), "case {}", case)
+        });
+    }
+}
@@ -0,0 +1,24 @@
+use bindings::Windows::Win32::{Foundation::HWND, System::{DataExchange::{CloseClipboard, GetClipboardData, OpenClipboard}, Memory::{GlobalLock, GlobalUnlock}}};
+
+const CF_UNICODETEXT: u32 = 13;
+
+/// The text currently on the clipboard, `None` if it holds no text or is used by another process.
+pub fn clipboard_text() -> Option<String> {
+    unsafe {
+        if !OpenClipboard(HWND::NULL).as_bool() {
+            return None;
+        }
+        let handle = GetClipboardData(CF_UNICODETEXT);
+        let text = match GlobalLock(handle.0) as *const u16 {
+            data if data.is_null() => None,
+            data => {
+                let length = (0..).take_while(|&i| *data.add(i) != 0).count();
+                let text = String::from_utf16_lossy(std::slice::from_raw_parts(data, length));
+                GlobalUnlock(handle.0);
+                Some(text)
+            },
+        };
+        CloseClipboard();
+        text
+    }
+}
@@ -0,0 +1,67 @@
+use std::{collections::HashSet, sync::{Mutex, MutexGuard, OnceLock, PoisonError}};
+
+use bindings::Windows::Win32::Foundation::POINT;
+
+use super::{Key, Mouse};
+
+#[derive(Debug, Default)]
+pub struct HeldInputs {
+    keys: HashSet<Key>,
+}
+
+impl HeldInputs {
+
+    pub fn track(&mut self, key: Key, pressed: bool) {
+        match pressed {
+            true => self.keys.insert(key),
+            false => self.keys.remove(&key),
+        };
+    }
+
+    pub fn drain(&mut self) -> Vec<Key> {
+        self.keys.drain().collect()
+    }
+}
+
+fn held() -> MutexGuard<'static, HeldInputs> {
+    static HELD: OnceLock<Mutex<HeldInputs>> = OnceLock::new();
+    HELD.get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+pub fn track(key: Key, pressed: bool) {
+    held().track(key, pressed);
+}
+
+pub fn release_all() {
+    let keys = held().drain();
+    for key in keys {
+        warn!("releasing held {:?}", key);
+        match Mouse::release_of(key) {
+            Some(event) => {
+                let position = Mouse::current_position().unwrap_or(POINT { x: 0, y: 0 });
+                event.trigger(&position);
+            },
+            None => key.release(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hardware::Key;
+
+    use super::HeldInputs;
+
+    #[test]
+    fn track() {
+        let mut held = HeldInputs::default();
+        held.track(Key::A, true);
+        held.track(Key::MouseLeft, true);
+        held.track(Key::MouseLeft, false);
+        held.track(Key::B, false);
+        assert_eq!(vec![Key::A], held.drain());
+        assert!(held.drain().is_empty());
+    }
+}
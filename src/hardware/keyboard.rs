@@ -1,9 +1,10 @@
-use serde::{Deserialize, Serialize};
-use std::convert::TryFrom;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize, de::{IntoDeserializer, value::Error}};
+use std::{convert::TryFrom, str::FromStr};
 
-use bindings::Windows::Win32::UI::KeyboardAndMouseInput::{INPUT, INPUT_0, KEYBDINPUT, KEYBD_EVENT_FLAGS, SendInput};
+use bindings::Windows::Win32::UI::KeyboardAndMouseInput::{GetAsyncKeyState, INPUT, INPUT_0, KEYBDINPUT, KEYBD_EVENT_FLAGS, SendInput};
 
-use super::InputType;
+use super::{InputType, held};
 
 #[allow(dead_code)]
 enum KeyState {
@@ -11,21 +12,28 @@ enum KeyState {
     Released = 0x0002,
 }
 
-impl Into<KEYBD_EVENT_FLAGS> for KeyState {
+impl From<KeyState> for KEYBD_EVENT_FLAGS {
 
-    fn into(self) -> KEYBD_EVENT_FLAGS {
-        KEYBD_EVENT_FLAGS(self as u32)
+    fn from(state: KeyState) -> Self {
+        KEYBD_EVENT_FLAGS(state as u32)
     }
 }
 
 #[allow(dead_code)]
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Hash, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema, PartialEq, Eq, Hash, Serialize)]
 pub enum Key {
     MouseLeft = 0x01,
     MouseRight = 0x02,
     MouseMiddle = 0x04,
     MouseX1 = 0x05,
     MouseX2 = 0x06,
+    Backspace = 0x08,
+    Tab = 0x09,
+    Enter = 0x0D,
+    Shift = 0x10,
+    Control = 0x11,
+    Alt = 0x12,
+    Space = 0x20,
     KeyboardLeft = 0x25,    
     KeyboardUp = 0x26,
     KeyboardRight = 0x27,
@@ -59,21 +67,10 @@ pub enum Key {
     Escape = 0x1B,
 }
 
-impl Into<INPUT> for Key {
+impl From<Key> for INPUT {
 
-    fn into(self) -> INPUT {
-        let keyboard_input = KEYBDINPUT {
-            wVk: self as u16,
-            wScan: 0,
-            dwFlags: KeyState::Pressed.into(),
-            time: 0,
-            dwExtraInfo: 0
-        };
-        let input = INPUT {
-            r#type: InputType::Keyboard.into(),
-            Anonymous: INPUT_0 { ki: keyboard_input },
-        };
-        input
+    fn from(key: Key) -> Self {
+        key.prepare_input(KeyState::Pressed)
     }
 }
 
@@ -87,6 +84,13 @@ impl TryFrom<u8> for Key {
             0x04 => Ok(Key::MouseMiddle),
             0x05 => Ok(Key::MouseX1),
             0x06 => Ok(Key::MouseX2),
+            0x08 => Ok(Key::Backspace),
+            0x09 => Ok(Key::Tab),
+            0x0D => Ok(Key::Enter),
+            0x10 => Ok(Key::Shift),
+            0x11 => Ok(Key::Control),
+            0x12 => Ok(Key::Alt),
+            0x20 => Ok(Key::Space),
             0x25 => Ok(Key::KeyboardLeft),
             0x26 => Ok(Key::KeyboardUp),
             0x27 => Ok(Key::KeyboardRight),
@@ -123,27 +127,40 @@ impl TryFrom<u8> for Key {
     }
 }
 
+impl FromStr for Key {
+    type Err = String;
+
+    /// Parses the key by the same name used in replay files, e.g. `MouseLeft` or `A`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Key::deserialize(IntoDeserializer::<Error>::into_deserializer(s)).map_err(|_| format!("unknown key '{}'", s))
+    }
+}
+
 impl Key {
 
     pub fn is_mouse(&self) -> bool {
-        match self {
-            Key::MouseLeft 
+        matches!(self, Key::MouseLeft 
             | Key::MouseRight 
             | Key::MouseMiddle
             | Key::MouseX1
-            | Key::MouseX2 => true,
-            _ => false,
-        }
+            | Key::MouseX2)
+    }
+
+    /// Whether the key is held down right now, regardless of which window has the focus.
+    pub fn is_pressed(&self) -> bool {
+        unsafe { GetAsyncKeyState(*self as i32) as u16 & 0x8000 != 0 }
     }
 
     pub fn press(&self) {
         let input = &mut self.prepare_input(KeyState::Pressed);
         unsafe { SendInput(1, input, std::mem::size_of::<INPUT>() as i32); }
+        held::track(*self, true);
     }
 
     pub fn release(&self) {
         let input = &mut self.prepare_input(KeyState::Released);
         unsafe { SendInput(1, input, std::mem::size_of::<INPUT>() as i32); }
+        held::track(*self, false);
     }
 
     fn prepare_input(&self, key_state: KeyState) -> INPUT {
@@ -154,10 +171,40 @@ impl Key {
             time: 0,
             dwExtraInfo: 0
         };
-        let input = INPUT {
+        INPUT {
             r#type: InputType::Keyboard.into(),
             Anonymous: INPUT_0 { ki: keyboard_input },
-        };
-        input
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::Key;
+
+    #[test]
+    fn codes() {
+        vec![Key::MouseLeft, Key::MouseX2, Key::Backspace, Key::Tab, Key::Enter, Key::Shift, Key::Control, Key::Alt, Key::Space, Key::Escape, Key::KeyboardDown, Key::Z]
+            .into_iter()
+            .enumerate()
+            .for_each(|(case, key)| assert_eq!(Ok(key), Key::try_from(key as u8), "case {}", case));
+        assert_eq!(Err(()), Key::try_from(0x00));
+    }
+
+    #[test]
+    fn from_str() {
+        vec![
+            (Ok(Key::MouseLeft), "MouseLeft"),
+            (Ok(Key::A), "A"),
+            (Ok(Key::Escape), "Escape"),
+            (Ok(Key::Control), "Control"),
+            (Err(String::from("unknown key 'a'")), "a"),
+            (Err(String::from("unknown key ''")), ""),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, (expected, s))| assert_eq!(expected, s.parse::<Key>(), "case {}", case));
     }
 }
@@ -1,10 +1,18 @@
 use bindings::Windows::Win32::UI::KeyboardAndMouseInput::INPUT_TYPE;
 
+mod clipboard;
+mod held;
 mod keyboard;
 mod mouse;
+mod screen;
+mod window;
 
+pub use clipboard::clipboard_text;
+pub use held::release_all;
 pub use keyboard::Key;
 pub use mouse::{Mouse, VirtualMouse};
+pub use screen::{find_template, Color, Desktop, Image, ImageScreen, Sampler, Screen};
+pub use window::{focused_window_title, window_exists};
 
 #[allow(dead_code)]
 pub enum InputType {
@@ -13,9 +21,9 @@ pub enum InputType {
     Hardware,
 }
 
-impl Into<INPUT_TYPE> for InputType {
+impl From<InputType> for INPUT_TYPE {
 
-    fn into(self) -> INPUT_TYPE {
-        INPUT_TYPE(self as u32)
+    fn from(input_type: InputType) -> Self {
+        INPUT_TYPE(input_type as u32)
     }
 }
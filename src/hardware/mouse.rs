@@ -1,10 +1,17 @@
-use std::{sync::{Arc, atomic::{AtomicBool, Ordering}, mpsc::Receiver}, thread, time::Duration};
+use std::{sync::{Arc, atomic::{AtomicBool, AtomicU64, Ordering}, mpsc::Receiver}, thread, time::Duration};
 
 use bindings::Windows::Win32::{Foundation::{BOOL, POINT}, UI::{KeyboardAndMouseInput::{INPUT, INPUT_0, MOUSEINPUT, MOUSE_EVENT_FLAGS, SendInput}, WindowsAndMessaging::{GetCursorPos, SetCursorPos}}};
 
 use crate::eventgrid::Signal;
 
-use super::InputType;
+use crate::lifecycle::Lifecycle;
+
+use super::{InputType, Key, held};
+
+const NOT_INJECTED: u64 = u64::MAX;
+
+/// The last position the cursor was moved to by the bot, both coordinates packed into one value.
+static INJECTED: AtomicU64 = AtomicU64::new(NOT_INJECTED);
 
 #[allow(dead_code)]
 #[derive(Clone, Copy)]
@@ -29,7 +36,7 @@ impl Mouse {
 
     fn of(events: &[Self]) -> MOUSE_EVENT_FLAGS {
         MOUSE_EVENT_FLAGS(events
-            .into_iter()
+            .iter()
             .fold(0u32, |acc, &x| acc | x as u32))
     }
 
@@ -43,12 +50,58 @@ impl Mouse {
         }
     }
 
+    /// The position of the last input sent by the bot, `None` if it never moved the cursor.
+    pub fn injected_position() -> Option<POINT> {
+        match INJECTED.load(Ordering::SeqCst) {
+            NOT_INJECTED => None,
+            packed => Some(POINT { x: (packed >> 32) as u32 as i32, y: packed as u32 as i32 }),
+        }
+    }
+
+    /// Presses and releases a mouse button at the given position.
+    pub fn click(key: Key, position: &POINT) -> Result<(), String> {
+        let (down, up) = match key {
+            Key::MouseLeft => (Mouse::LeftDown, Mouse::LeftUp),
+            Key::MouseRight => (Mouse::RightDown, Mouse::RightUp),
+            Key::MouseMiddle => (Mouse::MiddleDown, Mouse::MiddleUp),
+            other => return Err(format!("clicking {:?} is not supported", other)),
+        };
+        down.trigger(position);
+        up.trigger(position);
+        Ok(())
+    }
+
+    pub fn release_of(key: Key) -> Option<Self> {
+        match key {
+            Key::MouseLeft => Some(Mouse::LeftUp),
+            Key::MouseRight => Some(Mouse::RightUp),
+            Key::MouseMiddle => Some(Mouse::MiddleUp),
+            _ => None,
+        }
+    }
+
+    fn button(&self) -> Option<(Key, bool)> {
+        match self {
+            Mouse::LeftDown => Some((Key::MouseLeft, true)),
+            Mouse::LeftUp => Some((Key::MouseLeft, false)),
+            Mouse::RightDown => Some((Key::MouseRight, true)),
+            Mouse::RightUp => Some((Key::MouseRight, false)),
+            Mouse::MiddleDown => Some((Key::MouseMiddle, true)),
+            Mouse::MiddleUp => Some((Key::MouseMiddle, false)),
+            _ => None,
+        }
+    }
+
     pub fn trigger(&self, position: &POINT) {
         let input = &mut self.prepare_input(position);
+        INJECTED.store(((position.x as u32 as u64) << 32) | position.y as u32 as u64, Ordering::SeqCst);
         unsafe {             
             SetCursorPos(position.x, position.y);
             SendInput(1, input, std::mem::size_of::<INPUT>() as i32); 
         }
+        if let Some((key, pressed)) = self.button() {
+            held::track(key, pressed);
+        }
     }
 
     fn prepare_input(&self, position: &POINT) -> INPUT {
@@ -60,17 +113,15 @@ impl Mouse {
             time: 0,
             dwExtraInfo: 0,
         };
-        let input = INPUT {
+        INPUT {
             r#type: InputType::Mouse.into(),
             Anonymous: INPUT_0 { mi: mouse_input },
-        };
-        input
+        }
     }
 }
 
 pub struct VirtualMouse {
     rx: Receiver<Signal>,
-    running: Arc<AtomicBool>,
     fire: Arc<AtomicBool>,
 }
 
@@ -79,23 +130,22 @@ impl VirtualMouse {
     pub fn new(rx: Receiver<Signal>) -> Self {
         Self {
             rx,
-            running: Arc::new(AtomicBool::new(true)),
             fire: Arc::new(AtomicBool::new(false)),
         }
     }
 
-    pub fn start(&self) {
+    pub fn start(&self, lifecycle: &mut Lifecycle) {
         info!("stating virtual mouse, pause/unpause with UP");
-        self.worker();
-        while self.running.load(Ordering::Relaxed) {
+        self.worker(lifecycle);
+        loop {
             match self.rx.recv() {
                 Err(_) => {
                     error!("corrupted receiver");
-                    self.running.store(false, Ordering::Relaxed);
+                    break;
                 }
                 Ok(Signal::Shutdown) => {
                     info!("receiver shutdown signal");
-                    self.running.store(false, Ordering::Relaxed);
+                    break;
                 }
                 Ok(Signal::Pause) => {
                     match self.fire.load(Ordering::Acquire) {
@@ -115,11 +165,10 @@ impl VirtualMouse {
         info!("shutting down virtual mouse");
     }
 
-    fn worker(&self) {
-        let running = self.running.clone();
+    fn worker(&self, lifecycle: &mut Lifecycle) {
         let fire = self.fire.clone();
-        thread::spawn(move || {
-            while running.load(Ordering::Relaxed) {
+        lifecycle.spawn("clicker", move |token| {
+            while !token.is_cancelled() {
                 if fire.load(Ordering::Relaxed) {
                     match Mouse::current_position() {
                         Ok(point) => Self::click(point),
@@ -0,0 +1,130 @@
+use std::{convert::TryFrom, fmt::{self, Display}, str::FromStr};
+
+use schemars::{JsonSchema, gen::SchemaGenerator, schema::{InstanceType, Schema, SchemaObject, StringValidation}};
+use serde::{Deserialize, Serialize};
+
+/// A RGB color, written as `#rrggbb` in replay files.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Color {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Color {
+
+    /// Converts a `COLORREF`, which is laid out as `0x00BBGGRR`.
+    pub fn from_colorref(colorref: u32) -> Self {
+        Self {
+            red: (colorref & 0xFF) as u8,
+            green: ((colorref >> 8) & 0xFF) as u8,
+            blue: ((colorref >> 16) & 0xFF) as u8,
+        }
+    }
+
+    /// Whether no channel differs by more than `tolerance`.
+    pub fn matches(&self, other: &Color, tolerance: u8) -> bool {
+        self.red.abs_diff(other.red) <= tolerance
+            && self.green.abs_diff(other.green) <= tolerance
+            && self.blue.abs_diff(other.blue) <= tolerance
+    }
+}
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix('#').filter(|hex| hex.len() == 6 && hex.is_ascii());
+        let channel = |i: usize| hex.and_then(|hex| u8::from_str_radix(&hex[i..i + 2], 16).ok());
+        match (channel(0), channel(2), channel(4)) {
+            (Some(red), Some(green), Some(blue)) => Ok(Color { red, green, blue }),
+            _ => Err(format!("expected a color like '#ff8000' but got '{}'", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Color> for String {
+
+    fn from(color: Color) -> Self {
+        color.to_string()
+    }
+}
+
+impl JsonSchema for Color {
+
+    fn schema_name() -> String {
+        String::from("Color")
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        let pattern = Some(String::from("^#[0-9a-fA-F]{6}$"));
+        Schema::Object(SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            string: Some(Box::new(StringValidation { pattern, ..Default::default() })),
+            ..Default::default()
+        })
+    }
+}
+
+impl Display for Color {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.red, self.green, self.blue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Color;
+
+    #[test]
+    fn from_colorref() {
+        vec![
+            (Color { red: 0, green: 0, blue: 0 }, 0x0000_0000),
+            (Color { red: 255, green: 0, blue: 0 }, 0x0000_00FF),
+            (Color { red: 0x12, green: 0x34, blue: 0x56 }, 0x0056_3412),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, (expected, colorref))| assert_eq!(expected, Color::from_colorref(colorref), "case {}", case));
+    }
+
+    #[test]
+    fn parse() {
+        vec![
+            (Ok(Color { red: 255, green: 128, blue: 0 }), "#ff8000"),
+            (Ok(Color { red: 0x12, green: 0xAB, blue: 0xCD }), "#12ABcd"),
+            (Err(String::from("expected a color like '#ff8000' but got 'ff8000'")), "ff8000"),
+            (Err(String::from("expected a color like '#ff8000' but got '#ff80'")), "#ff80"),
+            (Err(String::from("expected a color like '#ff8000' but got '#gg8000'")), "#gg8000"),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, (expected, s))| assert_eq!(expected, s.parse::<Color>(), "case {}", case));
+        assert_eq!("#ff8000", Color { red: 255, green: 128, blue: 0 }.to_string());
+    }
+
+    #[test]
+    fn matches() {
+        let orange = Color { red: 255, green: 128, blue: 0 };
+        vec![
+            (true, Color { red: 255, green: 128, blue: 0 }, 0),
+            (false, Color { red: 250, green: 128, blue: 0 }, 0),
+            (true, Color { red: 250, green: 133, blue: 5 }, 5),
+            (false, Color { red: 250, green: 134, blue: 5 }, 5),
+            (true, Color { red: 0, green: 0, blue: 255 }, 255),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, (expected, color, tolerance))| assert_eq!(expected, orange.matches(&color, tolerance), "case {}", case));
+    }
+}
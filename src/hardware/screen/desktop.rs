@@ -0,0 +1,76 @@
+use std::{ffi::c_void, mem::size_of};
+
+use bindings::Windows::Win32::{Foundation::{BOOL, HWND}, Graphics::Gdi::{BITMAPINFO, BITMAPINFOHEADER, BitBlt, CreateCompatibleBitmap, CreateCompatibleDC, DIB_RGB_COLORS, DeleteDC, DeleteObject, GetDC, GetDIBits, GetPixel, ReleaseDC, SRCCOPY, SelectObject}, UI::WindowsAndMessaging::{GetSystemMetrics, SM_CXSCREEN, SM_CYSCREEN}};
+
+use super::{Color, Image, Screen};
+
+const CLR_INVALID: u32 = 0xFFFF_FFFF;
+
+/// The real screen, captures may span all monitors while the size covers the primary one.
+pub struct Desktop;
+
+impl Screen for Desktop {
+
+    fn pixel(&self, x: i32, y: i32) -> Result<Color, String> {
+        unsafe {
+            let dc = GetDC(HWND::NULL);
+            if dc.0 == 0 {
+                return Err(String::from("failed to access the screen"));
+            }
+            let colorref = GetPixel(dc, x, y);
+            ReleaseDC(HWND::NULL, dc);
+            match colorref {
+                CLR_INVALID => Err(format!("failed to read the pixel at ({}|{})", x, y)),
+                colorref => Ok(Color::from_colorref(colorref)),
+            }
+        }
+    }
+
+    fn capture(&self, x: i32, y: i32, width: u32, height: u32) -> Result<Image, String> {
+        let (w, h) = (width as i32, height as i32);
+        unsafe {
+            let screen = GetDC(HWND::NULL);
+            if screen.0 == 0 {
+                return Err(String::from("failed to access the screen"));
+            }
+            let memory = CreateCompatibleDC(screen);
+            let bitmap = CreateCompatibleBitmap(screen, w, h);
+            let previous = SelectObject(memory, bitmap);
+            let copied = BitBlt(memory, 0, 0, w, h, screen, x, y, SRCCOPY);
+            SelectObject(memory, previous);
+            let mut info = BITMAPINFO {
+                bmiHeader: BITMAPINFOHEADER {
+                    biSize: size_of::<BITMAPINFOHEADER>() as u32,
+                    biWidth: w,
+                    // a negative height requests the rows top down
+                    biHeight: -h,
+                    biPlanes: 1,
+                    biBitCount: 32,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let mut buffer = vec![0u8; width as usize * height as usize * 4];
+            let lines = GetDIBits(screen, bitmap, 0, height, buffer.as_mut_ptr() as *mut c_void, &mut info, DIB_RGB_COLORS);
+            DeleteObject(bitmap);
+            DeleteDC(memory);
+            ReleaseDC(HWND::NULL, screen);
+            if copied == BOOL(0) || lines != h {
+                return Err(format!("failed to capture {}x{} at ({}|{})", width, height, x, y));
+            }
+            let pixels = buffer
+                .chunks_exact(4)
+                .map(|bgra| Color { red: bgra[2], green: bgra[1], blue: bgra[0] })
+                .collect();
+            Image::new(width, height, pixels)
+        }
+    }
+
+    fn size(&self) -> Result<(u32, u32), String> {
+        let (width, height) = unsafe { (GetSystemMetrics(SM_CXSCREEN), GetSystemMetrics(SM_CYSCREEN)) };
+        match width > 0 && height > 0 {
+            true => Ok((width as u32, height as u32)),
+            false => Err(String::from("failed to retrieve the screen size")),
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use std::{fs::File, io::{BufReader, BufWriter}, path::Path};
+
+use png::{BitDepth, ColorType, Decoder, Encoder, Transformations};
+
+use super::Color;
+
+/// A rectangular grid of colors stored row by row.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    width: u32,
+    height: u32,
+    pixels: Vec<Color>,
+}
+
+impl Image {
+
+    pub fn new(width: u32, height: u32, pixels: Vec<Color>) -> Result<Self, String> {
+        match pixels.len() == width as usize * height as usize {
+            true => Ok(Self { width, height, pixels }),
+            false => Err(format!("{} pixels do not fill an image of {}x{}", pixels.len(), width, height)),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Option<Color> {
+        match x < self.width && y < self.height {
+            true => self.pixels.get((y * self.width + x) as usize).copied(),
+            false => None,
+        }
+    }
+
+    pub fn pixels(&self) -> &[Color] {
+        &self.pixels
+    }
+
+    /// Copies the area of the given size with its top left corner at (x|y).
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Result<Image, String> {
+        if x.checked_add(width).is_none_or(|right| right > self.width) || y.checked_add(height).is_none_or(|bottom| bottom > self.height) {
+            return Err(format!("the area {}x{} at ({}|{}) exceeds the image of {}x{}", width, height, x, y, self.width, self.height));
+        }
+        let pixels = (y..y + height)
+            .flat_map(|row| {
+                let start = (row * self.width + x) as usize;
+                self.pixels[start..start + width as usize].iter().copied()
+            })
+            .collect();
+        Image::new(width, height, pixels)
+    }
+
+    /// Reads a PNG file, any transparency is dropped.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| format!("failed to open '{}' because '{:?}'", path.to_string_lossy(), e.kind()))?;
+        let mut decoder = Decoder::new(BufReader::new(file));
+        decoder.set_transformations(Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(|e| format!("'{}' is no valid PNG: {}", path.to_string_lossy(), e))?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).map_err(|e| format!("'{}' is no valid PNG: {}", path.to_string_lossy(), e))?;
+        let channels = info.color_type.samples();
+        let pixels = buffer[..info.buffer_size()]
+            .chunks_exact(channels)
+            .map(|pixel| match info.color_type {
+                ColorType::Grayscale | ColorType::GrayscaleAlpha => Color { red: pixel[0], green: pixel[0], blue: pixel[0] },
+                _ => Color { red: pixel[0], green: pixel[1], blue: pixel[2] },
+            })
+            .collect();
+        Image::new(info.width, info.height, pixels)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let file = File::create(path).map_err(|e| format!("failed to create '{}' because '{:?}'", path.to_string_lossy(), e.kind()))?;
+        let mut encoder = Encoder::new(BufWriter::new(file), self.width, self.height);
+        encoder.set_color(ColorType::Rgb);
+        encoder.set_depth(BitDepth::Eight);
+        let data: Vec<u8> = self.pixels
+            .iter()
+            .flat_map(|color| vec![color.red, color.green, color.blue])
+            .collect();
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&data))
+            .map_err(|e| format!("failed to write '{}': {}", path.to_string_lossy(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hardware::Color;
+
+    use super::Image;
+
+    fn gradient(width: u32, height: u32) -> Image {
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| Color { red: x as u8, green: y as u8, blue: 0 }))
+            .collect();
+        Image::new(width, height, pixels).unwrap()
+    }
+
+    #[test]
+    fn crop() {
+        let image = gradient(4, 3);
+        let cropped = image.crop(1, 1, 2, 2).expect("valid area");
+        assert_eq!((2, 2), (cropped.width(), cropped.height()));
+        assert_eq!(Some(Color { red: 1, green: 1, blue: 0 }), cropped.get(0, 0));
+        assert_eq!(Some(Color { red: 2, green: 2, blue: 0 }), cropped.get(1, 1));
+        assert_eq!(None, cropped.get(2, 0));
+        vec![(3, 0, 2, 1), (0, 2, 1, 2), (u32::MAX, 0, 2, 1)]
+            .into_iter()
+            .enumerate()
+            .for_each(|(case, (x, y, width, height))| assert!(image.crop(x, y, width, height).is_err(), "case {}", case));
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = std::env::temp_dir().join(format!("clicker-bot-image-{}.png", std::process::id()));
+        let image = gradient(5, 4);
+        assert!(image.save(&path).is_ok());
+        assert_eq!(Ok(image), Image::load(&path));
+        let _ = std::fs::remove_file(path);
+        assert!(Image::load("./test/commands.yaml".as_ref()).is_err());
+    }
+}
@@ -0,0 +1,124 @@
+use std::{convert::TryFrom, path::Path, sync::mpsc::Receiver, time::Duration};
+
+use crate::eventgrid::Signal;
+
+use super::Mouse;
+
+pub use self::color::Color;
+pub use self::desktop::Desktop;
+pub use self::image::Image;
+pub use self::template::find_template;
+
+mod color;
+mod desktop;
+mod image;
+mod template;
+
+pub trait Screen: Send + Sync {
+
+    fn pixel(&self, x: i32, y: i32) -> Result<Color, String>;
+
+    /// Captures the area of the given size with its top left corner at (x|y).
+    fn capture(&self, x: i32, y: i32, width: u32, height: u32) -> Result<Image, String>;
+
+    /// The width and height of the area starting at (0|0).
+    fn size(&self) -> Result<(u32, u32), String>;
+}
+
+/// A fake screen showing a fixed image, the top left pixel of the image is at (0|0).
+pub struct ImageScreen {
+    image: Image,
+}
+
+impl ImageScreen {
+
+    pub fn new(image: Image) -> Self {
+        Self { image }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        Image::load(path).map(Self::new)
+    }
+}
+
+impl Screen for ImageScreen {
+
+    fn pixel(&self, x: i32, y: i32) -> Result<Color, String> {
+        match (u32::try_from(x), u32::try_from(y)) {
+            (Ok(px), Ok(py)) => self.image.get(px, py),
+            _ => None,
+        }
+        .ok_or_else(|| format!("({}|{}) is outside of the screen", x, y))
+    }
+
+    fn capture(&self, x: i32, y: i32, width: u32, height: u32) -> Result<Image, String> {
+        match (u32::try_from(x), u32::try_from(y)) {
+            (Ok(x), Ok(y)) => self.image.crop(x, y, width, height),
+            _ => Err(format!("({}|{}) is outside of the screen", x, y)),
+        }
+    }
+
+    fn size(&self) -> Result<(u32, u32), String> {
+        Ok((self.image.width(), self.image.height()))
+    }
+}
+
+/// Reports the color under the cursor.
+pub struct Sampler<S: Screen> {
+    screen: S,
+    rx: Receiver<Signal>,
+}
+
+impl<S: Screen> Sampler<S> {
+
+    pub fn new(screen: S, rx: Receiver<Signal>) -> Self {
+        Self { screen, rx }
+    }
+
+    /// Samples once or, when watching, on every change until shutdown.
+    pub fn start(&self, watch: bool) {
+        if watch {
+            info!("sampling the color under the cursor, stop with DOWN");
+        }
+        let mut last = None;
+        loop {
+            match Mouse::current_position().map_err(|_| String::from("failed to retrieve mouse position")) {
+                Ok(point) => match self.screen.pixel(point.x, point.y) {
+                    Ok(color) if last != Some((point.x, point.y, color)) => {
+                        info!("({}|{}) {}", point.x, point.y, color);
+                        last = Some((point.x, point.y, color));
+                    },
+                    Ok(_) => (),
+                    Err(what) => warn!("{}", what),
+                },
+                Err(what) => warn!("{}", what),
+            }
+            if !watch {
+                break;
+            }
+            match self.rx.recv_timeout(Duration::from_millis(100)) {
+                Ok(Signal::Shutdown) | Ok(Signal::Finished) => break,
+                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
+                _ => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Color, Image, ImageScreen, Screen};
+
+    #[test]
+    fn image_screen() {
+        let pixels = (0..6).map(|i| Color { red: i * 10, green: 0, blue: 0 }).collect();
+        let screen = ImageScreen::new(Image::new(3, 2, pixels).unwrap());
+        assert_eq!(Ok(Color { red: 40, green: 0, blue: 0 }), screen.pixel(1, 1));
+        assert!(screen.pixel(3, 0).is_err());
+        assert!(screen.pixel(-1, 0).is_err());
+        let captured = screen.capture(1, 0, 2, 2).expect("valid area");
+        assert_eq!(Some(Color { red: 50, green: 0, blue: 0 }), captured.get(1, 1));
+        assert!(screen.capture(2, 0, 2, 1).is_err());
+        assert_eq!(Ok((3, 2)), screen.size());
+    }
+}
@@ -0,0 +1,279 @@
+use super::{Color, Image};
+
+/// Templates are scaled down only while their shorter side stays at least this long.
+const MIN_TEMPLATE_SIDE: usize = 8;
+/// How many of the best positions of a coarse level are refined on the next finer one.
+const CANDIDATES: usize = 16;
+/// How far a refined position may be off the doubled position of its coarse candidate.
+const REFINEMENT: usize = 2;
+
+/// The top left corner of a template within a larger image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Match {
+    pub x: u32,
+    pub y: u32,
+    /// The normalized cross-correlation, 1.0 is a perfect match.
+    pub score: f64,
+}
+
+/// Finds the best match of the template scoring at least `confidence` using normalized cross-correlation of the brightness.
+///
+/// The search runs coarse to fine: only the smallest level of the image pyramid is searched exhaustively, every finer level
+/// just refines the best candidates of the previous one.
+pub fn find_template(image: &Image, template: &Image, confidence: f64) -> Result<Option<Match>, String> {
+    let (width, height) = (template.width() as usize, template.height() as usize);
+    if width == 0 || height == 0 {
+        return Err(String::from("the template is empty"));
+    }
+    if width > image.width() as usize || height > image.height() as usize {
+        return Ok(None);
+    }
+    let mut images = vec![Brightness::of(image)];
+    let mut templates = vec![Brightness::of(template)];
+    if !templates[0].has_contrast() {
+        return Err(String::from("the template consists of a single color"));
+    }
+    while let (Some(image), Some(template)) = (images.last(), templates.last()) {
+        if template.width.min(template.height) / 2 < MIN_TEMPLATE_SIDE {
+            break;
+        }
+        let (image, template) = (image.half(), template.half());
+        if !template.has_contrast() {
+            break;
+        }
+        images.push(image);
+        templates.push(template);
+    }
+    let coarsest = Search::new(&images[images.len() - 1], &templates[templates.len() - 1]);
+    let mut candidates = coarsest.candidates();
+    for level in (0..images.len() - 1).rev() {
+        let search = Search::new(&images[level], &templates[level]);
+        candidates = candidates.into_iter().map(|candidate| search.refine(&candidate)).collect();
+    }
+    Ok(candidates
+        .into_iter()
+        .fold(None, |best: Option<Match>, candidate| match best {
+            Some(best) if best.score >= candidate.score => Some(best),
+            _ => Some(candidate),
+        })
+        .filter(|best| best.score >= confidence))
+}
+
+fn brightness(color: &Color) -> f64 {
+    0.299 * color.red as f64 + 0.587 * color.green as f64 + 0.114 * color.blue as f64
+}
+
+/// The brightness of every pixel, one level of an image pyramid.
+struct Brightness {
+    width: usize,
+    height: usize,
+    values: Vec<f64>,
+}
+
+impl Brightness {
+
+    fn of(image: &Image) -> Self {
+        Self {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            values: image.pixels().iter().map(brightness).collect(),
+        }
+    }
+
+    /// Averages blocks of 2x2 pixels, an odd last row or column is dropped.
+    fn half(&self) -> Self {
+        let (width, height) = (self.width / 2, self.height / 2);
+        let values = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let top = 2 * y * self.width + 2 * x;
+                let bottom = top + self.width;
+                (self.values[top] + self.values[top + 1] + self.values[bottom] + self.values[bottom + 1]) / 4.0
+            })
+            .collect();
+        Self { width, height, values }
+    }
+
+    fn has_contrast(&self) -> bool {
+        let mean = self.values.iter().sum::<f64>() / self.values.len() as f64;
+        self.values.iter().map(|value| (value - mean) * (value - mean)).sum::<f64>() >= f64::EPSILON
+    }
+}
+
+/// Scores positions of a template within an image of the same pyramid level.
+struct Search<'a> {
+    image: &'a Brightness,
+    integral: Integral,
+    width: usize,
+    height: usize,
+    deviations: Vec<f64>,
+    variance: f64,
+}
+
+impl<'a> Search<'a> {
+
+    fn new(image: &'a Brightness, template: &Brightness) -> Self {
+        let mean = template.values.iter().sum::<f64>() / template.values.len() as f64;
+        let deviations: Vec<f64> = template.values.iter().map(|value| value - mean).collect();
+        let variance = deviations.iter().map(|d| d * d).sum();
+        Self { image, integral: Integral::new(image), width: template.width, height: template.height, deviations, variance }
+    }
+
+    fn score(&self, x: usize, y: usize) -> f64 {
+        let count = (self.width * self.height) as f64;
+        let (sum, squares) = self.integral.area(x, y, self.width, self.height);
+        let variance = squares - sum * sum / count;
+        // rounding errors of the summed area tables leave flat areas with a tiny variance
+        if variance < count * 1e-6 {
+            return 0.0;
+        }
+        let correlation: f64 = (0..self.height)
+            .map(|row| {
+                let start = (y + row) * self.image.width + x;
+                self.image.values[start..start + self.width]
+                    .iter()
+                    .zip(&self.deviations[row * self.width..(row + 1) * self.width])
+                    .map(|(pixel, deviation)| pixel * deviation)
+                    .sum::<f64>()
+            })
+            .sum();
+        correlation / (variance * self.variance).sqrt()
+    }
+
+    fn last(&self) -> (usize, usize) {
+        (self.image.width - self.width, self.image.height - self.height)
+    }
+
+    /// Scores every position and keeps the best ones which are not next to a better one.
+    fn candidates(&self) -> Vec<Match> {
+        let (last_x, last_y) = self.last();
+        let mut scored: Vec<Match> = (0..=last_y)
+            .flat_map(|y| (0..=last_x).map(move |x| (x, y)))
+            .map(|(x, y)| Match { x: x as u32, y: y as u32, score: self.score(x, y) })
+            .collect();
+        scored.sort_by(|a, b| b.score.total_cmp(&a.score));
+        let mut candidates: Vec<Match> = Vec::with_capacity(CANDIDATES);
+        for found in scored {
+            let near = |other: &Match| found.x.abs_diff(other.x) as usize <= REFINEMENT && found.y.abs_diff(other.y) as usize <= REFINEMENT;
+            if !candidates.iter().any(near) {
+                candidates.push(found);
+                if candidates.len() == CANDIDATES {
+                    break;
+                }
+            }
+        }
+        candidates
+    }
+
+    /// The best position around a candidate of the next coarser level.
+    fn refine(&self, coarse: &Match) -> Match {
+        let (last_x, last_y) = self.last();
+        let (x, y) = (2 * coarse.x as usize, 2 * coarse.y as usize);
+        let mut best = Match { x: 0, y: 0, score: f64::NEG_INFINITY };
+        for y in y.saturating_sub(REFINEMENT)..=(y + REFINEMENT).min(last_y) {
+            for x in x.saturating_sub(REFINEMENT)..=(x + REFINEMENT).min(last_x) {
+                let score = self.score(x, y);
+                if score > best.score {
+                    best = Match { x: x as u32, y: y as u32, score };
+                }
+            }
+        }
+        best
+    }
+}
+
+/// Summed area tables of the brightness and its square, to sum any rectangle in constant time.
+struct Integral {
+    stride: usize,
+    sums: Vec<f64>,
+    squares: Vec<f64>,
+}
+
+impl Integral {
+
+    fn new(image: &Brightness) -> Self {
+        let stride = image.width + 1;
+        let mut sums = vec![0.0; stride * (image.height + 1)];
+        let mut squares = sums.clone();
+        for (i, value) in image.values.iter().enumerate() {
+            let (x, y) = (i % image.width + 1, i / image.width + 1);
+            let (here, left, up, diagonal) = (y * stride + x, y * stride + x - 1, (y - 1) * stride + x, (y - 1) * stride + x - 1);
+            sums[here] = value + sums[left] + sums[up] - sums[diagonal];
+            squares[here] = value * value + squares[left] + squares[up] - squares[diagonal];
+        }
+        Self { stride, sums, squares }
+    }
+
+    fn area(&self, x: usize, y: usize, width: usize, height: usize) -> (f64, f64) {
+        let corner = |table: &[f64]| {
+            table[(y + height) * self.stride + x + width] - table[y * self.stride + x + width]
+                - table[(y + height) * self.stride + x] + table[y * self.stride + x]
+        };
+        (corner(&self.sums), corner(&self.squares))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hardware::Color;
+
+    use super::{find_template, Image};
+
+    fn image(width: u32, height: u32, pixel: impl Fn(u32, u32) -> u8) -> Image {
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let value = pixel(x, y);
+                Color { red: value, green: value, blue: value }
+            })
+            .collect();
+        Image::new(width, height, pixels).unwrap()
+    }
+
+    fn noise(x: u32, y: u32) -> u8 {
+        ((x.wrapping_mul(73_856_093) ^ y.wrapping_mul(19_349_663)).wrapping_mul(2_654_435_761) >> 24) as u8
+    }
+
+    #[test]
+    fn exact_match() {
+        let screen = image(40, 30, noise);
+        let template = screen.crop(17, 9, 5, 4).unwrap();
+        let found = find_template(&screen, &template, 0.99).unwrap().expect("template is part of the screen");
+        assert_eq!((17, 9), (found.x, found.y));
+        assert!((found.score - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn brightness_invariance() {
+        let screen = image(20, 20, |x, y| match (8..12).contains(&x) && (5..8).contains(&y) {
+            true => 100 + ((x + y) % 2) as u8 * 80,
+            false => 30,
+        });
+        let template = image(4, 3, |x, y| 20 + ((x + y + 13) % 2) as u8 * 40);
+        let found = find_template(&screen, &template, 0.95).unwrap().expect("same pattern with less contrast");
+        assert_eq!((8, 5), (found.x, found.y));
+    }
+
+    #[test]
+    fn pyramid() {
+        let screen = image(320, 240, |x, y| noise(x / 3, y / 3));
+        vec![(0, 0), (213, 97), (319 - 48, 239 - 36), (101, 3)]
+            .into_iter()
+            .enumerate()
+            .for_each(|(case, (x, y))| {
+                let template = screen.crop(x, y, 48, 36).unwrap();
+                let found = find_template(&screen, &template, 0.99).unwrap();
+                assert_eq!(Some((x, y)), found.map(|found| (found.x, found.y)), "case {}", case);
+            });
+    }
+
+    #[test]
+    fn threshold() {
+        let screen = image(30, 20, noise);
+        let template = image(5, 5, |x, y| ((x * 3 + y * 11) % 7) as u8 * 30);
+        assert_eq!(Ok(None), find_template(&screen, &template, 0.9));
+        assert!(find_template(&screen, &template, -1.0).unwrap().is_some());
+        assert_eq!(Ok(None), find_template(&template, &screen, 0.0));
+        assert!(find_template(&screen, &image(3, 3, |_, _| 80), 0.5).is_err());
+    }
+}
@@ -0,0 +1,18 @@
+use bindings::Windows::Win32::{Foundation::PWSTR, UI::WindowsAndMessaging::{FindWindowW, GetForegroundWindow, GetWindowTextW}};
+
+/// Whether a top level window with exactly this title exists.
+pub fn window_exists(title: &str) -> bool {
+    unsafe { !FindWindowW(PWSTR::default(), title).is_null() }
+}
+
+pub fn focused_window_title() -> Option<String> {
+    unsafe {
+        let window = GetForegroundWindow();
+        if window.is_null() {
+            return None;
+        }
+        let mut buffer = [0u16; 512];
+        let length = GetWindowTextW(window, PWSTR(buffer.as_mut_ptr()), buffer.len() as i32);
+        Some(String::from_utf16_lossy(&buffer[..length.max(0) as usize]))
+    }
+}
@@ -4,7 +4,9 @@ use std::sync::mpsc::channel;
 
 pub use config::Configuration;
 use config::SubCommand;
-use hardware::VirtualMouse;
+use failsafe::PanicCorner;
+use hardware::{Desktop, Sampler, VirtualMouse};
+use lifecycle::Lifecycle;
 use replay::{Recorder, Replayer};
 
 use crate::eventgrid::EventGrid;
@@ -12,25 +14,157 @@ use crate::eventgrid::EventGrid;
 mod config;
 mod hardware;
 mod eventgrid;
+mod expression;
+mod failsafe;
+mod lifecycle;
 mod replay;
+#[cfg(feature = "scripting")]
+mod script;
 
-pub fn run(config: Configuration) {
+/// Runs the subcommand and returns the exit code, which is non-zero if a replay failed.
+pub fn run(config: Configuration) -> i32 {
     let (tx, rx) = channel();
-    EventGrid::new(tx).start();
+    let mut lifecycle = Lifecycle::new();
+    if config.subcommand.is_live() {
+        failsafe::install(tx.clone());
+        EventGrid::new(tx.clone()).start(&mut lifecycle);
+        if !config.no_panic_corner {
+            PanicCorner::new(tx.clone()).start(&mut lifecycle);
+        }
+    }
+    let mut exit_code = 0;
     match config.subcommand {
-        SubCommand::Click => {            
+        SubCommand::Click => {
             let mouse = VirtualMouse::new(rx);
-            mouse.start();
+            mouse.start(&mut lifecycle);
         },
         SubCommand::Record(config) => {
-            let mut recorder = Recorder::new(config.out_file(), rx);
-            recorder.start();
+            let mut recorder = Recorder::new(config.out_file(), config.format(), rx);
+            if let Err(what) = recorder.start() {
+                error!("{}", what);
+                exit_code = 1;
+            }
+        },
+        SubCommand::Recover(config) => {
+            match config.recover() {
+                Ok((file, warnings)) => {
+                    warnings.iter().for_each(|warning| warn!("{}", warning));
+                    info!("recovered the recording into '{}'", file.display());
+                },
+                Err(what) => {
+                    error!("{}", what);
+                    exit_code = 1;
+                },
+            }
         },
         SubCommand::Replay(config) => {
-            let commands = config.load_replay();
-            info!("loaded replay file with '{}' commands", commands.iter_commands().count());
-            let mut replayer = Replayer::new(commands, rx);
-            replayer.start();
+            let prepared = config.load_replay().and_then(|program| {
+                let options = config.options(&program)?;
+                let screen = config.screen(&program)?;
+                Ok((program, options, screen))
+            });
+            match prepared {
+                Ok((program, options, screen)) if config.dry_run => {
+                    let (timeline, report) = Replayer::dry_run(program, options, screen);
+                    println!("{}", timeline.render());
+                    info!("{}", timeline.summary());
+                    if !report.is_success() {
+                        error!("{}", report.summary());
+                        exit_code = 1;
+                    }
+                },
+                Ok((program, options, screen)) => {
+                    info!("loaded replay file with '{}' instructions", program.len());
+                    let mut replayer = Replayer::new(program, options, screen, tx, rx);
+                    replayer.start(&mut lifecycle);
+                    // the worker has to stop before its report is complete
+                    lifecycle.shutdown();
+                    let report = replayer.report();
+                    info!("{}", report.summary());
+                    if let Err(what) = config.save_report(&report) {
+                        error!("{}", what);
+                    }
+                    if !report.is_success() {
+                        exit_code = 1;
+                    }
+                },
+                Err(what) => {
+                    error!("{}", what);
+                    exit_code = 1;
+                },
+            }
+        },
+        SubCommand::Sample(config) => Sampler::new(Desktop, rx).start(config.watch),
+        SubCommand::Schema(config) => {
+            if let Err(what) = config.write() {
+                error!("{}", what);
+                exit_code = 1;
+            }
+        },
+        SubCommand::Migrate(config) => {
+            for (file, format) in config.files() {
+                match replay::migrate(file, format) {
+                    Ok(Some(backup)) => info!("migrated '{}', the original was saved as '{}'", file.display(), backup.display()),
+                    Ok(None) => info!("'{}' is already up to date", file.display()),
+                    Err(what) => {
+                        error!("failed to migrate '{}': {}", file.display(), what);
+                        exit_code = 1;
+                    },
+                }
+            }
+        },
+        SubCommand::Lint(config) => {
+            match config.lint() {
+                Ok(diagnostics) => {
+                    if diagnostics.errors() + diagnostics.warnings() > 0 {
+                        println!("{}", diagnostics.render());
+                    }
+                    info!("found {} error(s) and {} warning(s)", diagnostics.errors(), diagnostics.warnings());
+                    if !diagnostics.is_ok() || (config.deny_warnings && diagnostics.warnings() > 0) {
+                        exit_code = 1;
+                    }
+                },
+                Err(what) => {
+                    error!("{}", what);
+                    exit_code = 1;
+                },
+            }
+        },
+        SubCommand::Convert(config) => {
+            match config.convert() {
+                Ok(warnings) => warnings.iter().for_each(|warning| warn!("{}", warning)),
+                Err(what) => {
+                    error!("{}", what);
+                    exit_code = 1;
+                },
+            }
+        },
+        SubCommand::Export(config) => {
+            match config.export() {
+                Ok(warnings) => warnings.iter().for_each(|warning| warn!("{}", warning)),
+                Err(what) => {
+                    error!("{}", what);
+                    exit_code = 1;
+                },
+            }
+        },
+        #[cfg(feature = "scripting")]
+        SubCommand::Script(config) => {
+            match script::compile(&config.file) {
+                Ok(ast) => {
+                    let mut runner = script::ScriptRunner::new(ast, tx, rx);
+                    runner.start(&mut lifecycle);
+                    if runner.failed() {
+                        exit_code = 1;
+                    }
+                },
+                Err(what) => {
+                    error!("{}", what);
+                    exit_code = 1;
+                },
+            }
         },
-    }    
+    }
+    lifecycle.shutdown();
+    exit_code
 }
@@ -0,0 +1,84 @@
+use std::thread::{self, JoinHandle};
+
+use crate::hardware;
+
+pub use self::token::CancellationToken;
+
+mod token;
+
+pub struct Lifecycle {
+    token: CancellationToken,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Lifecycle {
+
+    pub fn new() -> Self {
+        Self {
+            token: CancellationToken::new(),
+            workers: Vec::new(),
+        }
+    }
+
+    pub fn spawn<F>(&mut self, name: &str, worker: F)
+    where
+        F: FnOnce(CancellationToken) + Send + 'static,
+    {
+        let token = self.token.clone();
+        match thread::Builder::new().name(name.into()).spawn(move || worker(token)) {
+            Ok(handle) => self.workers.push(handle),
+            Err(e) => error!("failed to spawn {} worker: {}", name, e),
+        }
+    }
+
+    pub fn shutdown(&mut self) {
+        self.token.cancel();
+        for worker in self.workers.drain(..) {
+            let name = worker.thread().name().unwrap_or("unnamed").to_string();
+            match worker.join() {
+                Ok(_) => info!("{} worker stopped", name),
+                Err(_) => error!("{} worker panicked", name),
+            }
+        }
+        hardware::release_all();
+    }
+}
+
+impl Default for Lifecycle {
+
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Lifecycle {
+
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
+
+    use super::Lifecycle;
+
+    #[test]
+    fn shutdown_joins_workers() {
+        let stopped = Arc::new(AtomicUsize::new(0));
+        let mut lifecycle = Lifecycle::new();
+        (0..3).for_each(|_| {
+            let stopped = stopped.clone();
+            lifecycle.spawn("test", move |token| {
+                while !token.is_cancelled() {
+                    std::thread::yield_now();
+                }
+                stopped.fetch_add(1, Ordering::SeqCst);
+            });
+        });
+        lifecycle.shutdown();
+        assert_eq!(3, stopped.load(Ordering::SeqCst));
+        assert!(lifecycle.token.is_cancelled());
+    }
+}
@@ -0,0 +1,64 @@
+use std::{sync::{Arc, atomic::{AtomicBool, Ordering}}, thread, time::{Duration, Instant}};
+
+const SLICE: Duration = Duration::from_millis(20);
+
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Sleeps for the given duration in small slices, returns `false` if the token got cancelled meanwhile.
+    pub fn sleep(&self, duration: Duration) -> bool {
+        let deadline = Instant::now() + duration;
+        loop {
+            if self.is_cancelled() {
+                return false;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return true;
+            }
+            thread::sleep(SLICE.min(deadline - now));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::CancellationToken;
+
+    #[test]
+    fn cancel_is_shared() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        assert!(!clone.is_cancelled());
+        token.cancel();
+        assert!(clone.is_cancelled());
+    }
+
+    #[test]
+    fn sleep() {
+        let token = CancellationToken::new();
+        assert!(token.sleep(Duration::from_millis(30)));
+        token.cancel();
+        let start = Instant::now();
+        assert!(!token.sleep(Duration::from_secs(10)));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -4,5 +4,5 @@ use clicker_bot::Configuration;
 fn main() {
     env_logger::builder().parse_filters("info").init();
     let config = Configuration::parse();
-    clicker_bot::run(config);
+    std::process::exit(clicker_bot::run(config));
 }
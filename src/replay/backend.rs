@@ -0,0 +1,168 @@
+use std::{fs, path::Path, sync::{Mutex, atomic::{AtomicUsize, Ordering}}, time::{Duration, Instant}};
+
+use bindings::Windows::Win32::Foundation::POINT;
+
+use crate::{hardware::{Image, Key, Mouse}, lifecycle::CancellationToken};
+
+use super::{Condition, timeline::Timeline};
+
+/// The instructions a dry run executes at most, well above what even recordings of millions of events take.
+pub const MAX_DRY_RUN_INSTRUCTIONS: usize = 10_000_000;
+
+/// Everything a replay does to the machine, apart from reading the screen.
+pub trait Backend: Send + Sync {
+
+    fn click(&self, key: Key, point: &POINT) -> Result<(), String>;
+
+    /// Presses the key while holding the modifiers.
+    fn press(&self, key: Key, modifiers: &[Key]);
+
+    /// Returns `false` if the replay got cancelled meanwhile.
+    fn sleep(&self, duration: Duration, token: &CancellationToken) -> bool;
+
+    /// Called once before the condition of a `wait_until` command gets polled.
+    fn wait(&self, condition: &Condition, timeout: Option<Duration>);
+
+    /// Saves a screenshot, missing directories are created.
+    fn save(&self, path: &Path, image: &Image) -> Result<(), String>;
+
+    /// The current time, loops with a duration end by it.
+    fn now(&self) -> Instant;
+
+    /// Called before every instruction and every repetition of a command, `unbounded` tells whether a loop without
+    /// a fixed amount of repetitions is running. Returns `false` once the replay has to stop.
+    fn step(&self, unbounded: bool, token: &CancellationToken) -> bool;
+}
+
+/// Sends the input to the focused window and writes screenshots to disk.
+pub struct Hardware;
+
+impl Backend for Hardware {
+
+    fn click(&self, key: Key, point: &POINT) -> Result<(), String> {
+        Mouse::click(key, point)
+    }
+
+    fn press(&self, key: Key, modifiers: &[Key]) {
+        modifiers.iter().for_each(Key::press);
+        key.press();
+        key.release();
+        modifiers.iter().rev().for_each(Key::release);
+    }
+
+    fn sleep(&self, duration: Duration, token: &CancellationToken) -> bool {
+        token.sleep(duration)
+    }
+
+    fn wait(&self, _: &Condition, _: Option<Duration>) {}
+
+    fn save(&self, path: &Path, image: &Image) -> Result<(), String> {
+        save_screenshot(path, image)
+    }
+
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn step(&self, _: bool, _: &CancellationToken) -> bool {
+        true
+    }
+}
+
+/// Writes the image as PNG, missing directories are created.
+pub fn save_screenshot(path: &Path, image: &Image) -> Result<(), String> {
+    if let Some(directory) = path.parent().filter(|directory| !directory.as_os_str().is_empty()) {
+        fs::create_dir_all(directory)
+            .map_err(|e| format!("failed to create '{}' because '{:?}'", directory.to_string_lossy(), e.kind()))?;
+    }
+    image.save(path)
+}
+
+/// Records every action on a timeline instead of executing it, sleeping only advances the clock of the timeline.
+pub struct DryRun {
+    start: Instant,
+    timeline: Mutex<Timeline>,
+    /// The instructions executed so far and the amount after which the dry run stops.
+    instructions: AtomicUsize,
+    budget: usize,
+}
+
+impl Default for DryRun {
+
+    fn default() -> Self {
+        Self::new(MAX_DRY_RUN_INSTRUCTIONS)
+    }
+}
+
+impl DryRun {
+
+    pub fn new(budget: usize) -> Self {
+        Self {
+            start: Instant::now(),
+            timeline: Mutex::new(Timeline::default()),
+            instructions: AtomicUsize::new(0),
+            budget,
+        }
+    }
+
+    pub fn timeline(&self) -> Timeline {
+        self.timeline.lock().map(|timeline| timeline.clone()).unwrap_or_default()
+    }
+
+    fn record(&self, action: String) {
+        if let Ok(mut timeline) = self.timeline.lock() {
+            timeline.record(action);
+        }
+    }
+}
+
+impl Backend for DryRun {
+
+    fn click(&self, key: Key, point: &POINT) -> Result<(), String> {
+        self.record(format!("click {:?} at ({}|{})", key, point.x, point.y));
+        Ok(())
+    }
+
+    fn press(&self, key: Key, modifiers: &[Key]) {
+        let keys: Vec<_> = modifiers.iter().chain(std::iter::once(&key)).map(|key| format!("{:?}", key)).collect();
+        self.record(format!("press {}", keys.join("+")));
+    }
+
+    fn sleep(&self, duration: Duration, token: &CancellationToken) -> bool {
+        if let Ok(mut timeline) = self.timeline.lock() {
+            timeline.advance(duration);
+        }
+        !token.is_cancelled()
+    }
+
+    fn wait(&self, condition: &Condition, timeout: Option<Duration>) {
+        match timeout {
+            Some(timeout) => self.record(format!("wait until {:?} for at most {}ms", condition, timeout.as_millis())),
+            None => self.record(format!("wait until {:?}", condition)),
+        }
+    }
+
+    fn save(&self, path: &Path, _: &Image) -> Result<(), String> {
+        self.record(format!("screenshot {}", path.to_string_lossy()));
+        Ok(())
+    }
+
+    fn now(&self) -> Instant {
+        self.start + self.timeline.lock().map(|timeline| timeline.elapsed()).unwrap_or_default()
+    }
+
+    /// Only replays running an unbounded loop when the budget is used up are considered to never end.
+    fn step(&self, unbounded: bool, token: &CancellationToken) -> bool {
+        if self.instructions.fetch_add(1, Ordering::Relaxed) < self.budget {
+            return true;
+        }
+        if let Ok(mut timeline) = self.timeline.lock() {
+            match unbounded {
+                true => timeline.truncate(),
+                false => timeline.cut_short(),
+            }
+        }
+        token.cancel();
+        false
+    }
+}
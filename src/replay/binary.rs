@@ -0,0 +1,390 @@
+//! A compact encoding for long recordings, which are mostly clicks, key presses and the sleeps between them.
+//!
+//! The file starts with the magic bytes and the layout and replay versions, followed by everything but the
+//! commands as JSON. Clicks, key presses and sleeps with plain numbers are written as varints, coordinates as
+//! difference to the previous click and sleeps already hold the time since the previous event. Every other
+//! command is stored as JSON. A CRC-32 of all preceding bytes ends the file.
+
+use std::{collections::BTreeMap, convert::TryFrom, path::PathBuf};
+
+use serde::Serialize;
+
+use crate::{expression::Argument, hardware::Key};
+
+use super::{Command, Commands, Loops, Parameter, command::Procedure, diagnostic::ParseError, migration::VERSION};
+
+const MAGIC: &[u8; 4] = b"CLKB";
+
+/// The version of the byte layout, independent of the version of the replay model.
+const LAYOUT: u8 = 1;
+
+const MOUSE: u8 = 0;
+const KEYBOARD: u8 = 1;
+const SLEEP: u8 = 2;
+const OTHER: u8 = 3;
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+/// Everything of a replay apart from its commands, borrowed to not copy huge recordings.
+#[derive(Serialize)]
+struct Header<'a> {
+    loops: &'a Loops,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    include: &'a [PathBuf],
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    parameters: &'a BTreeMap<String, Parameter>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    variables: &'a BTreeMap<String, Argument>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    procedures: &'a BTreeMap<String, Procedure>,
+}
+
+pub fn encode(commands: &Commands) -> Result<Vec<u8>, String> {
+    let header = Header {
+        loops: &commands.loops,
+        include: &commands.include,
+        parameters: &commands.parameters,
+        variables: &commands.variables,
+        procedures: &commands.procedures,
+    };
+    let header = serde_json::to_vec(&header).map_err(|e| e.to_string())?;
+    let mut bytes = Vec::with_capacity(header.len() + commands.commands.len() * 6 + 32);
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(LAYOUT);
+    write_varint(&mut bytes, u64::from(commands.version));
+    write_varint(&mut bytes, header.len() as u64);
+    bytes.extend_from_slice(&header);
+    write_varint(&mut bytes, commands.commands.len() as u64);
+    let mut position = (0, 0);
+    for command in &commands.commands {
+        encode_command(&mut bytes, command, &mut position)?;
+    }
+    let checksum = crc32(&bytes);
+    bytes.extend_from_slice(&checksum.to_le_bytes());
+    Ok(bytes)
+}
+
+/// `position` is the one of the previous click, which the coordinates of the next one are relative to.
+fn encode_command(bytes: &mut Vec<u8>, command: &Command, position: &mut (i64, i64)) -> Result<(), String> {
+    match command {
+        Command::MouseCommand { key, loops, x: Argument::Literal(x), y: Argument::Literal(y) } if amount(loops).is_some() => {
+            bytes.extend_from_slice(&[MOUSE, *key as u8]);
+            write_varint(bytes, amount(loops).unwrap_or(1));
+            write_signed(bytes, x.wrapping_sub(position.0));
+            write_signed(bytes, y.wrapping_sub(position.1));
+            *position = (*x, *y);
+        },
+        Command::KeyboardCommand { key, modifiers, loops } if amount(loops).is_some() => {
+            bytes.extend_from_slice(&[KEYBOARD, *key as u8]);
+            write_varint(bytes, modifiers.len() as u64);
+            bytes.extend(modifiers.iter().map(|modifier| *modifier as u8));
+            write_varint(bytes, amount(loops).unwrap_or(1));
+        },
+        Command::SleepCommand { millis: Argument::Literal(millis) } if *millis >= 0 => {
+            bytes.push(SLEEP);
+            write_varint(bytes, *millis as u64);
+        },
+        command => {
+            let json = serde_json::to_vec(command).map_err(|e| e.to_string())?;
+            bytes.push(OTHER);
+            write_varint(bytes, json.len() as u64);
+            bytes.extend_from_slice(&json);
+        },
+    }
+    Ok(())
+}
+
+/// The amount of loops if it is a plain number, which is the only kind stored as varint.
+fn amount(loops: &Loops) -> Option<u64> {
+    match loops {
+        Loops::Amount(Argument::Literal(amount)) => u64::try_from(*amount).ok(),
+        _ => None,
+    }
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Commands, ParseError> {
+    let error = |message: &str| ParseError::new(message, None);
+    if !bytes.starts_with(MAGIC) {
+        return Err(error("the file is no binary replay"));
+    }
+    if bytes.len() < MAGIC.len() + 5 {
+        return Err(error("the binary replay is truncated"));
+    }
+    let (content, checksum) = bytes.split_at(bytes.len() - 4);
+    if crc32(content).to_le_bytes() != checksum {
+        return Err(error("the checksum of the binary replay does not match, the file is damaged"));
+    }
+    let mut reader = Reader { bytes: content, position: MAGIC.len() };
+    let layout = reader.byte()?;
+    if layout != LAYOUT {
+        return Err(error(&format!("the binary layout {} is not supported, only {} is", layout, LAYOUT)));
+    }
+    let version = u32::try_from(reader.varint()?).unwrap_or(u32::MAX);
+    if version > VERSION {
+        return Err(error(&format!("the replay has version {} but this clicker-bot only supports versions up to {}", version, VERSION)));
+    }
+    let length = reader.length()?;
+    let mut commands: Commands = serde_json::from_slice(reader.slice(length)?).map_err(|e| error(&format!("invalid header: {}", e)))?;
+    commands.version = version;
+    let count = reader.length()?;
+    // every command takes at least two bytes, which bounds the allocation for damaged counts
+    commands.commands.reserve(count.min(content.len() / 2));
+    let mut position = (0, 0);
+    for _ in 0..count {
+        let command = decode_command(&mut reader, &mut position)?;
+        commands.commands.push(command);
+    }
+    match reader.position == content.len() {
+        true => Ok(commands),
+        false => Err(error("the binary replay has unexpected data after its commands")),
+    }
+}
+
+fn decode_command(reader: &mut Reader, position: &mut (i64, i64)) -> Result<Command, ParseError> {
+    let amount = |reader: &mut Reader| -> Result<Loops, ParseError> {
+        let amount = i64::try_from(reader.varint()?).map_err(|_| reader.error("the amount of loops is too large"))?;
+        Ok(Loops::Amount(Argument::Literal(amount)))
+    };
+    let offset = reader.position;
+    match reader.byte()? {
+        MOUSE => {
+            let key = reader.key()?;
+            let loops = amount(reader)?;
+            *position = (position.0.wrapping_add(reader.signed()?), position.1.wrapping_add(reader.signed()?));
+            Ok(Command::MouseCommand { key, loops, x: Argument::Literal(position.0), y: Argument::Literal(position.1) })
+        },
+        KEYBOARD => {
+            let key = reader.key()?;
+            let count = reader.length()?;
+            let modifiers = (0..count).map(|_| reader.key()).collect::<Result<_, _>>()?;
+            let loops = amount(reader)?;
+            Ok(Command::KeyboardCommand { key, modifiers, loops })
+        },
+        SLEEP => {
+            let millis = i64::try_from(reader.varint()?).map_err(|_| reader.error("the sleep is too long"))?;
+            Ok(Command::SleepCommand { millis: Argument::Literal(millis) })
+        },
+        OTHER => {
+            let length = reader.length()?;
+            let json = reader.slice(length)?;
+            serde_json::from_slice(json).map_err(|e| reader.error(&format!("invalid command: {}", e)))
+        },
+        tag => Err(ParseError::new(&format!("unknown command type {} at byte {}", tag, offset), None)),
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+
+    fn error(&self, message: &str) -> ParseError {
+        ParseError::new(&format!("{} at byte {}", message, self.position), None)
+    }
+
+    fn byte(&mut self) -> Result<u8, ParseError> {
+        let byte = *self.bytes.get(self.position).ok_or_else(|| self.error("unexpected end of data"))?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn slice(&mut self, length: usize) -> Result<&'a [u8], ParseError> {
+        let slice = self.position
+            .checked_add(length)
+            .and_then(|end| self.bytes.get(self.position..end))
+            .ok_or_else(|| self.error("unexpected end of data"))?;
+        self.position += length;
+        Ok(slice)
+    }
+
+    fn varint(&mut self) -> Result<u64, ParseError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7F) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(self.error("the number is too large"))
+    }
+
+    /// Signed numbers are zigzag encoded, so small differences in either direction take a single byte.
+    fn signed(&mut self) -> Result<i64, ParseError> {
+        let value = self.varint()?;
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+
+    fn length(&mut self) -> Result<usize, ParseError> {
+        let length = self.varint()?;
+        usize::try_from(length).map_err(|_| self.error("the length is too large"))
+    }
+
+    fn key(&mut self) -> Result<Key, ParseError> {
+        let code = self.byte()?;
+        Key::try_from(code).map_err(|_| self.error(&format!("unknown key code {:#04x}", code)))
+    }
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn write_signed(bytes: &mut Vec<u8>, value: i64) {
+    write_varint(bytes, ((value << 1) ^ (value >> 63)) as u64);
+}
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { 0xEDB8_8320 ^ (crc >> 1) } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// The CRC-32 of zip and PNG files.
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, byte| CRC_TABLE[((crc ^ u32::from(*byte)) & 0xFF) as usize] ^ (crc >> 8))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path, time::Instant};
+
+    use crate::{expression::Argument, hardware::Key, replay::{Command, Commands, Format, Loops}};
+
+    use super::{crc32, decode, encode};
+
+    /// A recording of clicks around the screen, key presses and the pauses between them.
+    fn recording(events: usize) -> Commands {
+        let mut seed = 0x2545_F491_4F6C_DD1Du64;
+        let mut random = move |limit: u64| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed % limit
+        };
+        let mut commands: Vec<_> = (0..events)
+            .map(|i| match (i % 2, random(10)) {
+                (0, _) => Command::SleepCommand { millis: Argument::Literal(50 + random(2000) as i64) },
+                (_, 0) => Command::KeyboardCommand { key: Key::S, modifiers: vec![Key::Control], loops: Loops::default() },
+                _ => Command::MouseCommand {
+                    key: Key::MouseLeft,
+                    loops: Loops::default(),
+                    x: Argument::Literal(random(2560) as i64 - 640),
+                    y: Argument::Literal(random(1440) as i64),
+                },
+            })
+            .collect();
+        Commands::from(&mut commands)
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut extremes = vec![
+            Command::MouseCommand { key: Key::MouseRight, loops: Loops::Amount(Argument::Literal(3)), x: Argument::Literal(i64::MIN), y: Argument::Literal(-1) },
+            Command::MouseCommand { key: Key::MouseX1, loops: Loops::default(), x: Argument::Literal(i64::MAX), y: Argument::Literal(0) },
+            Command::MouseCommand { key: Key::MouseLeft, loops: Loops::Infinite, x: Argument::Literal(1), y: Argument::Expression("y + 1".into()) },
+            Command::KeyboardCommand { key: Key::Z, modifiers: vec![Key::Control, Key::Shift], loops: Loops::Amount(Argument::Literal(-2)) },
+            Command::SleepCommand { millis: Argument::Literal(-5) },
+            Command::SleepCommand { millis: Argument::Literal(i64::MAX) },
+        ];
+        vec!["commands", "control_flow", "procedures", "parameters", "wait", "assertions"]
+            .into_iter()
+            .map(|name| Commands::read(Path::new(&format!("./test/{}.yaml", name)), Format::Yaml).expect("valid replay"))
+            .chain(vec![Commands::from(&mut extremes), recording(1000)])
+            .enumerate()
+            .for_each(|(case, commands)| {
+                let bytes = encode(&commands).unwrap_or_else(|e| panic!("case {}: {}", case, e));
+                assert_eq!(Ok(&commands), decode(&bytes).as_ref(), "case {}", case);
+            });
+    }
+
+    #[test]
+    fn layout() {
+        let mut commands = vec![
+            Command::MouseCommand { key: Key::MouseLeft, loops: Loops::default(), x: Argument::Literal(10), y: Argument::Literal(20) },
+            Command::SleepCommand { millis: Argument::Literal(300) },
+            Command::MouseCommand { key: Key::MouseLeft, loops: Loops::default(), x: Argument::Literal(12), y: Argument::Literal(18) },
+            Command::KeyboardCommand { key: Key::S, modifiers: vec![Key::Control], loops: Loops::default() },
+        ];
+        let header = br#"{"loops":{"Amount":1}}"#;
+        let mut expected = b"CLKB\x01\x01".to_vec();
+        expected.push(header.len() as u8);
+        expected.extend_from_slice(header);
+        expected.extend_from_slice(&[4, 0, 0x01, 1, 20, 40, 2, 0xAC, 0x02, 0, 0x01, 1, 4, 3, 1, 0x53, 1, 0x11, 1]);
+        expected.extend_from_slice(&crc32(&expected).to_le_bytes());
+        assert_eq!(Ok(expected), encode(&Commands::from(&mut commands)));
+        assert_eq!(0xCBF4_3926, crc32(b"123456789"));
+    }
+
+    #[test]
+    fn damaged() {
+        let bytes = encode(&recording(10)).expect("encoded recording");
+        let mut flipped = bytes.clone();
+        flipped[20] ^= 0x01;
+        let mut newer = recording(1);
+        newer.version = 99;
+        vec![
+            ("the file is no binary replay", b"commands: []".to_vec()),
+            ("the binary replay is truncated", b"CLKB\x01".to_vec()),
+            ("the checksum of the binary replay does not match, the file is damaged", flipped),
+            ("the checksum of the binary replay does not match, the file is damaged", bytes[..bytes.len() - 1].to_vec()),
+            ("the replay has version 99 but this clicker-bot only supports versions up to 1", encode(&newer).expect("encoded recording")),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, (expected, bytes))| assert_eq!(Err(String::from(expected)), decode(&bytes).map(|_| ()).map_err(String::from), "case {}", case));
+    }
+
+    #[test]
+    fn files() {
+        let directory = std::env::temp_dir().join(format!("clicker-bot-binary-{}", std::process::id()));
+        fs::create_dir_all(&directory).expect("temporary directory");
+        let path = directory.join("recording.clkb");
+        let commands = recording(100);
+        commands.save(&path, Format::resolve(None, &path)).expect("saved recording");
+        assert_eq!(Ok(commands), Commands::load(&path, Format::Binary));
+        assert!(fs::metadata(&path).is_ok_and(|metadata| metadata.len() < 600));
+        fs::remove_dir_all(&directory).expect("removed temporary directory");
+    }
+
+    /// Run with `cargo test --release binary::tests::benchmark -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn benchmark() {
+        let events = 1_000_000;
+        let commands = recording(events);
+        let directory = std::env::temp_dir().join(format!("clicker-bot-benchmark-{}", std::process::id()));
+        fs::create_dir_all(&directory).expect("temporary directory");
+        println!("{} events", events);
+        println!("{:<8} {:>12} {:>10} {:>10}", "format", "bytes", "save ms", "load ms");
+        for format in [Format::Yaml, Format::Json, Format::Binary] {
+            let path = directory.join(format!("recording.{}", format.extension()));
+            let start = Instant::now();
+            commands.save(&path, format).expect("saved recording");
+            let saved = start.elapsed();
+            let start = Instant::now();
+            let loaded = Commands::load(&path, format).expect("loaded recording");
+            let load = start.elapsed();
+            assert_eq!(events, loaded.commands.len());
+            let size = fs::metadata(&path).map(|metadata| metadata.len()).unwrap_or_default();
+            println!("{:<8} {:>12} {:>10} {:>10}", format.extension(), size, saved.as_millis(), load.as_millis());
+        }
+        fs::remove_dir_all(&directory).expect("removed temporary directory");
+    }
+}
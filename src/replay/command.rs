@@ -1,60 +1,362 @@
-use std::slice::Iter;
+use std::{collections::{BTreeMap, HashSet}, fs, path::{Path, PathBuf}, slice::Iter, time::{Duration, Instant}};
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use crate::hardware::Key;
+use crate::{expression::{Argument, Scope, Template, Value}, hardware::Key};
 
-#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+use super::{Assignment, Condition, Format, OnTimeout, Parameter, Program, diagnostic::Pointer, migration::{self, VERSION}, condition::{Region, default_confidence, validate_confidence, validate_minimum}};
+
+pub const BUILTIN_VARIABLES: [&str; 2] = ["iteration", "repetition"];
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
 pub enum Loops {
+    Infinite,
+    Amount(Argument),
+    /// Repeats until the given amount of milliseconds passed.
+    Duration(Argument),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Limit {
     Infinite,
     Amount(usize),
+    Until(Instant),
+}
+
+impl Limit {
+
+    pub fn allows(&self, iteration: usize, now: Instant) -> bool {
+        match self {
+            Limit::Infinite => true,
+            Limit::Amount(amount) => iteration < *amount,
+            Limit::Until(deadline) => now < *deadline,
+        }
+    }
 }
 
 impl Loops {
 
-    pub fn iter(&self) -> Box<dyn Iterator<Item = usize>> {
+    /// Durations start at `now`, which is the time of the replay's clock.
+    pub fn limit(&self, scope: &Scope, now: Instant) -> Result<Limit, String> {
         match self {
-            Loops::Infinite => Box::new(0..),
-            Loops::Amount(i) => Box::new(0..*i),
+            Loops::Infinite => Ok(Limit::Infinite),
+            Loops::Amount(i) => Ok(Limit::Amount(i.evaluate(scope)?.as_usize()?)),
+            Loops::Duration(millis) => Ok(Limit::Until(now + Duration::from_millis(millis.evaluate(scope)?.as_u64()?))),
+        }
+    }
+
+    pub fn iter<'a>(&self, scope: &Scope, clock: &'a dyn Fn() -> Instant) -> Result<Box<dyn Iterator<Item = usize> + 'a>, String> {
+        let limit = self.limit(scope, clock())?;
+        match limit {
+            Limit::Infinite => Ok(Box::new(0..)),
+            Limit::Amount(i) => Ok(Box::new(0..i)),
+            Limit::Until(_) => Ok(Box::new((0..).take_while(move |&i| limit.allows(i, clock())))),
         }      
     }
+
+    /// Whether the loop repeats a fixed amount of times, other loops run until they are stopped or time is up.
+    pub fn is_bounded(&self) -> bool {
+        matches!(self, Loops::Amount(_))
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Loops::Infinite => Ok(()),
+            Loops::Amount(amount) => validate_minimum(amount, 0, "the amount of loops"),
+            Loops::Duration(millis) => validate_minimum(millis, 0, "the loop duration"),
+        }
+    }
+
+    fn argument(&self) -> Option<&Argument> {
+        match self {
+            Loops::Infinite => None,
+            Loops::Amount(i) | Loops::Duration(i) => Some(i),
+        }
+    }
 }
 
 impl Default for Loops {
 
     fn default() -> Self {
-        Self::Amount(1)
+        Self::Amount(1.into())
     }
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
 pub struct Commands {
-    loops: Loops,
-    commands: Vec<Command>,
+    /// The version of the replay model the file was written in, files without one are upgraded on load.
+    #[serde(default)]
+    pub(super) version: u32,
+    #[serde(default)]
+    pub(super) loops: Loops,
+    /// Script files, relative to this one, whose procedures and variables are merged into this script.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(super) include: Vec<PathBuf>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(super) parameters: BTreeMap<String, Parameter>,
+    /// The parameter values given on the command line.
+    #[serde(skip)]
+    arguments: BTreeMap<String, Value>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(super) variables: BTreeMap<String, Argument>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(super) procedures: BTreeMap<String, Procedure>,
+    #[serde(default)]
+    pub(super) commands: Vec<Command>,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub struct Procedure {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parameters: Vec<String>,
+    pub commands: Vec<Command>,
+}
+
+impl Default for Commands {
+
+    fn default() -> Self {
+        Self {
+            version: VERSION,
+            loops: Loops::default(),
+            include: Vec::new(),
+            parameters: BTreeMap::new(),
+            arguments: BTreeMap::new(),
+            variables: BTreeMap::new(),
+            procedures: BTreeMap::new(),
+            commands: Vec::new(),
+        }
+    }
 }
 
 impl Commands {
 
-    pub fn iter_loop(&self) -> Box<dyn Iterator<Item = usize>> {
-        self.loops.iter()     
+    /// Reads a replay file and resolves its includes, which are read in the format of their extension or else in the given one.
+    pub fn load(path: &Path, format: Format) -> Result<Self, String> {
+        let mut commands = Self::read(path, format)?;
+        let canonical = path.canonicalize().map_err(|e| format!("failed to open '{}' because '{:?}'", path.display(), e.kind()))?;
+        commands.resolve_includes(path, format, &mut vec![canonical], &mut HashSet::new())?;
+        Ok(commands)
+    }
+
+    /// Reads a single replay file, its includes are left unresolved. Outdated versions are upgraded silently, the
+    /// diagnostics tell about them.
+    pub fn read(path: &Path, format: Format) -> Result<Self, String> {
+        let content = fs::read(path).map_err(|e| format!("failed to open '{}' because '{:?}'", path.display(), e.kind()))?;
+        let (commands, _) = migration::upgrade(&content, format).map_err(|e| format!("'{}' contains an invalid replay: {}", path.display(), e))?;
+        Ok(commands)
+    }
+
+    pub fn save(&self, path: &Path, format: Format) -> Result<(), String> {
+        let content = format.encode(self).map_err(|e| format!("failed to serialize the replay: {}", e))?;
+        fs::write(path, content).map_err(|e| format!("failed to write '{}' because '{:?}'", path.display(), e.kind()))
+    }
+
+    /// Merges the included files depth first, `stack` holds the files currently being included to detect cycles.
+    fn resolve_includes(&mut self, path: &Path, format: Format, stack: &mut Vec<PathBuf>, loaded: &mut HashSet<PathBuf>) -> Result<(), String> {
+        let base = path.parent().unwrap_or_else(|| Path::new("."));
+        for include in std::mem::take(&mut self.include) {
+            let file = base.join(&include);
+            let canonical = file
+                .canonicalize()
+                .map_err(|e| format!("failed to include '{}' because '{:?}'", file.display(), e.kind()))?;
+            if stack.contains(&canonical) {
+                let cycle = stack
+                    .iter()
+                    .chain(std::iter::once(&canonical))
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(" -> ");
+                return Err(format!("include cycle {}", cycle));
+            }
+            if !loaded.insert(canonical.clone()) {
+                continue;
+            }
+            let format = Format::from_path(&file).unwrap_or(format);
+            let mut library = Self::read(&file, format)?;
+            if !library.commands.is_empty() {
+                return Err(format!("included file '{}' may only define procedures and variables", file.display()));
+            }
+            stack.push(canonical);
+            library.resolve_includes(&file, format, stack, loaded)?;
+            stack.pop();
+            for (name, procedure) in library.procedures {
+                if self.procedures.contains_key(&name) {
+                    return Err(format!("procedure '{}' from '{}' is already defined", name, file.display()));
+                }
+                self.procedures.insert(name, procedure);
+            }
+            for (name, value) in library.variables {
+                self.variables.entry(name).or_insert(value);
+            }
+            for (name, parameter) in library.parameters {
+                self.parameters.entry(name).or_insert(parameter);
+            }
+        }
+        Ok(())
     }
 
-    pub fn iter_commands(&self) -> Iter<Command> {
+    pub fn iter_loop<'a>(&self, scope: &Scope, clock: &'a dyn Fn() -> Instant) -> Result<Box<dyn Iterator<Item = usize> + 'a>, String> {
+        self.loops.iter(scope, clock)
+    }
+
+    /// Overrides the parameter defaults, every assignment must match a declared parameter and its type.
+    pub fn bind(&mut self, assignments: &[Assignment]) -> Result<(), String> {
+        for assignment in assignments {
+            let parameter = self.parameters
+                .get(&assignment.name)
+                .ok_or_else(|| format!("the script has no parameter '{}'", assignment.name))?;
+            let value = parameter.kind
+                .value(&Argument::Expression(assignment.value.clone().into()))
+                .map_err(|e| format!("invalid value for parameter '{}': {}", assignment.name, e))?;
+            self.arguments.insert(assignment.name.clone(), value);
+        }
+        match self.parameters.keys().find(|name| !self.arguments.contains_key(*name) && self.parameters[*name].default.is_none()) {
+            Some(name) => Err(format!("the parameter '{}' has no default and must be given with --param {}=<value>", name, name)),
+            None => Ok(()),
+        }
+    }
+
+    /// Evaluates the parameters and the initial variables, which may refer to each other regardless of their order.
+    pub fn scope(&self) -> Result<Scope, String> {
+        let mut scope = Scope::default();
+        for (name, parameter) in &self.parameters {
+            let value = match self.arguments.get(name) {
+                Some(value) => Ok(value.clone()),
+                None => parameter.default_value().unwrap_or_else(|| Err(String::from("no value was given"))),
+            };
+            scope.set(name, value.map_err(|e| format!("failed to initialize parameter '{}': {}", name, e))?);
+        }
+        let mut pending: Vec<(&String, &Argument)> = self.variables.iter().collect();
+        while !pending.is_empty() {
+            let before = pending.len();
+            let mut failure = None;
+            pending.retain(|(name, argument)| match argument.evaluate(&scope) {
+                Ok(value) => {
+                    scope.set(name, value);
+                    false
+                },
+                Err(e) => {
+                    failure = Some(format!("failed to initialize variable '{}': {}", name, e));
+                    true
+                },
+            });
+            if pending.len() == before {
+                return Err(failure.unwrap_or_default());
+            }
+        }
+        Ok(scope)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match self.problems().into_iter().next() {
+            Some((pointer, problem)) if pointer.is_root() => Err(problem),
+            Some((pointer, problem)) => Err(format!("{}: {}", pointer, problem)),
+            None => Ok(()),
+        }
+    }
+
+    /// Lists every problem together with the part of the replay it is found in.
+    pub fn problems(&self) -> Vec<(Pointer, String)> {
+        let root = Pointer::default();
+        let mut commands = Vec::new();
+        walk_pointers(&self.commands, &root.key("commands"), &mut commands);
+        for (name, procedure) in &self.procedures {
+            walk_pointers(&procedure.commands, &root.key("procedures").key(name).key("commands"), &mut commands);
+        }
+        let mut problems: Vec<(Pointer, String)> = commands
+            .iter()
+            .filter_map(|(pointer, command)| command.validate().err().map(|problem| (pointer.clone(), problem)))
+            .collect();
+        if let Err(problem) = self.loops.validate() {
+            problems.push((root.key("loops"), problem));
+        }
+        for (name, parameter) in &self.parameters {
+            let pointer = root.key("parameters").key(name);
+            if !is_identifier(name) || BUILTIN_VARIABLES.contains(&name.as_str()) || self.variables.contains_key(name) {
+                problems.push((pointer, format!("'{}' is no valid parameter name", name)));
+            } else if let Some(Err(e)) = parameter.default_value() {
+                problems.push((pointer, format!("invalid default of parameter '{}': {}", name, e)));
+            }
+        }
+        for (name, procedure) in &self.procedures {
+            if let Some(parameter) = procedure.parameters.iter().find(|p| !is_identifier(p) || BUILTIN_VARIABLES.contains(&p.as_str())) {
+                problems.push((root.key("procedures").key(name), format!("'{}' is no valid parameter name of procedure '{}'", parameter, name)));
+            }
+        }
+        if let Err(problem) = Program::compile(self) {
+            problems.push((root.clone(), problem));
+        }
+        let declared: HashSet<&str> = self.variables
+            .keys()
+            .chain(self.parameters.keys())
+            .map(String::as_str)
+            .chain(self.procedures.values().flat_map(|p| p.parameters.iter().map(String::as_str)))
+            .chain(commands.iter().filter_map(|(_, c)| c.assigned_variable()))
+            .chain(BUILTIN_VARIABLES.iter().copied())
+            .collect();
+        let undeclared = |arguments: Vec<&Argument>| arguments
+            .into_iter()
+            .filter_map(|argument| argument.expression().ok().flatten())
+            .find_map(|expression| expression.variables().into_iter().find(|variable| !declared.contains(variable)).map(String::from));
+        let used = self.variables
+            .iter()
+            .map(|(name, value)| (root.key("variables").key(name), vec![value]))
+            .chain(std::iter::once((root.key("loops"), self.loops.argument().into_iter().collect())))
+            .chain(commands.iter().map(|(pointer, command)| (pointer.clone(), command.arguments())));
+        for (pointer, arguments) in used {
+            if let Some(variable) = undeclared(arguments) {
+                problems.push((pointer, format!("variable '{}' is never declared", variable)));
+            }
+        }
+        problems
+    }
+
+    pub fn iter_commands(&self) -> Iter<'_, Command> {
         self.commands.iter()
     }
+
+    pub fn procedures(&self) -> &BTreeMap<String, Procedure> {
+        &self.procedures
+    }
+
+    /// Lists all commands including the ones nested in blocks and procedures, depth first.
+    pub fn walk(&self) -> Vec<&Command> {
+        let mut all = Vec::new();
+        walk(&self.commands, &mut all);
+        self.procedures.values().for_each(|procedure| walk(&procedure.commands, &mut all));
+        all
+    }
+}
+
+fn walk<'a>(commands: &'a [Command], all: &mut Vec<&'a Command>) {
+    for command in commands {
+        all.push(command);
+        command.blocks().into_iter().for_each(|(_, block)| walk(block, all));
+    }
+}
+
+/// Like `walk` but pairs every command with its position, e.g. `commands[2].repeat.commands[0].mouse`.
+fn walk_pointers<'a>(commands: &'a [Command], pointer: &Pointer, all: &mut Vec<(Pointer, &'a Command)>) {
+    for (i, command) in commands.iter().enumerate() {
+        let pointer = pointer.index(i).key(command.name());
+        all.push((pointer.clone(), command));
+        command.blocks().into_iter().for_each(|(name, block)| walk_pointers(block, &pointer.key(name), all));
+    }
 }
 
 impl From<&mut Vec<Command>> for Commands {
 
     fn from(commands: &mut Vec<Command>) -> Self {
         Self {
-            loops: Loops::Amount(1),
-            commands: commands.drain(0..).collect()
+            commands: std::mem::take(commands),
+            ..Self::default()
         }
     }
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[allow(clippy::enum_variant_names)]
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
 pub enum Command {
 
     #[serde(rename = "mouse")]
@@ -62,45 +364,268 @@ pub enum Command {
         key: Key,
         #[serde(default)]
         loops: Loops, 
-        x: i32, 
-        y: i32, 
+        x: Argument, 
+        y: Argument, 
     },
 
+    /// Presses the key while holding the modifiers, e.g. `Control` for Ctrl+S.
     #[serde(rename = "keyboard")]
     KeyboardCommand { 
         key: Key,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        modifiers: Vec<Key>,
         #[serde(default)]
         loops: Loops, 
     },
 
     #[serde(rename = "sleep")]
-    SleepCommand { millis: u64, },
+    SleepCommand { millis: Argument, },
+
+    #[serde(rename = "label")]
+    LabelCommand { name: String, },
+
+    #[serde(rename = "set")]
+    SetCommand { 
+        name: String, 
+        value: Argument, 
+    },
+
+    #[serde(rename = "increment")]
+    IncrementCommand { 
+        name: String, 
+        #[serde(default = "Command::default_increment")]
+        by: Argument, 
+    },
+
+    #[serde(rename = "repeat")]
+    RepeatCommand {
+        loops: Loops,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        counter: Option<String>,
+        commands: Vec<Command>,
+    },
+
+    #[serde(rename = "if")]
+    IfCommand {
+        condition: Argument,
+        then: Vec<Command>,
+        #[serde(default, rename = "else", skip_serializing_if = "Vec::is_empty")]
+        otherwise: Vec<Command>,
+    },
+
+    #[serde(rename = "break")]
+    BreakCommand,
+
+    #[serde(rename = "continue")]
+    ContinueCommand,
+
+    #[serde(rename = "goto")]
+    GotoCommand { label: String, },
+
+    /// Polls the condition every `interval` milliseconds, optionally giving up after `timeout` milliseconds.
+    #[serde(rename = "wait_until")]
+    WaitUntilCommand {
+        condition: Condition,
+        #[serde(default = "Command::default_interval")]
+        interval: Argument,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout: Option<Argument>,
+        #[serde(default, skip_serializing_if = "OnTimeout::is_fail")]
+        on_timeout: OnTimeout,
+    },
+
+    #[serde(rename = "call")]
+    CallCommand {
+        name: String,
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        arguments: BTreeMap<String, Argument>,
+    },
+
+    /// Searches the screen or a region of it for a PNG, storing the center of the match in `variable` and/or clicking it.
+    #[serde(rename = "find_image")]
+    FindImageCommand {
+        image: PathBuf,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        region: Option<Region>,
+        #[serde(default = "default_confidence")]
+        confidence: f64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        variable: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        click: Option<Key>,
+    },
+
+    /// Checks the condition once, a failure is reported and only stops the replay when `abort` is set.
+    #[serde(rename = "assert")]
+    AssertCommand {
+        condition: Condition,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        abort: bool,
+    },
+
+    /// Saves the screen or a region of it as PNG, missing directories are created.
+    #[serde(rename = "screenshot")]
+    ScreenshotCommand {
+        path: Template,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        region: Option<Region>,
+    },
 }
 
 impl Command {
 
+    fn default_increment() -> Argument {
+        1.into()
+    }
+
+    fn default_interval() -> Argument {
+        100.into()
+    }
+
     pub fn is_valid(&self) -> bool {
+        self.validate().is_ok()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Self::MouseCommand{ key, .. } if !key.is_mouse() => Err(format!("{:?} is no mouse key", key)),
+            Self::KeyboardCommand{ key, modifiers, .. } if key.is_mouse() || modifiers.iter().any(Key::is_mouse) => Err(format!("{:?} is no keyboard key", key)),
+            Self::LabelCommand{ name } | Self::GotoCommand{ label: name } if name.is_empty() => Err(String::from("labels must not be empty")),
+            Self::CallCommand{ name, .. } if name.is_empty() => Err(String::from("procedure names must not be empty")),
+            Self::WaitUntilCommand{ timeout: None, on_timeout: OnTimeout::Goto(_), .. } => Err(String::from("jumping on timeout requires a timeout")),
+            Self::WaitUntilCommand{ on_timeout: OnTimeout::Goto(label), .. } if label.is_empty() => Err(String::from("labels must not be empty")),
+            Self::FindImageCommand{ click: Some(key), .. } if !key.is_mouse() => Err(format!("{:?} is no mouse key", key)),
+            _ => match self.assigned_variable() {
+                Some(name) if !is_identifier(name) => Err(format!("'{}' is no valid variable name", name)),
+                Some(name) if BUILTIN_VARIABLES.contains(&name) => Err(format!("'{}' is a builtin variable", name)),
+                _ => self.validate_ranges().and_then(|_| self.arguments().iter().try_for_each(|argument| argument.validate())),
+            },
+        }
+    }
+
+    /// Rejects literals out of range, expressions are only checked at replay time.
+    fn validate_ranges(&self) -> Result<(), String> {
+        match self {
+            Self::MouseCommand{ loops, .. } | Self::KeyboardCommand{ loops, .. } | Self::RepeatCommand{ loops, .. } => loops.validate(),
+            Self::SleepCommand{ millis } => validate_minimum(millis, 0, "the sleep duration"),
+            Self::WaitUntilCommand{ condition, interval, timeout, .. } => condition
+                .validate()
+                .and_then(|_| validate_minimum(interval, 1, "the polling interval"))
+                .and_then(|_| timeout.as_ref().map_or(Ok(()), |timeout| validate_minimum(timeout, 0, "the timeout"))),
+            Self::AssertCommand{ condition, .. } => condition.validate(),
+            Self::FindImageCommand{ confidence, region, .. } => validate_confidence(*confidence).and_then(|_| region.as_ref().map_or(Ok(()), Region::validate)),
+            Self::ScreenshotCommand{ region, .. } => region.as_ref().map_or(Ok(()), Region::validate),
+            _ => Ok(()),
+        }
+    }
+
+    /// The nested blocks by the name of their field.
+    pub fn blocks(&self) -> Vec<(&'static str, &[Command])> {
         match self {
-            Self::MouseCommand{ key, .. } => key.is_mouse(),
-            Self::KeyboardCommand{ key, .. } => !key.is_mouse(),
-            Self::SleepCommand{ .. } => true,
+            Self::RepeatCommand{ commands, .. } => vec![("commands", commands)],
+            Self::IfCommand{ then, otherwise, .. } => vec![("then", then), ("else", otherwise)],
+            _ => vec![],
         }
     }
 
-    pub fn iter(&self) -> Box<dyn Iterator<Item = usize>> {
+    /// The name of the command in replay files.
+    pub fn name(&self) -> &'static str {
         match self {
-            Command::MouseCommand { loops, .. } => loops.iter(),
-            Command::KeyboardCommand { loops, .. } => loops.iter(),
-            Command::SleepCommand { .. } => Loops::default().iter(),
+            Self::MouseCommand{ .. } => "mouse",
+            Self::KeyboardCommand{ .. } => "keyboard",
+            Self::SleepCommand{ .. } => "sleep",
+            Self::LabelCommand{ .. } => "label",
+            Self::SetCommand{ .. } => "set",
+            Self::IncrementCommand{ .. } => "increment",
+            Self::RepeatCommand{ .. } => "repeat",
+            Self::IfCommand{ .. } => "if",
+            Self::BreakCommand => "break",
+            Self::ContinueCommand => "continue",
+            Self::GotoCommand{ .. } => "goto",
+            Self::WaitUntilCommand{ .. } => "wait_until",
+            Self::CallCommand{ .. } => "call",
+            Self::FindImageCommand{ .. } => "find_image",
+            Self::AssertCommand{ .. } => "assert",
+            Self::ScreenshotCommand{ .. } => "screenshot",
         }
     }
+
+    pub fn arguments(&self) -> Vec<&Argument> {
+        match self {
+            Self::MouseCommand{ loops, x, y, .. } => loops.argument().into_iter().chain(vec![x, y]).collect(),
+            Self::KeyboardCommand{ loops, .. } => loops.argument().into_iter().collect(),
+            Self::SleepCommand{ millis } => vec![millis],
+            Self::LabelCommand{ .. } => vec![],
+            Self::SetCommand{ value, .. } => vec![value],
+            Self::IncrementCommand{ by, .. } => vec![by],
+            Self::RepeatCommand{ loops, .. } => loops.argument().into_iter().collect(),
+            Self::IfCommand{ condition, .. } => vec![condition],
+            Self::BreakCommand | Self::ContinueCommand | Self::GotoCommand{ .. } => vec![],
+            Self::CallCommand{ arguments, .. } => arguments.values().collect(),
+            Self::WaitUntilCommand{ condition, interval, timeout, .. } => condition
+                .arguments()
+                .into_iter()
+                .chain(Some(interval))
+                .chain(timeout.as_ref())
+                .collect(),
+            Self::AssertCommand{ condition, .. } => condition.arguments(),
+            Self::FindImageCommand{ region, .. } => region.iter().flat_map(Region::arguments).collect(),
+            Self::ScreenshotCommand{ path, region } => path
+                .arguments()
+                .into_iter()
+                .chain(region.iter().flat_map(Region::arguments))
+                .collect(),
+        }
+    }
+
+    pub fn reads_screen(&self) -> bool {
+        match self {
+            Self::WaitUntilCommand{ condition, .. } | Self::AssertCommand{ condition, .. } => condition.reads_screen(),
+            Self::FindImageCommand{ .. } | Self::ScreenshotCommand{ .. } => true,
+            _ => false,
+        }
+    }
+
+    fn assigned_variable(&self) -> Option<&str> {
+        match self {
+            Self::SetCommand{ name, .. } | Self::IncrementCommand{ name, .. } => Some(name),
+            Self::RepeatCommand{ counter, .. } => counter.as_deref(),
+            Self::FindImageCommand{ variable, .. } => variable.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn iter<'a>(&self, scope: &Scope, clock: &'a dyn Fn() -> Instant) -> Result<Box<dyn Iterator<Item = usize> + 'a>, String> {
+        match self {
+            Command::MouseCommand { loops, .. } => loops.iter(scope, clock),
+            Command::KeyboardCommand { loops, .. } => loops.iter(scope, clock),
+            _ => Loops::default().iter(scope, clock),
+        }
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_alphabetic() || c == '_') && chars.all(|c| c.is_alphanumeric() || c == '_')
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{hardware::Key, replay::Command};
+    use std::{convert::TryFrom, time::Instant};
+
+    use crate::{expression::{Argument, Scope, Template, Value}, hardware::Key, replay::{Command, Condition}};
+
+    use super::{Commands, Format, Loops};
 
-    use super::{Commands, Loops};
+    fn commands(loops: Loops, commands: Vec<Command>) -> Commands {
+        Commands { loops, commands, ..Commands::default() }
+    }
+
+    fn expression(source: &str) -> Argument {
+        Argument::Expression(source.into())
+    }
 
     fn sample_yaml() -> String {
         String::from_utf8_lossy(&std::fs::read("./test/commands.yaml").expect("valid yaml file")).into()
@@ -117,11 +642,26 @@ mod tests {
     #[test]
     fn is_valid() {
         vec![
-            (true, Command::MouseCommand { key: Key::MouseLeft, loops: Loops::Infinite, x: 0, y: 0, }),
-            (false, Command::MouseCommand { key: Key::A, loops: Loops::Infinite, x: 0, y: 0, }),
-            (true, Command::KeyboardCommand { key: Key::A, loops: Loops::Infinite, }),
-            (false, Command::KeyboardCommand { key: Key::MouseMiddle, loops: Loops::Infinite, }),
-            (true, Command::SleepCommand { millis: 0 }),
+            (true, Command::MouseCommand { key: Key::MouseLeft, loops: Loops::Infinite, x: 0.into(), y: 0.into(), }),
+            (false, Command::MouseCommand { key: Key::A, loops: Loops::Infinite, x: 0.into(), y: 0.into(), }),
+            (true, Command::KeyboardCommand { key: Key::A, modifiers: vec![], loops: Loops::Infinite, }),
+            (false, Command::KeyboardCommand { key: Key::MouseMiddle, modifiers: vec![], loops: Loops::Infinite, }),
+            (true, Command::KeyboardCommand { key: Key::S, modifiers: vec![Key::Control, Key::Shift], loops: Loops::default(), }),
+            (false, Command::KeyboardCommand { key: Key::S, modifiers: vec![Key::MouseLeft], loops: Loops::default(), }),
+            (true, Command::SleepCommand { millis: 0.into() }),
+            (true, Command::LabelCommand { name: "start".into() }),
+            (false, Command::LabelCommand { name: "".into() }),
+            (true, Command::MouseCommand { key: Key::MouseLeft, loops: Loops::Amount(expression("n * 2")), x: expression("x + 1"), y: 0.into(), }),
+            (false, Command::MouseCommand { key: Key::MouseLeft, loops: Loops::Infinite, x: expression("x +"), y: 0.into(), }),
+            (true, Command::SetCommand { name: "x".into(), value: expression("point(1, 2)") }),
+            (false, Command::SetCommand { name: "1x".into(), value: 1.into() }),
+            (false, Command::IncrementCommand { name: "iteration".into(), by: 1.into() }),
+            (true, Command::FindImageCommand { image: "button.png".into(), region: None, confidence: 0.8, variable: Some("button".into()), click: Some(Key::MouseLeft) }),
+            (false, Command::FindImageCommand { image: "button.png".into(), region: None, confidence: 1.5, variable: None, click: None }),
+            (false, Command::FindImageCommand { image: "button.png".into(), region: None, confidence: 0.8, variable: None, click: Some(Key::A) }),
+            (false, Command::FindImageCommand { image: "button.png".into(), region: None, confidence: 0.8, variable: Some("repetition".into()), click: None }),
+            (true, Command::AssertCommand { condition: Condition::Clipboard("copied".into()), name: None, abort: true }),
+            (false, Command::AssertCommand { condition: Condition::Image { image: "logo.png".into(), region: None, confidence: 3.0 }, name: None, abort: false }),
         ]
         .into_iter()
         .enumerate()
@@ -131,31 +671,135 @@ mod tests {
     #[test]
     fn iter_loop_infinite() {
         (10..100)
-            .into_iter()
-            .map(|i| (i, Commands { loops: Loops::Infinite, commands: Vec::default() }.iter_loop()))
-            .for_each(|(cap, iter)| {
-                assert!(iter.skip(cap).next().is_some());
+            .map(|i| (i, commands(Loops::Infinite, Vec::default()).iter_loop(&Scope::default(), &Instant::now).unwrap()))
+            .for_each(|(cap, mut iter)| {
+                assert!(iter.nth(cap).is_some());
             });
     }
 
     #[test]
     fn iter_loop_amount() {
         (10..100)
-            .into_iter()
-            .map(|i| (i, Commands { loops: Loops::Amount(i), commands: Vec::default() }.iter_loop()))
-            .for_each(|(cap, iter)| {
-                assert!(iter.skip(cap).next().is_none());
+            .map(|i| (i, commands(Loops::Amount(i.into()), Vec::default()).iter_loop(&Scope::default(), &Instant::now).unwrap()))
+            .for_each(|(cap, mut iter)| {
+                assert!(iter.nth(cap).is_none());
             });
     }
 
     #[test]
     fn from() {
         let mut records = vec![
-            Command::MouseCommand { key: Key::MouseLeft, loops: Loops::Infinite, x: 0, y: 0, },
-            Command::SleepCommand { millis: 0, },
+            Command::MouseCommand { key: Key::MouseLeft, loops: Loops::Infinite, x: 0.into(), y: 0.into(), },
+            Command::SleepCommand { millis: 0.into(), },
         ];
         let result: Commands = Commands::from(&mut records);
         assert!(records.is_empty());
         assert_eq!(2, result.commands.len());
     }
+
+    #[test]
+    fn scope() {
+        let mut sample = commands(Loops::default(), Vec::new());
+        sample.variables.insert("a".into(), expression("b + 1"));
+        sample.variables.insert("b".into(), 41.into());
+        sample.variables.insert("origin".into(), expression("point(a, b)"));
+        let scope = sample.scope().expect("valid variables");
+        assert_eq!(Some(&Value::Number(42.0)), scope.get("a"));
+        assert_eq!(Some(&Value::Point(42.0, 41.0)), scope.get("origin"));
+        sample.variables.insert("c".into(), expression("c + 1"));
+        assert!(sample.scope().is_err());
+    }
+
+    #[test]
+    fn validate() {
+        let mut sample = commands(Loops::Amount(expression("rows")), vec![
+            Command::SetCommand { name: "x".into(), value: 10.into() },
+            Command::MouseCommand { key: Key::MouseLeft, loops: Loops::default(), x: expression("x + repetition"), y: expression("y"), },
+        ]);
+        assert!(sample.validate().is_err());
+        sample.variables.insert("rows".into(), 3.into());
+        assert!(sample.validate().is_err());
+        sample.variables.insert("y".into(), 5.into());
+        assert!(sample.validate().is_ok());
+        sample.commands.push(Command::ScreenshotCommand { path: Template::try_from(String::from("shots/{x}-{missing}.png")).unwrap(), region: None });
+        assert!(sample.validate().is_err());
+    }
+
+    #[test]
+    fn variables_deserialization() {
+        let result: Commands = serde_yaml::from_str("
+            loops:
+                Amount: count
+            variables:
+                count: 3
+                step: \"count * 10\"
+            commands:
+              - increment:
+                    name: step
+              - mouse:
+                    key: MouseLeft
+                    x: \"step + 1\"
+                    y: 20
+        ").expect("valid yaml");
+        assert!(result.validate().is_ok(), "{:?}", result.validate());
+        let scope = result.scope().expect("valid variables");
+        assert_eq!(3, result.iter_loop(&scope, &Instant::now).unwrap().count());
+        assert_eq!(Some(&Value::Number(30.0)), scope.get("step"));
+    }
+
+    #[test]
+    fn iter_loop_duration() {
+        let iter = commands(Loops::Duration(30.into()), Vec::default()).iter_loop(&Scope::default(), &Instant::now).unwrap();
+        let start = std::time::Instant::now();
+        iter.for_each(|_| std::thread::sleep(std::time::Duration::from_millis(5)));
+        assert!(start.elapsed() >= std::time::Duration::from_millis(30));
+    }
+
+    #[test]
+    fn load() {
+        let result = Commands::load(std::path::Path::new("./test/procedures.yaml"), Format::Yaml).expect("valid replay");
+        assert!(result.include.is_empty());
+        assert_eq!(vec!["login", "open_menu"], result.procedures.keys().collect::<Vec<_>>());
+        assert!(result.variables.contains_key("menu"));
+        assert!(result.validate().is_ok(), "{:?}", result.validate());
+    }
+
+    #[test]
+    fn include_cycle() {
+        let result = Commands::load(std::path::Path::new("./test/cycle.yaml"), Format::Yaml);
+        assert!(matches!(&result, Err(e) if e.starts_with("include cycle")), "{:?}", result);
+    }
+
+    #[test]
+    fn parameters() {
+        let parse = |kind: &str, default: &str| serde_yaml::from_str::<Commands>(&format!("
+            parameters:
+                count:
+                    type: {}
+                    default: {}
+            commands:
+              - sleep:
+                    millis: count
+        ", kind, default)).expect("valid yaml");
+        assert!(parse("number", "20").validate().is_ok());
+        assert!(parse("number", "\"point(1, 2)\"").validate().is_err());
+        assert!(parse("bool", "20").validate().is_err());
+        let mut sample = parse("number", "20");
+        assert_eq!(Some(&Value::Number(20.0)), sample.scope().unwrap().get("count"));
+        sample.bind(&["count=7".parse().unwrap()]).expect("valid assignment");
+        assert_eq!(Some(&Value::Number(7.0)), sample.scope().unwrap().get("count"));
+        assert!(sample.bind(&["count=true".parse().unwrap()]).is_err());
+    }
+
+    #[test]
+    fn walk() {
+        let sample = commands(Loops::default(), vec![
+            Command::RepeatCommand { loops: Loops::Infinite, counter: Some("row".into()), commands: vec![
+                Command::IfCommand { condition: expression("row > 2"), then: vec![Command::BreakCommand], otherwise: vec![Command::ContinueCommand] },
+            ]},
+            Command::SleepCommand { millis: expression("row") },
+        ]);
+        assert_eq!(5, sample.walk().len());
+        assert!(sample.validate().is_ok(), "{:?}", sample.validate());
+    }
 }
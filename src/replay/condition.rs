@@ -0,0 +1,259 @@
+use std::path::PathBuf;
+
+use bindings::Windows::Win32::Foundation::POINT;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{expression::{Argument, Scope}, hardware::{self, Color, Image, Key, Screen}};
+
+/// A state of the machine a replay can wait for.
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub enum Condition {
+
+    /// Holds while no color channel of the pixel differs by more than `tolerance`.
+    #[serde(rename = "pixel")]
+    Pixel {
+        x: Argument,
+        y: Argument,
+        color: Color,
+        #[serde(default, skip_serializing_if = "is_zero")]
+        tolerance: u8,
+    },
+
+    #[serde(rename = "key_pressed")]
+    KeyPressed(Key),
+
+    /// Matches the exact title of any top level window.
+    #[serde(rename = "window_exists")]
+    WindowExists(String),
+
+    #[serde(rename = "window_focused")]
+    WindowFocused(String),
+
+    #[serde(rename = "file_exists")]
+    FileExists(PathBuf),
+
+    /// Holds while the PNG is found on the screen or the region with at least the given confidence.
+    #[serde(rename = "image")]
+    Image {
+        image: PathBuf,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        region: Option<Region>,
+        #[serde(default = "default_confidence")]
+        confidence: f64,
+    },
+
+    /// Matches the exact text on the clipboard.
+    #[serde(rename = "clipboard")]
+    Clipboard(String),
+}
+
+impl Condition {
+
+    pub fn is_met(&self, scope: &Scope, screen: &dyn Screen) -> Result<bool, String> {
+        self.is_met_with(scope, screen, self.load_template()?.as_ref())
+    }
+
+    /// Loads the PNG an image condition searches for, so polling the condition does not read it again.
+    pub fn load_template(&self) -> Result<Option<Image>, String> {
+        match self {
+            Condition::Image { image, .. } => Image::load(image).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Like `is_met` with the template of an image condition already loaded by `load_template`.
+    pub fn is_met_with(&self, scope: &Scope, screen: &dyn Screen, template: Option<&Image>) -> Result<bool, String> {
+        match self {
+            Condition::Pixel { x, y, color, tolerance } => {
+                let pixel = screen.pixel(x.evaluate(scope)?.as_i32()?, y.evaluate(scope)?.as_i32()?)?;
+                Ok(pixel.matches(color, *tolerance))
+            },
+            Condition::KeyPressed(key) => Ok(key.is_pressed()),
+            Condition::WindowExists(title) => Ok(hardware::window_exists(title)),
+            Condition::WindowFocused(title) => Ok(hardware::focused_window_title().as_ref() == Some(title)),
+            Condition::FileExists(path) => Ok(path.exists()),
+            Condition::Image { image, region, confidence } => {
+                let template = template.ok_or_else(|| format!("'{}' is not loaded", image.to_string_lossy()))?;
+                locate(screen, template, region.as_ref(), *confidence, scope).map(|found| found.is_some())
+            },
+            Condition::Clipboard(text) => Ok(hardware::clipboard_text().as_ref() == Some(text)),
+        }
+    }
+
+    /// Pixels and images are read from the screen, every other condition checks the live system.
+    pub fn reads_screen(&self) -> bool {
+        matches!(self, Condition::Pixel { .. } | Condition::Image { .. })
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Condition::Image { confidence, region, .. } => validate_confidence(*confidence).and_then(|_| region.as_ref().map_or(Ok(()), Region::validate)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn arguments(&self) -> Vec<&Argument> {
+        match self {
+            Condition::Pixel { x, y, .. } => vec![x, y],
+            Condition::Image { region, .. } => region.iter().flat_map(Region::arguments).collect(),
+            _ => vec![],
+        }
+    }
+}
+
+/// The area of the screen with its top left corner at (x|y).
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub struct Region {
+    pub x: Argument,
+    pub y: Argument,
+    pub width: Argument,
+    pub height: Argument,
+}
+
+impl Region {
+
+    pub fn validate(&self) -> Result<(), String> {
+        validate_minimum(&self.width, 1, "the width of a region").and_then(|_| validate_minimum(&self.height, 1, "the height of a region"))
+    }
+
+    pub fn arguments(&self) -> Vec<&Argument> {
+        vec![&self.x, &self.y, &self.width, &self.height]
+    }
+}
+
+pub fn default_confidence() -> f64 {
+    0.9
+}
+
+pub fn validate_confidence(confidence: f64) -> Result<(), String> {
+    match (-1.0..=1.0).contains(&confidence) {
+        true => Ok(()),
+        false => Err(format!("the confidence {} is not between -1 and 1", confidence)),
+    }
+}
+
+/// Fails for literals below the minimum, expressions can only be checked once evaluated.
+pub fn validate_minimum(argument: &Argument, minimum: i64, what: &str) -> Result<(), String> {
+    match argument.literal() {
+        Some(n) if n < minimum => Err(format!("{} must be at least {} but is {}", what, minimum, n)),
+        _ => Ok(()),
+    }
+}
+
+/// Captures the region or the whole screen and returns the image with the position of its top left corner.
+pub fn capture(screen: &dyn Screen, region: Option<&Region>, scope: &Scope) -> Result<(i32, i32, Image), String> {
+    let (x, y, width, height) = match region {
+        Some(region) => (
+            region.x.evaluate(scope)?.as_i32()?,
+            region.y.evaluate(scope)?.as_i32()?,
+            region.width.evaluate(scope)?.as_u32()?,
+            region.height.evaluate(scope)?.as_u32()?,
+        ),
+        None => {
+            let (width, height) = screen.size()?;
+            (0, 0, width, height)
+        },
+    };
+    screen.capture(x, y, width, height).map(|image| (x, y, image))
+}
+
+/// Returns the center of the best match of the PNG on the screen together with its score.
+pub fn locate(screen: &dyn Screen, template: &Image, region: Option<&Region>, confidence: f64, scope: &Scope) -> Result<Option<(POINT, f64)>, String> {
+    let (x, y, captured) = capture(screen, region, scope)?;
+    let found = hardware::find_template(&captured, template, confidence)?;
+    Ok(found.map(|found| {
+        let center = POINT { x: x + (found.x + template.width() / 2) as i32, y: y + (found.y + template.height() / 2) as i32 };
+        (center, found.score)
+    }))
+}
+
+fn is_zero(n: &u8) -> bool {
+    *n == 0
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub enum OnTimeout {
+    #[default]
+    #[serde(rename = "fail")]
+    Fail,
+    #[serde(rename = "goto")]
+    Goto(String),
+}
+
+impl OnTimeout {
+
+    pub fn is_fail(&self) -> bool {
+        matches!(self, OnTimeout::Fail)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{expression::Scope, hardware::{Color, ImageScreen, Key}};
+
+    use super::{Condition, OnTimeout, Region};
+
+    #[test]
+    fn deserialization() {
+        vec![
+            (Condition::Pixel { x: 10.into(), y: "y + 1".into(), color: Color { red: 255, green: 0, blue: 0 }, tolerance: 0 }, "pixel: { x: 10, y: y + 1, color: \"#ff0000\" }"),
+            (Condition::Pixel { x: 1.into(), y: 2.into(), color: Color { red: 0, green: 0, blue: 0 }, tolerance: 8 }, "pixel: { x: 1, y: 2, color: \"#000000\", tolerance: 8 }"),
+            (Condition::KeyPressed(Key::Escape), "key_pressed: Escape"),
+            (Condition::WindowExists("Editor".into()), "window_exists: Editor"),
+            (Condition::FileExists("done.txt".into()), "file_exists: done.txt"),
+            (Condition::Image { image: "ok.png".into(), region: None, confidence: 0.9 }, "image: { image: ok.png }"),
+            (Condition::Clipboard("copied".into()), "clipboard: copied"),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, (expected, yaml))| assert_eq!(expected, serde_yaml::from_str::<Condition>(yaml).unwrap(), "case {}", case));
+        assert_eq!(OnTimeout::Goto("retry".into()), serde_yaml::from_str::<OnTimeout>("goto: retry").unwrap());
+        assert_eq!(OnTimeout::Fail, serde_yaml::from_str::<OnTimeout>("fail").unwrap());
+    }
+
+    fn screen() -> ImageScreen {
+        ImageScreen::load("./test/screen.png".as_ref()).expect("valid image")
+    }
+
+    #[test]
+    fn file_exists() {
+        let scope = Scope::default();
+        assert_eq!(Ok(true), Condition::FileExists("./test/commands.yaml".into()).is_met(&scope, &screen()));
+        assert_eq!(Ok(false), Condition::FileExists("./test/missing.yaml".into()).is_met(&scope, &screen()));
+    }
+
+    #[test]
+    fn pixel() {
+        let scope = Scope::default();
+        let green = Color { red: 40, green: 200, blue: 120 };
+        vec![
+            (Ok(false), Condition::Pixel { x: 5.into(), y: 5.into(), color: green, tolerance: 0 }),
+            (Ok(true), Condition::Pixel { x: 5.into(), y: 5.into(), color: green, tolerance: 7 }),
+            (Ok(false), Condition::Pixel { x: 6.into(), y: 5.into(), color: green, tolerance: 7 }),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, (expected, condition))| assert_eq!(expected, condition.is_met(&scope, &screen()), "case {}", case));
+        assert!(Condition::Pixel { x: 32.into(), y: 0.into(), color: green, tolerance: 0 }.is_met(&scope, &screen()).is_err());
+    }
+
+    #[test]
+    fn image() {
+        let scope = Scope::default();
+        let region = |x: i32, y: i32| Some(Region { x: x.into(), y: y.into(), width: 10.into(), height: 8.into() });
+        vec![
+            (Ok(true), None, 0.99),
+            (Ok(true), region(18, 8), 0.99),
+            (Ok(false), region(0, 0), 0.5),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, (expected, region, confidence))| {
+            let condition = Condition::Image { image: "./test/button.png".into(), region, confidence };
+            assert_eq!(expected, condition.is_met(&scope, &screen()), "case {}", case);
+        });
+        assert!(Condition::Image { image: "./test/missing.png".into(), region: None, confidence: 0.9 }.is_met(&scope, &screen()).is_err());
+        assert!(Condition::Image { image: "./test/button.png".into(), region: None, confidence: 2.0 }.validate().is_err());
+    }
+}
@@ -0,0 +1,527 @@
+use std::mem;
+
+use crate::{expression::Argument, hardware::Key, replay::{Command, Loops, dsl}};
+
+use super::{Importer, Repeat, Target, character};
+
+/// Commands which only tune how AutoHotkey sends input and have no counterpart in replays.
+const SETTINGS: [&str; 7] = ["sendmode", "setworkingdir", "setbatchlines", "setkeydelay", "setmousedelay", "setdefaultmousespeed", "persistent"];
+
+enum Kind {
+    Loop(Loops),
+    /// Braces without a statement and hotkeys, whose commands become part of the replay.
+    Group,
+    /// Conditions, functions and other blocks replays cannot express.
+    Skipped,
+}
+
+/// A block whose commands are collected until it is closed, single statement blocks end after one statement.
+struct Block {
+    kind: Kind,
+    single: bool,
+    outer: Vec<Command>,
+}
+
+#[derive(Default)]
+struct Script {
+    blocks: Vec<Block>,
+    /// The kind of a block whose header is not followed by a brace on the same line.
+    header: Option<Kind>,
+}
+
+pub fn import(source: &str, importer: &mut Importer) -> Result<(), String> {
+    let mut script = Script::default();
+    let mut comment = false;
+    for (i, line) in source.lines().enumerate() {
+        importer.line = i + 1;
+        let line = strip_comment(line).trim();
+        if comment || line.starts_with("/*") {
+            comment = !line.ends_with("*/") && !line.starts_with("*/");
+            continue;
+        }
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(kind) = script.header.take() {
+            let single = line != "{";
+            script.open(kind, single, importer);
+            if !single {
+                continue;
+            }
+        }
+        script.statement(line, importer)?;
+    }
+    if script.header.is_some() {
+        return Err(format!("line {}: the block has no body", importer.line));
+    }
+    match script.blocks.is_empty() {
+        true => Ok(()),
+        false => Err(format!("line {}: expected '}}' but found the end of the file", importer.line)),
+    }
+}
+
+/// Comments start with a semicolon at the beginning of the line or after whitespace.
+fn strip_comment(line: &str) -> &str {
+    match line.trim_start().starts_with(';') {
+        true => "",
+        false => line.find(" ;").or_else(|| line.find("\t;")).map_or(line, |index| &line[..index]),
+    }
+}
+
+impl Script {
+
+    fn statement(&mut self, line: &str, importer: &mut Importer) -> Result<(), String> {
+        if let Some(rest) = line.strip_prefix('}') {
+            self.close(importer)?;
+            self.completed(importer);
+            let rest = rest.trim();
+            return match rest.is_empty() {
+                true => Ok(()),
+                false => self.statement(rest, importer),
+            };
+        }
+        if let Some((hotkey, rest)) = line.split_once("::") {
+            importer.warn(&format!("the hotkey '{}' is not supported, its commands are converted as part of the replay", hotkey));
+            return match rest.trim() {
+                "" => Ok(()),
+                "{" => {
+                    self.open(Kind::Group, false, importer);
+                    Ok(())
+                },
+                rest => self.statement(rest, importer),
+            };
+        }
+        if line.starts_with('#') {
+            return Ok(());
+        }
+        if line == "{" {
+            self.open(Kind::Group, false, importer);
+            return Ok(());
+        }
+        let (opens, line) = match line.strip_suffix('{') {
+            Some(line) => (true, line.trim_end()),
+            None => (false, line),
+        };
+        let (name, arguments, raw) = split(line);
+        let kind = match name.to_ascii_lowercase().as_str() {
+            "loop" => Some(loops(&arguments).map_or_else(|| {
+                importer.warn("only loops with a number of iterations are supported, the loop is left out");
+                Kind::Skipped
+            }, Kind::Loop)),
+            control if control.starts_with("if") || ["else", "while", "for", "try", "catch", "finally"].contains(&control) || opens => {
+                importer.warn(&format!("'{}' is not supported, its block is left out", line));
+                Some(Kind::Skipped)
+            },
+            _ => None,
+        };
+        match kind {
+            Some(kind) if opens => self.open(kind, false, importer),
+            Some(kind) => self.header = Some(kind),
+            None => {
+                command(name, &arguments, raw, importer);
+                self.completed(importer);
+            },
+        }
+        Ok(())
+    }
+
+    fn open(&mut self, kind: Kind, single: bool, importer: &mut Importer) {
+        let outer = mem::take(&mut importer.commands);
+        self.blocks.push(Block { kind, single, outer });
+    }
+
+    fn close(&mut self, importer: &mut Importer) -> Result<(), String> {
+        let block = self.blocks.pop().ok_or_else(|| format!("line {}: '}}' without a block", importer.line))?;
+        let commands = mem::replace(&mut importer.commands, block.outer);
+        match block.kind {
+            Kind::Loop(loops) => importer.commands.push(Command::RepeatCommand { loops, counter: None, commands }),
+            Kind::Group => importer.commands.extend(commands),
+            Kind::Skipped => (),
+        }
+        Ok(())
+    }
+
+    /// Ends the single statement blocks around a statement which just ended.
+    fn completed(&mut self, importer: &mut Importer) {
+        while self.blocks.last().is_some_and(|block| block.single) {
+            // cannot fail as there is an open block
+            let _ = self.close(importer);
+        }
+    }
+}
+
+/// Splits `Name, a, b`, `Name a, b` and `Name(a, b)` into the name, the arguments and the unsplit arguments.
+fn split(line: &str) -> (&str, Vec<String>, &str) {
+    let end = line.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(line.len());
+    let (name, rest) = line.split_at(end);
+    let rest = rest.trim();
+    let raw = match rest.strip_prefix('(').and_then(|rest| rest.strip_suffix(')')) {
+        Some(inner) => inner,
+        None => rest.strip_prefix(',').unwrap_or(rest).trim_start(),
+    };
+    let arguments = match raw.is_empty() {
+        true => Vec::new(),
+        false => raw.split(',').map(|argument| unquote(argument.trim()).into()).collect(),
+    };
+    (name, arguments, raw)
+}
+
+/// Strips the quotes around strings of version 2.
+fn unquote(text: &str) -> &str {
+    ['"', '\''].iter().find_map(|quote| text.strip_prefix(*quote).and_then(|text| text.strip_suffix(*quote))).unwrap_or(text)
+}
+
+/// `Loop` repeats infinitely and `Loop 5` five times, all other kinds of loops are unsupported.
+fn loops(arguments: &[String]) -> Option<Loops> {
+    match arguments {
+        [] => Some(Loops::Infinite),
+        [count] => count.parse().ok().map(|count| Loops::Amount(Argument::Literal(count))),
+        _ => None,
+    }
+}
+
+fn command(name: &str, arguments: &[String], raw: &str, importer: &mut Importer) {
+    let number = |argument: Option<&String>| argument.and_then(|argument| argument.parse::<i64>().ok());
+    match name.to_ascii_lowercase().as_str() {
+        "click" => click(&arguments.join(" "), importer),
+        "mouseclick" => {
+            let key = match arguments.first().map(String::as_str).unwrap_or_default() {
+                "" => Some(Key::MouseLeft),
+                name => button(name),
+            };
+            let position = number(arguments.get(1)).zip(number(arguments.get(2)));
+            // pressing or releasing only and relative coordinates have no counterpart
+            match (key, arguments.iter().skip(5).any(|argument| !argument.is_empty())) {
+                (Some(key), false) => importer.click(key, position, times(number(arguments.get(3)).unwrap_or(1))),
+                _ => importer.warn(&format!("'MouseClick {}' is not supported and left out", raw)),
+            }
+        },
+        "mousemove" => match (number(arguments.first()).zip(number(arguments.get(1))), arguments.get(3)) {
+            (Some(position), None) => importer.position = Some(position),
+            _ => importer.warn(&format!("'MouseMove {}' is not supported and left out", raw)),
+        },
+        "sleep" => match number(arguments.first()) {
+            Some(millis) => importer.sleep(millis),
+            None => importer.warn(&format!("the delay '{}' is no number, the sleep is left out", raw)),
+        },
+        "send" | "sendinput" | "sendevent" | "sendplay" => {
+            let keys = match arguments.len() {
+                1 => arguments[0].as_str(),
+                _ => raw,
+            };
+            match keys.strip_prefix("{Raw}").or_else(|| keys.strip_prefix("{Text}")) {
+                Some(text) => importer.type_text(text),
+                None => send(keys, importer),
+            }
+        },
+        "sendraw" | "sendtext" => importer.type_text(arguments.first().map_or(raw, String::as_str)),
+        "return" | "exitapp" => (),
+        setting if SETTINGS.contains(&setting) => (),
+        _ => importer.warn(&format!("'{}' is not supported and left out", name)),
+    }
+}
+
+/// Clicks like `Click`, `Click 100, 200`, `Click right 100 200 2`, a count of 0 only moves the mouse.
+fn click(options: &str, importer: &mut Importer) {
+    let mut key = Key::MouseLeft;
+    let mut numbers = Vec::new();
+    for option in options.split(|c: char| c == ',' || c.is_whitespace()).filter(|option| !option.is_empty()) {
+        match (option.parse::<i64>(), button(option)) {
+            (Ok(number), _) => numbers.push(number),
+            (_, Some(button)) => key = button,
+            _ => return importer.warn(&format!("'Click {}' is not supported and left out", options)),
+        }
+    }
+    let position = match numbers.as_slice() {
+        [x, y, ..] => Some((*x, *y)),
+        _ => None,
+    };
+    match numbers.get(2) {
+        Some(0) => importer.position = position.or(importer.position),
+        count => importer.click(key, position, times(count.copied().unwrap_or(1))),
+    }
+}
+
+fn button(name: &str) -> Option<Key> {
+    match name.to_ascii_lowercase().as_str() {
+        "left" | "l" => Some(Key::MouseLeft),
+        "right" | "r" => Some(Key::MouseRight),
+        "middle" | "m" => Some(Key::MouseMiddle),
+        "x1" => Some(Key::MouseX1),
+        "x2" => Some(Key::MouseX2),
+        _ => None,
+    }
+}
+
+fn times(count: i64) -> Loops {
+    match count {
+        1 => Loops::default(),
+        count => Loops::Amount(Argument::Literal(count)),
+    }
+}
+
+/// Presses the keys of a `Send` like `^s{Enter 2}Hello`, modifiers apply to the key following them.
+fn send(keys: &str, importer: &mut Importer) {
+    let mut modifiers = Vec::new();
+    let mut windows = false;
+    let mut chars = keys.chars();
+    while let Some(c) = chars.next() {
+        let (pressed, count) = match c {
+            '^' => {
+                modifiers.push(Key::Control);
+                continue;
+            },
+            '!' => {
+                modifiers.push(Key::Alt);
+                continue;
+            },
+            '+' => {
+                modifiers.push(Key::Shift);
+                continue;
+            },
+            '#' => {
+                windows = true;
+                continue;
+            },
+            '{' => {
+                let name: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                let (name, count) = match name.rsplit_once(' ') {
+                    Some((name, count)) => (name, count.parse().ok()),
+                    None => (name.as_str(), Some(1)),
+                };
+                let pressed = match name.chars().count() {
+                    1 => name.chars().next().and_then(character),
+                    _ => dsl::key(name).map(|key| (key, vec![])),
+                };
+                match (pressed, count) {
+                    (Some(pressed), Some(count)) => (pressed, count),
+                    _ => {
+                        importer.warn(&format!("the key '{{{}}}' is not supported and left out", name));
+                        modifiers.clear();
+                        continue;
+                    },
+                }
+            },
+            c => match character(c) {
+                Some(pressed) => (pressed, 1),
+                None => {
+                    importer.warn(&format!("'{}' cannot be typed and is left out", c));
+                    modifiers.clear();
+                    continue;
+                },
+            },
+        };
+        let (key, mut implied) = pressed;
+        implied.retain(|modifier| !modifiers.contains(modifier));
+        match mem::take(&mut windows) {
+            true => importer.warn(&format!("the Windows key is not supported, Win+{:?} is left out", key)),
+            false => importer.press(key, modifiers.drain(..).chain(implied).collect(), times(count)),
+        }
+        modifiers.clear();
+    }
+}
+
+/// AutoHotkey v2 scripts, DOWN stops them like it stops replays.
+pub struct Ahk;
+
+impl Target for Ahk {
+
+    fn header(&self) -> Vec<String> {
+        ["; exported from clicker-bot", "#Requires AutoHotkey v2.0", "CoordMode \"Mouse\", \"Screen\"", "SendMode \"Input\"", ""]
+            .iter()
+            .map(|line| String::from(*line))
+            .collect()
+    }
+
+    fn footer(&self) -> Vec<String> {
+        ["ExitApp", "", "Down::ExitApp"].iter().map(|line| String::from(*line)).collect()
+    }
+
+    fn click(&self, key: Key, x: i64, y: i64, count: i64) -> String {
+        let mut options = vec![x.to_string(), y.to_string()];
+        if key != Key::MouseLeft {
+            options.push(format!("\"{}\"", button_name(key)));
+        }
+        if count != 1 {
+            options.push(count.to_string());
+        }
+        format!("Click {}", options.join(", "))
+    }
+
+    /// Repeated combinations hold the modifiers down, like `{Ctrl down}{s 3}{Ctrl up}`.
+    fn press(&self, key: Key, modifiers: &[Key], count: i64) -> String {
+        let keys = match (count, key_name(key)) {
+            (1, name) if name.len() == 1 => name,
+            (1, name) => format!("{{{}}}", name),
+            (count, name) => format!("{{{} {}}}", name, count),
+        };
+        let keys = match count {
+            1 => format!("{}{}", modifiers.iter().map(|modifier| modifier_symbol(*modifier)).collect::<String>(), keys),
+            _ => {
+                let down: String = modifiers.iter().map(|modifier| format!("{{{} down}}", key_name(*modifier))).collect();
+                let up: String = modifiers.iter().rev().map(|modifier| format!("{{{} up}}", key_name(*modifier))).collect();
+                format!("{}{}{}", down, keys, up)
+            },
+        };
+        format!("Send \"{}\"", keys)
+    }
+
+    fn sleep(&self, millis: i64) -> String {
+        format!("Sleep {}", millis)
+    }
+
+    fn open(&self, repeat: Repeat, depth: usize) -> Vec<String> {
+        match repeat {
+            Repeat::Times(count) => vec![format!("Loop {} {{", count)],
+            Repeat::Forever => vec![String::from("Loop {")],
+            Repeat::For(millis) => vec![format!("start{} := A_TickCount", depth), format!("while A_TickCount - start{} < {} {{", depth, millis)],
+        }
+    }
+
+    fn close(&self) -> String {
+        String::from("}")
+    }
+
+    fn empty(&self) -> Option<String> {
+        None
+    }
+
+    fn exit(&self) -> String {
+        String::from("ExitApp")
+    }
+}
+
+fn button_name(key: Key) -> &'static str {
+    match key {
+        Key::MouseRight => "Right",
+        Key::MouseMiddle => "Middle",
+        Key::MouseX1 => "X1",
+        Key::MouseX2 => "X2",
+        _ => "Left",
+    }
+}
+
+/// The names of keys in `Send`, letters are lowercase as capitals would be sent with Shift.
+fn key_name(key: Key) -> String {
+    match key {
+        Key::Control => String::from("Ctrl"),
+        Key::Escape => String::from("Esc"),
+        Key::KeyboardLeft => String::from("Left"),
+        Key::KeyboardUp => String::from("Up"),
+        Key::KeyboardRight => String::from("Right"),
+        Key::KeyboardDown => String::from("Down"),
+        key => match format!("{:?}", key) {
+            letter if letter.len() == 1 => letter.to_ascii_lowercase(),
+            name => name,
+        },
+    }
+}
+
+fn modifier_symbol(key: Key) -> String {
+    match key {
+        Key::Control => String::from("^"),
+        Key::Alt => String::from("!"),
+        Key::Shift => String::from("+"),
+        key => format!("{{{}}}", key_name(key)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::replay::{convert::{Dialect, export, import}, dsl};
+
+    #[test]
+    fn commands() {
+        vec![
+            ("click left 100,200", "Click, 100, 200"),
+            ("click right 100,200 x2", "Click right 100 200 2"),
+            ("click left 10,20\nclick middle 10,20", "MouseMove, 10, 20\nClick\nMouseClick, M"),
+            ("click left 10,20", "Click 10, 20, 0\nClick"),
+            ("click x1 5,6 x3", "MouseClick(\"X1\", 5, 6, 3)"),
+            ("sleep 500ms\nsleep 1s", "Sleep, 500\nSleep(1000)"),
+            ("press Ctrl+S\npress Enter x2\npress Shift+H\npress I", "Send ^s{Enter 2}Hi"),
+            ("press Ctrl+Alt+Shift+A", "SendInput, ^!A"),
+            ("press Shift+A\npress Space\npress B", "SendRaw, A b"),
+            ("repeat 3 {\n    click left 1,2\n    sleep 10ms\n}", "Loop, 3\n{\n    Click 1, 2\n    Sleep 10\n}"),
+            ("repeat forever {\n    repeat 2 {\n        press A\n    }\n}", "Loop {\n    Loop 2\n        Send a\n}"),
+            ("press A\npress B", "^j::\n{\n    Send a ; the first key\n}\n/*\nSend c\n*/\nSend b\nreturn"),
+            ("press B", "#NoEnv\nSendMode Input\nif WinActive(\"Notepad\") {\n    Send a\n} else {\n    Send c\n}\nSend b"),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, (expected, source))| {
+            let converted = import(source, Dialect::Ahk).map(|(commands, _)| commands);
+            assert_eq!(dsl::parse(&format!("version 1\n{}", expected)).map_err(String::from), converted, "case {}", case);
+        });
+    }
+
+    #[test]
+    fn warnings() {
+        vec![
+            (vec!["line 1: the click is left out as the mouse position is unknown, move the mouse first"], "Click"),
+            (vec!["line 2: 'Click 1 2 down' is not supported and left out"], "\nClick 1 2 down"),
+            (vec!["line 1: 'MouseMove 5, 5, 0, R' is not supported and left out"], "MouseMove 5, 5, 0, R"),
+            (vec!["line 1: the Windows key is not supported, Win+R is left out", "line 1: '1' cannot be typed and is left out"], "Send #r1"),
+            (vec!["line 1: the key '{F5}' is not supported and left out"], "Send {F5}"),
+            (vec!["line 1: 'WinActivate' is not supported and left out"], "WinActivate, Notepad"),
+            (vec!["line 1: only loops with a number of iterations are supported, the loop is left out"], "Loop, Files, *.txt\n    Send a"),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, (expected, source))| {
+            assert_eq!(Ok(expected.into_iter().map(String::from).collect()), import(source, Dialect::Ahk).map(|(_, warnings)| warnings), "case {}", case);
+        });
+        vec![
+            ("line 1: '}' without a block", "}"),
+            ("line 2: expected '}' but found the end of the file", "Loop 2 {\nSend a"),
+            ("line 1: the block has no body", "Loop 2"),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, (expected, source))| assert_eq!(Err(String::from(expected)), import(source, Dialect::Ahk).map(|_| ()), "case {}", case));
+    }
+
+    #[test]
+    fn exports() {
+        let commands = dsl::parse("\
+loops 2
+proc save {
+    press Ctrl+S
+}
+click left 100,200
+click right 10,20 x2
+repeat for 1s {
+    press Enter x3
+    press Ctrl+Shift+Z x2
+}
+call save
+set x = 1
+sleep 250ms
+").expect("valid macro");
+        let (script, warnings) = export(&commands, Dialect::Ahk);
+        assert_eq!("\
+; exported from clicker-bot
+#Requires AutoHotkey v2.0
+CoordMode \"Mouse\", \"Screen\"
+SendMode \"Input\"
+
+Loop 2 {
+    Click 100, 200
+    Click 10, 20, \"Right\", 2
+    start1 := A_TickCount
+    while A_TickCount - start1 < 1000 {
+        Send \"{Enter 3}\"
+        Send \"{Ctrl down}{Shift down}{z 2}{Shift up}{Ctrl up}\"
+    }
+    Send \"^s\"
+    Sleep 250
+}
+ExitApp
+
+Down::ExitApp
+", script);
+        assert_eq!(vec!["commands[4].set: the set command is left out as it uses expressions or has no counterpart"], warnings);
+        let flat = dsl::parse("click left 1,2\nclick middle 3,4 x2\npress Alt+F\nsleep 1s\nrepeat 3 {\n    press Shift+B\n}").expect("valid macro");
+        assert_eq!(Ok(flat.commands.clone()), import(&export(&flat, Dialect::Ahk).0, Dialect::Ahk).map(|(commands, _)| commands.commands));
+    }
+}
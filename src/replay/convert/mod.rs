@@ -0,0 +1,278 @@
+//! Conversion between replays and scripts written for other automation tools.
+
+mod ahk;
+mod xdotool;
+
+use std::{path::Path, str::FromStr};
+
+use crate::{expression::Argument, hardware::Key};
+
+use super::{Command, Commands, Format, Loops, diagnostic::Pointer};
+
+/// The languages of the scripts that can be converted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dialect {
+    /// AutoHotkey, the commands of version 1 and the function calls of version 2.
+    Ahk,
+    /// Sequences of xdotool commands, as in shell scripts or files run by `xdotool script`.
+    Xdotool,
+}
+
+impl Dialect {
+
+    /// Detects the dialect by the file extension.
+    pub fn from_path(path: &Path) -> Option<Dialect> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "ahk" | "ah2" => Some(Dialect::Ahk),
+            "xdo" | "sh" => Some(Dialect::Xdotool),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Dialect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ahk" | "autohotkey" => Ok(Dialect::Ahk),
+            "xdotool" | "xdo" => Ok(Dialect::Xdotool),
+            _ => Err(format!("unknown script language '{}', expected ahk or xdotool", s)),
+        }
+    }
+}
+
+/// What can be converted into a replay, a script of another tool or a replay in another format.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Source {
+    Script(Dialect),
+    Replay(Format),
+}
+
+impl Source {
+
+    /// Detects the source by the file extension, script extensions take precedence.
+    pub fn from_path(path: &Path) -> Option<Source> {
+        Dialect::from_path(path).map(Source::Script).or_else(|| Format::from_path(path).map(Source::Replay))
+    }
+}
+
+impl FromStr for Source {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse()
+            .map(Source::Script)
+            .or_else(|_| s.parse().map(Source::Replay))
+            .map_err(|_: String| format!("unknown source '{}', expected ahk, xdotool or a replay format", s))
+    }
+}
+
+/// Converts the script and returns warnings about everything which was left out, each prefixed with its line.
+pub fn import(source: &str, dialect: Dialect) -> Result<(Commands, Vec<String>), String> {
+    let mut importer = Importer::default();
+    match dialect {
+        Dialect::Ahk => ahk::import(source, &mut importer)?,
+        Dialect::Xdotool => xdotool::import(source, &mut importer),
+    }
+    Ok((Commands::from(&mut importer.commands), importer.warnings))
+}
+
+/// Writes the replay as script, the warnings name every command which has no counterpart and was left out.
+pub fn export(commands: &Commands, dialect: Dialect) -> (String, Vec<String>) {
+    let target: &dyn Target = match dialect {
+        Dialect::Ahk => &ahk::Ahk,
+        Dialect::Xdotool => &xdotool::Xdotool,
+    };
+    let mut exporter = Exporter { target, commands, lines: target.header(), warnings: Vec::new(), depth: 0, loops: 0, calls: Vec::new() };
+    let root = Pointer::default();
+    match (&commands.loops, repeat(&commands.loops)) {
+        (Loops::Amount(Argument::Literal(1)), _) => exporter.block(&commands.commands, &root.key("commands")),
+        (_, Some(repeat)) => exporter.repeat(repeat, |exporter| exporter.block(&commands.commands, &root.key("commands"))),
+        (_, None) => exporter.warn(&root.key("loops"), "the amount of loops is no number, the replay is exported without its loop"),
+    }
+    exporter.lines.extend(target.footer());
+    let mut script = exporter.lines.join("\n");
+    script.push('\n');
+    (script, exporter.warnings)
+}
+
+/// How often a loop of an exported script repeats, only literals can be exported.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Repeat {
+    Times(i64),
+    Forever,
+    /// Repeats until the given amount of milliseconds passed.
+    For(i64),
+}
+
+fn repeat(loops: &Loops) -> Option<Repeat> {
+    match loops {
+        Loops::Infinite => Some(Repeat::Forever),
+        Loops::Amount(amount) => amount.literal().map(Repeat::Times),
+        Loops::Duration(millis) => millis.literal().map(Repeat::For),
+    }
+}
+
+/// The syntax of a language replays are exported to.
+trait Target {
+
+    fn header(&self) -> Vec<String>;
+
+    fn footer(&self) -> Vec<String>;
+
+    fn click(&self, key: Key, x: i64, y: i64, count: i64) -> String;
+
+    fn press(&self, key: Key, modifiers: &[Key], count: i64) -> String;
+
+    fn sleep(&self, millis: i64) -> String;
+
+    /// The lines opening a loop, `depth` tells the variables of nested loops apart.
+    fn open(&self, repeat: Repeat, depth: usize) -> Vec<String>;
+
+    fn close(&self) -> String;
+
+    /// The statement of blocks without any, if the language requires one.
+    fn empty(&self) -> Option<String>;
+
+    fn exit(&self) -> String;
+}
+
+struct Exporter<'a> {
+    target: &'a dyn Target,
+    commands: &'a Commands,
+    lines: Vec<String>,
+    warnings: Vec<String>,
+    depth: usize,
+    /// The loops the current command is nested in, `break` and `continue` outside of them end the replay.
+    loops: usize,
+    /// The procedures being inlined, to detect recursion.
+    calls: Vec<&'a str>,
+}
+
+impl<'a> Exporter<'a> {
+
+    fn warn(&mut self, pointer: &Pointer, message: &str) {
+        self.warnings.push(format!("{}: {}", pointer, message));
+    }
+
+    fn line(&mut self, line: String) {
+        self.lines.push(format!("{}{}", "    ".repeat(self.depth), line));
+    }
+
+    fn repeat<F: FnOnce(&mut Self)>(&mut self, repeat: Repeat, body: F) {
+        for line in self.target.open(repeat, self.depth) {
+            self.line(line);
+        }
+        let before = self.lines.len();
+        self.depth += 1;
+        self.loops += 1;
+        body(self);
+        if self.lines.len() == before {
+            if let Some(empty) = self.target.empty() {
+                self.line(empty);
+            }
+        }
+        self.loops -= 1;
+        self.depth -= 1;
+        let close = self.target.close();
+        self.line(close);
+    }
+
+    fn block(&mut self, commands: &'a [Command], pointer: &Pointer) {
+        for (i, command) in commands.iter().enumerate() {
+            self.command(command, &pointer.index(i).key(command.name()));
+        }
+    }
+
+    fn command(&mut self, command: &'a Command, pointer: &Pointer) {
+        let target = self.target;
+        match command {
+            Command::MouseCommand { key, loops, x: Argument::Literal(x), y: Argument::Literal(y) } => match repeat(loops) {
+                Some(Repeat::Times(count)) => self.line(target.click(*key, *x, *y, count)),
+                Some(repeat) => self.repeat(repeat, |exporter| exporter.line(target.click(*key, *x, *y, 1))),
+                None => self.warn(pointer, "the amount of loops is no number, the click is left out"),
+            },
+            Command::KeyboardCommand { key, modifiers, loops } => match repeat(loops) {
+                Some(Repeat::Times(count)) => self.line(target.press(*key, modifiers, count)),
+                Some(repeat) => self.repeat(repeat, |exporter| exporter.line(target.press(*key, modifiers, 1))),
+                None => self.warn(pointer, "the amount of loops is no number, the key press is left out"),
+            },
+            Command::SleepCommand { millis: Argument::Literal(millis) } => self.line(target.sleep(*millis)),
+            Command::RepeatCommand { loops, commands, .. } => match repeat(loops) {
+                Some(repeat) => self.repeat(repeat, |exporter| exporter.block(commands, &pointer.key("commands"))),
+                None => self.warn(pointer, "the amount of loops is no number, the block is left out"),
+            },
+            Command::BreakCommand | Command::ContinueCommand if self.loops == 0 => self.line(target.exit()),
+            Command::BreakCommand => self.line(String::from("break")),
+            Command::ContinueCommand => self.line(String::from("continue")),
+            Command::CallCommand { name, arguments } if arguments.is_empty() => match self.commands.procedures.get(name) {
+                Some(_) if self.calls.contains(&name.as_str()) => self.warn(pointer, &format!("the recursive call of '{}' is left out", name)),
+                Some(procedure) => {
+                    self.calls.push(name);
+                    self.block(&procedure.commands, &Pointer::default().key("procedures").key(name).key("commands"));
+                    self.calls.pop();
+                },
+                None => self.warn(pointer, &format!("the procedure '{}' does not exist, the call is left out", name)),
+            },
+            Command::LabelCommand { .. } => (),
+            command => self.warn(pointer, &format!("the {} command is left out as it uses expressions or has no counterpart", command.name())),
+        }
+    }
+}
+
+/// The commands converted so far, clicks without coordinates happen at the last position the mouse was moved to.
+#[derive(Default)]
+struct Importer {
+    commands: Vec<Command>,
+    position: Option<(i64, i64)>,
+    line: usize,
+    warnings: Vec<String>,
+}
+
+impl Importer {
+
+    fn warn(&mut self, message: &str) {
+        self.warnings.push(format!("line {}: {}", self.line, message));
+    }
+
+    fn click(&mut self, key: Key, position: Option<(i64, i64)>, loops: Loops) {
+        if position.is_some() {
+            self.position = position;
+        }
+        match self.position {
+            Some((x, y)) => self.commands.push(Command::MouseCommand { key, loops, x: Argument::Literal(x), y: Argument::Literal(y) }),
+            None => self.warn("the click is left out as the mouse position is unknown, move the mouse first"),
+        }
+    }
+
+    fn press(&mut self, key: Key, modifiers: Vec<Key>, loops: Loops) {
+        self.commands.push(Command::KeyboardCommand { key, modifiers, loops });
+    }
+
+    fn sleep(&mut self, millis: i64) {
+        self.commands.push(Command::SleepCommand { millis: Argument::Literal(millis) });
+    }
+
+    /// Types the text key by key, characters without a key are left out.
+    fn type_text(&mut self, text: &str) {
+        for c in text.chars() {
+            match character(c) {
+                Some((key, modifiers)) => self.press(key, modifiers, Loops::default()),
+                None => self.warn(&format!("'{}' cannot be typed and is left out", c)),
+            }
+        }
+    }
+}
+
+/// The key and modifiers typing the character, capital letters are typed with Shift.
+fn character(c: char) -> Option<(Key, Vec<Key>)> {
+    match c {
+        ' ' => Some((Key::Space, vec![])),
+        '\n' => Some((Key::Enter, vec![])),
+        '\t' => Some((Key::Tab, vec![])),
+        c if c.is_ascii_lowercase() => c.to_ascii_uppercase().to_string().parse().ok().map(|key| (key, vec![])),
+        c if c.is_ascii_uppercase() => c.to_string().parse().ok().map(|key| (key, vec![Key::Shift])),
+        _ => None,
+    }
+}
@@ -0,0 +1,329 @@
+use crate::{expression::Argument, hardware::Key, replay::{Command, Loops, dsl}};
+
+use super::{Importer, Repeat, Target, character};
+
+/// The commands of xdotool, arguments up to the next one of them belong to the command before.
+const COMMANDS: [&str; 24] = [
+    "click", "getactivewindow", "getmouselocation", "getwindowfocus", "key", "keydown", "keyup", "mousedown", "mousemove",
+    "mousemove_relative", "mouseup", "search", "set_desktop", "set_window", "sleep", "type", "windowactivate", "windowclose",
+    "windowfocus", "windowkill", "windowmap", "windowminimize", "windowmove", "windowsize",
+];
+
+/// Options which are followed by a value.
+const VALUED: [&str; 6] = ["--delay", "--repeat", "--window", "--screen", "--terminator", "--file"];
+
+/// Converts lines of xdotool commands, with or without a leading `xdotool` as in shell scripts or `xdotool script` files.
+pub fn import(source: &str, importer: &mut Importer) {
+    for (i, line) in source.lines().enumerate() {
+        importer.line = i + 1;
+        for statement in words(line).split(|word| word == ";" || word == "&&") {
+            // environment assignments like DISPLAY=:0 only matter to the shell
+            let start = statement.iter().position(|word| !word.contains('=')).unwrap_or(statement.len());
+            let statement = match statement.get(start).map(String::as_str) {
+                Some("xdotool") => &statement[start + 1..],
+                _ => &statement[start..],
+            };
+            chain(statement, importer);
+        }
+    }
+}
+
+/// Splits the line like a shell, a `#` starting a word comments out the rest of the line.
+fn words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(open), c) if c == open => quote = None,
+            (Some('"'), '\\') | (None, '\\') => word.get_or_insert_with(String::new).extend(chars.next()),
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '"') | (None, '\'') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            },
+            (None, '#') if word.is_none() => break,
+            (None, ';') => {
+                words.extend(word.take());
+                words.push(String::from(";"));
+            },
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    words
+}
+
+fn chain(mut words: &[String], importer: &mut Importer) {
+    while let Some((name, rest)) = words.split_first() {
+        if !COMMANDS.contains(&name.as_str()) {
+            importer.warn(&format!("'{}' is no xdotool command, the rest of the line is left out", name));
+            return;
+        }
+        let end = rest.iter().position(|word| COMMANDS.contains(&word.as_str())).unwrap_or(rest.len());
+        let (arguments, next) = rest.split_at(end);
+        command(name, arguments, importer);
+        words = next;
+    }
+}
+
+/// Separates the options from the positional arguments, which may follow `--` to allow negative numbers.
+fn options(arguments: &[String]) -> (Vec<(&str, Option<&str>)>, Vec<&str>) {
+    let mut options = Vec::new();
+    let mut positional = Vec::new();
+    let mut arguments = arguments.iter().map(String::as_str);
+    while let Some(argument) = arguments.next() {
+        match argument {
+            "--" => positional.extend(arguments.by_ref()),
+            option if VALUED.contains(&option) => options.push((option, arguments.next())),
+            option if option.starts_with("--") => options.push((option, None)),
+            argument => positional.push(argument),
+        }
+    }
+    (options, positional)
+}
+
+fn command(name: &str, arguments: &[String], importer: &mut Importer) {
+    let (options, positional) = options(arguments);
+    let option = |name: &str| options.iter().find(|(option, _)| *option == name).map(|(_, value)| value.and_then(|value| value.parse::<i64>().ok()));
+    let unsupported = options.iter().find(|(option, _)| ["--window", "--screen", "--polar"].contains(option));
+    if let Some((option, _)) = unsupported {
+        return importer.warn(&format!("'{} {}' is not supported and left out", name, option));
+    }
+    match (name, positional.as_slice()) {
+        ("mousemove", [x, y]) => match (x.parse(), y.parse()) {
+            (Ok(x), Ok(y)) => importer.position = Some((x, y)),
+            _ => importer.warn(&format!("'mousemove {} {}' has no valid coordinates and is left out", x, y)),
+        },
+        ("click", [button]) => match self::button(button) {
+            Some(key) => match option("--repeat").flatten().unwrap_or(1) {
+                1 => importer.click(key, None, Loops::default()),
+                repeat => {
+                    // xdotool waits 100 milliseconds between the clicks by default
+                    let outer = std::mem::take(&mut importer.commands);
+                    importer.click(key, None, Loops::default());
+                    importer.sleep(option("--delay").flatten().unwrap_or(100));
+                    let commands = std::mem::replace(&mut importer.commands, outer);
+                    if !commands.is_empty() {
+                        importer.commands.push(Command::RepeatCommand { loops: Loops::Amount(Argument::Literal(repeat)), counter: None, commands });
+                    }
+                },
+            },
+            None => importer.warn(&format!("the mouse button '{}' is not supported and left out", button)),
+        },
+        ("key", keys) => keys.iter().for_each(|keys| key(keys, importer)),
+        ("type", texts) => texts.iter().for_each(|text| importer.type_text(text)),
+        ("sleep", [seconds]) => match seconds.parse::<f64>() {
+            Ok(seconds) if seconds >= 0.0 => importer.sleep((seconds * 1000.0).round() as i64),
+            _ => importer.warn(&format!("the delay '{}' is no number of seconds, the sleep is left out", seconds)),
+        },
+        (name, _) => importer.warn(&format!("'{}' is not supported and left out", std::iter::once(name).chain(arguments.iter().map(String::as_str)).collect::<Vec<_>>().join(" "))),
+    }
+}
+
+fn button(number: &str) -> Option<Key> {
+    match number {
+        "1" => Some(Key::MouseLeft),
+        "2" => Some(Key::MouseMiddle),
+        "3" => Some(Key::MouseRight),
+        "8" => Some(Key::MouseX1),
+        "9" => Some(Key::MouseX2),
+        _ => None,
+    }
+}
+
+/// Presses a combination of keysyms like `ctrl+shift+t` or `Return`.
+fn key(keys: &str, importer: &mut Importer) {
+    let names: Vec<&str> = keys.split('+').collect();
+    let (name, modifiers) = names.split_last().unwrap_or((&"", &[]));
+    let modifiers: Option<Vec<Key>> = modifiers
+        .iter()
+        .map(|name| dsl::key(name).filter(|key| matches!(key, Key::Control | Key::Alt | Key::Shift)))
+        .collect();
+    let pressed = match name.chars().count() {
+        1 => name.chars().next().and_then(character),
+        _ => dsl::key(name).filter(|key| !key.is_mouse()).map(|key| (key, vec![])),
+    };
+    match (pressed, modifiers) {
+        (Some((key, implied)), Some(mut modifiers)) => {
+            modifiers.extend(implied.into_iter().filter(|modifier| !modifiers.contains(modifier)).collect::<Vec<_>>());
+            importer.press(key, modifiers, Loops::default());
+        },
+        _ => importer.warn(&format!("the key '{}' is not supported and left out", keys)),
+    }
+}
+
+/// Shell scripts running xdotool, loops with a duration need GNU date.
+pub struct Xdotool;
+
+impl Target for Xdotool {
+
+    fn header(&self) -> Vec<String> {
+        vec![String::from("#!/bin/sh"), String::from("# exported from clicker-bot"), String::new()]
+    }
+
+    fn footer(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    fn click(&self, key: Key, x: i64, y: i64, count: i64) -> String {
+        format!("xdotool mousemove {} {} click{} {}", x, y, repetitions(count), button_number(key))
+    }
+
+    fn press(&self, key: Key, modifiers: &[Key], count: i64) -> String {
+        let keys: Vec<_> = modifiers.iter().chain(std::iter::once(&key)).map(|key| keysym(*key)).collect();
+        format!("xdotool key{} {}", repetitions(count), keys.join("+"))
+    }
+
+    fn sleep(&self, millis: i64) -> String {
+        let seconds = format!("{}.{:03}", millis / 1000, millis % 1000);
+        format!("sleep {}", seconds.trim_end_matches('0').trim_end_matches('.'))
+    }
+
+    fn open(&self, repeat: Repeat, depth: usize) -> Vec<String> {
+        match repeat {
+            Repeat::Times(count) => vec![format!("for _ in $(seq {}); do", count)],
+            Repeat::Forever => vec![String::from("while true; do")],
+            Repeat::For(millis) => vec![
+                format!("end{}=$(($(date +%s%3N) + {}))", depth, millis),
+                format!("while [ \"$(date +%s%3N)\" -lt \"$end{}\" ]; do", depth),
+            ],
+        }
+    }
+
+    fn close(&self) -> String {
+        String::from("done")
+    }
+
+    fn empty(&self) -> Option<String> {
+        Some(String::from(":"))
+    }
+
+    fn exit(&self) -> String {
+        String::from("exit 0")
+    }
+}
+
+/// Replays wait a millisecond between repetitions.
+fn repetitions(count: i64) -> String {
+    match count {
+        1 => String::new(),
+        count => format!(" --repeat {} --delay 1", count),
+    }
+}
+
+fn button_number(key: Key) -> u8 {
+    match key {
+        Key::MouseMiddle => 2,
+        Key::MouseRight => 3,
+        Key::MouseX1 => 8,
+        Key::MouseX2 => 9,
+        _ => 1,
+    }
+}
+
+fn keysym(key: Key) -> String {
+    match key {
+        Key::Control => String::from("ctrl"),
+        Key::Alt => String::from("alt"),
+        Key::Shift => String::from("shift"),
+        Key::Enter => String::from("Return"),
+        Key::Space => String::from("space"),
+        Key::Backspace => String::from("BackSpace"),
+        Key::KeyboardLeft => String::from("Left"),
+        Key::KeyboardUp => String::from("Up"),
+        Key::KeyboardRight => String::from("Right"),
+        Key::KeyboardDown => String::from("Down"),
+        key => match format!("{:?}", key) {
+            letter if letter.len() == 1 => letter.to_ascii_lowercase(),
+            name => name,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::replay::{convert::{Dialect, export, import}, dsl};
+
+    #[test]
+    fn commands() {
+        vec![
+            ("click left 100,200", "xdotool mousemove 100 200 click 1"),
+            ("click right 100,200\nclick middle 100,200", "xdotool mousemove --sync 100 200\nxdotool click 3 click 2"),
+            ("repeat 3 {\n    click left 1,2\n    sleep 100ms\n}", "mousemove 1 2\nclick --repeat 3 1"),
+            ("repeat 2 {\n    click x1 1,2\n    sleep 5ms\n}", "mousemove 1 2 click --repeat 2 --delay 5 8"),
+            ("press Ctrl+S\npress Enter\npress Shift+T", "xdotool key ctrl+s Return T"),
+            ("press Ctrl+Shift+T", "xdotool key --clearmodifiers ctrl+T"),
+            ("press H\npress I\npress Space\npress Shift+Y", "xdotool type 'hi Y'"),
+            ("sleep 500ms\nsleep 2s", "sleep 0.5 # shell sleep\nxdotool sleep 2"),
+            ("press A\nsleep 1s\npress B", "#!/bin/sh\nDISPLAY=:0 xdotool key a; sleep 1 && xdotool key b"),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, (expected, source))| {
+            let converted = import(source, Dialect::Xdotool).map(|(commands, _)| commands);
+            assert_eq!(dsl::parse(&format!("version 1\n{}", expected)).map_err(String::from), converted, "case {}", case);
+        });
+    }
+
+    #[test]
+    fn warnings() {
+        vec![
+            (vec!["line 1: the click is left out as the mouse position is unknown, move the mouse first"], "xdotool click 1"),
+            (vec!["line 2: 'mousemove_relative -- -5 5' is not supported and left out"], "\nxdotool mousemove_relative -- -5 5"),
+            (vec!["line 1: 'search --name Editor' is not supported and left out", "line 1: 'windowactivate' is not supported and left out"], "xdotool search --name 'Editor' windowactivate"),
+            (vec!["line 1: 'mousemove --window' is not supported and left out"], "xdotool mousemove --window 123 1 2"),
+            (vec!["line 1: the mouse button '4' is not supported and left out"], "xdotool mousemove 1 2 click 4"),
+            (vec!["line 1: the key 'super+l' is not supported and left out", "line 1: '!' cannot be typed and is left out"], "xdotool key super+l type a!"),
+            (vec!["line 1: 'echo' is no xdotool command, the rest of the line is left out"], "echo done"),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, (expected, source))| {
+            assert_eq!(Ok(expected.into_iter().map(String::from).collect()), import(source, Dialect::Xdotool).map(|(_, warnings)| warnings), "case {}", case);
+        });
+    }
+
+    #[test]
+    fn exports() {
+        let commands = dsl::parse("\
+click left 100,200
+click right 10,20 x2
+repeat forever {
+    repeat for 1500ms {
+    }
+    press Enter x3
+    press Ctrl+Shift+Z
+    sleep 10ms
+    break
+}
+goto end
+label end
+break
+").expect("valid macro");
+        let (script, warnings) = export(&commands, Dialect::Xdotool);
+        assert_eq!("\
+#!/bin/sh
+# exported from clicker-bot
+
+xdotool mousemove 100 200 click 1
+xdotool mousemove 10 20 click --repeat 2 --delay 1 3
+while true; do
+    end1=$(($(date +%s%3N) + 1500))
+    while [ \"$(date +%s%3N)\" -lt \"$end1\" ]; do
+        :
+    done
+    xdotool key --repeat 3 --delay 1 Return
+    xdotool key ctrl+shift+z
+    sleep 0.01
+    break
+done
+exit 0
+", script);
+        assert_eq!(vec!["commands[3].goto: the goto command is left out as it uses expressions or has no counterpart"], warnings);
+        let flat = dsl::parse("click left 1,2\nclick x2 3,4\npress Alt+F\npress Shift+B\nsleep 1250ms").expect("valid macro");
+        assert_eq!(Ok(flat.commands.clone()), import(&export(&flat, Dialect::Xdotool).0, Dialect::Xdotool).map(|(commands, _)| commands.commands));
+    }
+}
@@ -0,0 +1,584 @@
+use std::{collections::HashMap, fmt::{self, Display}, fs, path::{Path, PathBuf}};
+
+use schemars::schema_for;
+use serde_json::Value;
+use yaml_rust::{parser::{Event, MarkedEventReceiver, Parser}, scanner::Marker};
+
+use super::{Commands, Format, lint, migration};
+
+/// The position of a value in a replay document, e.g. `commands[2].mouse`.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Pointer(Vec<Segment>);
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+impl Pointer {
+
+    pub fn key(&self, key: &str) -> Self {
+        let mut pointer = self.clone();
+        pointer.0.push(Segment::Key(key.into()));
+        pointer
+    }
+
+    pub fn index(&self, index: usize) -> Self {
+        let mut pointer = self.clone();
+        pointer.0.push(Segment::Index(index));
+        pointer
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn parent(&self) -> Option<Self> {
+        let (_, parent) = self.0.split_last()?;
+        Some(Self(parent.to_vec()))
+    }
+}
+
+impl Display for Pointer {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, segment) in self.0.iter().enumerate() {
+            match segment {
+                Segment::Key(key) if i == 0 => write!(f, "{}", key)?,
+                Segment::Key(key) => write!(f, ".{}", key)?,
+                Segment::Index(index) => write!(f, "[{}]", index)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A line and column in the source, both starting at 1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Location {
+
+    pub fn at_offset(source: &str, offset: usize) -> Self {
+        let before = source.get(..offset).unwrap_or(source);
+        Self {
+            line: before.matches('\n').count() + 1,
+            column: before.rsplit('\n').next().unwrap_or_default().chars().count() + 1,
+        }
+    }
+}
+
+/// A replay which could not be read, located if the format tells where.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub location: Option<Location>,
+}
+
+impl ParseError {
+
+    pub fn new(message: &str, location: Option<Location>) -> Self {
+        Self { message: message.into(), location }
+    }
+}
+
+impl Display for ParseError {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.location {
+            Some(Location { line, column }) => write!(f, "{}:{}: {}", line, column, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl From<ParseError> for String {
+
+    fn from(error: ParseError) -> Self {
+        error.to_string()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub pointer: Pointer,
+    pub location: Option<Location>,
+}
+
+/// All errors and warnings of a replay file.
+pub struct Diagnostics {
+    file: PathBuf,
+    source: String,
+    items: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+
+    /// Checks the file, included files are only read as far as needed to validate this one.
+    pub fn check(file: &Path, format: Format) -> Result<Self, String> {
+        let content = fs::read(file).map_err(|e| format!("failed to open '{}' because '{:?}'", file.display(), e.kind()))?;
+        // binary replays have no lines to show
+        let source = match format {
+            Format::Binary => String::new(),
+            _ => String::from_utf8_lossy(&content).into_owned(),
+        };
+        let mut items = diagnose(file, &content, &source, format);
+        locate(&mut items, &source, format);
+        Ok(Self { file: file.into(), source, items })
+    }
+
+    /// Checks the file and adds warnings for suspicious constructs once it has no errors, see `lint`.
+    pub fn lint(file: &Path, format: Format, screen: Option<(u32, u32)>) -> Result<Self, String> {
+        let mut diagnostics = Self::check(file, format)?;
+        if diagnostics.is_ok() {
+            let mut items: Vec<_> = lint::lint(&Commands::load(file, format)?, screen)
+                .into_iter()
+                .map(|(pointer, message)| Diagnostic { severity: Severity::Warning, message, pointer, location: None })
+                .collect();
+            locate(&mut items, &diagnostics.source, format);
+            diagnostics.items.extend(items);
+        }
+        Ok(diagnostics)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.items.iter()
+    }
+
+    pub fn errors(&self) -> usize {
+        self.items.iter().filter(|item| item.severity == Severity::Error).count()
+    }
+
+    pub fn warnings(&self) -> usize {
+        self.items.len() - self.errors()
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.errors() == 0
+    }
+
+    pub fn render(&self) -> String {
+        self.items.iter().map(|item| self.render_item(item)).collect::<Vec<_>>().join("\n")
+    }
+
+    /// Renders the diagnostic like rustc with the source line below the message.
+    pub fn render_item(&self, diagnostic: &Diagnostic) -> String {
+        let mut text = format!("{}: {}\n", diagnostic.severity, diagnostic.message);
+        let mut padding = String::from(" ");
+        match diagnostic.location {
+            Some(Location { line, column }) => {
+                let number = line.to_string();
+                padding = " ".repeat(number.len() + 1);
+                let source = self.source.lines().nth(line - 1).unwrap_or_default();
+                // tabs are kept so the marker lines up however they are displayed
+                let indent: String = source.chars().take(column - 1).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+                text += &format!("{}--> {}:{}:{}\n", &padding[1..], self.file.display(), line, column);
+                text += &format!("{}|\n{} | {}\n{}| {}^\n", padding, number, source, padding, indent);
+            },
+            None => text += &format!("--> {}\n", self.file.display()),
+        }
+        if !diagnostic.pointer.is_root() {
+            text += &format!("{}= at {}\n", padding, diagnostic.pointer);
+        }
+        text
+    }
+}
+
+fn diagnose(file: &Path, content: &[u8], source: &str, format: Format) -> Vec<Diagnostic> {
+    let diagnostic = |severity, message: String, pointer: Pointer| Diagnostic { severity, message, pointer, location: None };
+    let schema = serde_json::to_value(schema_for!(Commands)).unwrap_or_default();
+    let document = match format.document(source) {
+        Some(Ok(mut document)) => migration::upgrade_document(&mut document).ok().map(|_| document),
+        _ => None,
+    };
+    let mut items = Vec::new();
+    if let Some(document) = &document {
+        let mut unknown = Vec::new();
+        unknown_fields(document, &schema, &schema["definitions"], Pointer::default(), &mut unknown);
+        items.extend(unknown.into_iter().map(|(pointer, message)| diagnostic(Severity::Warning, message, pointer)));
+    }
+    let upgraded = migration::upgrade(content, format);
+    if let Some(version) = upgraded.as_ref().ok().map(|(_, version)| *version).filter(|version| *version < migration::VERSION) {
+        let message = format!("the replay was written in version {} of the replay format, upgrade it with the migrate subcommand", version);
+        items.push(diagnostic(Severity::Warning, message, Pointer::default()));
+    }
+    if let Err(e) = upgraded {
+        // serde stops at the first error, the schema tells about all of them
+        let mut mismatches = Vec::new();
+        if let Some(document) = &document {
+            structure(document, &schema, &schema["definitions"], Pointer::default(), &mut mismatches);
+        }
+        match mismatches.is_empty() {
+            true => items.push(Diagnostic { location: e.location, ..diagnostic(Severity::Error, e.message, Pointer::default()) }),
+            false => items.extend(mismatches.into_iter().map(|(pointer, message)| diagnostic(Severity::Error, message, pointer))),
+        }
+        return items;
+    }
+    match Commands::load(file, format) {
+        Ok(commands) => items.extend(commands.problems().into_iter().map(|(pointer, message)| diagnostic(Severity::Error, message, pointer))),
+        Err(e) => items.push(diagnostic(Severity::Error, e, Pointer::default())),
+    }
+    items
+}
+
+/// Locates the diagnostics at their pointer or the closest parent found in the source.
+fn locate(items: &mut [Diagnostic], source: &str, format: Format) {
+    let index = index(source, format);
+    for item in items {
+        let mut pointer = Some(item.pointer.clone()).filter(|pointer| !pointer.is_root());
+        while let Some(current) = pointer {
+            item.location = index.get(&current).copied();
+            pointer = current.parent().filter(|parent| item.location.is_none() && !parent.is_root());
+        }
+    }
+}
+
+/// Follows references and the `allOf` wrappers schemars puts around references with defaults.
+fn resolve<'a>(schema: &'a Value, definitions: &'a Value) -> &'a Value {
+    if let Some(name) = schema.get("$ref").and_then(Value::as_str).and_then(|reference| reference.strip_prefix("#/definitions/")) {
+        return resolve(&definitions[name], definitions);
+    }
+    match schema.get("allOf").and_then(Value::as_array) {
+        Some(all) if all.len() == 1 => resolve(&all[0], definitions),
+        _ => schema,
+    }
+}
+
+/// Collects the fields serde would silently ignore, which are most likely typos.
+fn unknown_fields(value: &Value, schema: &Value, definitions: &Value, pointer: Pointer, unknown: &mut Vec<(Pointer, String)>) {
+    let schema = resolve(schema, definitions);
+    let map = match value {
+        Value::Object(map) => map,
+        Value::Array(items) => {
+            if let Some(item_schema) = schema.get("items") {
+                items.iter().enumerate().for_each(|(i, item)| unknown_fields(item, item_schema, definitions, pointer.index(i), unknown));
+            }
+            return;
+        },
+        _ => return,
+    };
+    if let Some(variants) = schema.get("oneOf").or_else(|| schema.get("anyOf")).and_then(Value::as_array) {
+        let fits = |variant: &Value| variant.get("required").and_then(Value::as_array).is_some_and(|required| required.iter().all(|key| key.as_str().is_some_and(|key| map.contains_key(key))));
+        if let Some(variant) = variants.iter().map(|variant| resolve(variant, definitions)).find(|variant| fits(variant)) {
+            unknown_fields(value, variant, definitions, pointer, unknown);
+        }
+        return;
+    }
+    let properties = schema.get("properties").and_then(Value::as_object);
+    let additional = schema.get("additionalProperties").filter(|additional| additional.is_object());
+    for (key, item) in map {
+        match (properties.and_then(|properties| properties.get(key)), additional, properties) {
+            (Some(property), _, _) | (None, Some(property), _) => unknown_fields(item, property, definitions, pointer.key(key), unknown),
+            (None, None, Some(properties)) => {
+                let expected = properties.keys().map(|key| format!("'{}'", key)).collect::<Vec<_>>().join(", ");
+                unknown.push((pointer.key(key), format!("unknown field '{}' is ignored, expected one of {}", key, expected)));
+            },
+            _ => (),
+        }
+    }
+}
+
+/// Collects where the document does not fit the types, tags and values of the schema.
+fn structure(value: &Value, schema: &Value, definitions: &Value, pointer: Pointer, mismatches: &mut Vec<(Pointer, String)>) {
+    let schema = resolve(schema, definitions);
+    if let Some(variants) = schema.get("oneOf").or_else(|| schema.get("anyOf")).and_then(Value::as_array) {
+        let variants: Vec<_> = variants.iter().map(|variant| resolve(variant, definitions)).collect();
+        let check = |variant: &Value| {
+            let mut found = Vec::new();
+            structure(value, variant, definitions, pointer.clone(), &mut found);
+            found
+        };
+        if variants.iter().any(|variant| check(variant).is_empty()) {
+            return;
+        }
+        let tagged = |variant: &&&Value| variant
+            .get("required")
+            .and_then(Value::as_array)
+            .is_some_and(|required| value.as_object().is_some_and(|map| required.iter().all(|key| key.as_str().is_some_and(|key| map.contains_key(key)))));
+        match variants.iter().find(tagged) {
+            Some(variant) => mismatches.extend(check(variant)),
+            None if variants.iter().all(|variant| variant.get("enum").is_none() && variant.get("required").is_none()) => {
+                let kinds = variants.iter().flat_map(|variant| expected(variant)).collect::<Vec<_>>().join(" or ");
+                mismatches.push((pointer, format!("expected {} but found {}", kinds, describe(value))));
+            },
+            None => {
+                let expected = variants.iter().flat_map(|variant| expected(variant)).collect::<Vec<_>>().join(", ");
+                mismatches.push((pointer, format!("{} is not valid here, expected one of {}", describe(value), expected)));
+            },
+        }
+        return;
+    }
+    if let Some(types) = schema.get("type") {
+        let types: Vec<&str> = match types {
+            Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+            other => other.as_str().into_iter().collect(),
+        };
+        if !types.iter().any(|kind| is_type(value, kind, schema.get("format").and_then(Value::as_str))) {
+            let kinds = types.iter().map(|kind| article(kind)).collect::<Vec<_>>().join(" or ");
+            return mismatches.push((pointer, format!("expected {} but found {}", kinds, describe(value))));
+        }
+    }
+    if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+        if !values.contains(value) {
+            let expected = values.iter().map(|value| value.to_string().replace('"', "'")).collect::<Vec<_>>().join(", ");
+            return mismatches.push((pointer, format!("{} is not valid here, expected one of {}", describe(value), expected)));
+        }
+    }
+    if let Some(minimum) = schema.get("minimum").and_then(Value::as_f64) {
+        if value.as_f64().is_some_and(|n| n < minimum) {
+            return mismatches.push((pointer, format!("{} is below the minimum of {}", value, minimum)));
+        }
+    }
+    match value {
+        Value::Array(items) => if let Some(item_schema) = schema.get("items") {
+            items.iter().enumerate().for_each(|(i, item)| structure(item, item_schema, definitions, pointer.index(i), mismatches));
+        },
+        Value::Object(map) => {
+            let required = schema.get("required").and_then(Value::as_array).into_iter().flatten().filter_map(Value::as_str);
+            for key in required.filter(|key| !map.contains_key(*key)) {
+                mismatches.push((pointer.clone(), format!("missing field '{}'", key)));
+            }
+            let properties = schema.get("properties").and_then(Value::as_object);
+            let additional = schema.get("additionalProperties").filter(|additional| additional.is_object());
+            for (key, item) in map {
+                if let Some(property) = properties.and_then(|properties| properties.get(key)).or(additional) {
+                    structure(item, property, definitions, pointer.key(key), mismatches);
+                }
+            }
+        },
+        _ => (),
+    }
+}
+
+/// What a variant of a `oneOf` accepts, its values, its tag or its type.
+fn expected(variant: &Value) -> Vec<String> {
+    if let Some(values) = variant.get("enum").and_then(Value::as_array) {
+        return values.iter().map(|value| value.to_string().replace('"', "'")).collect();
+    }
+    match variant.get("required").and_then(Value::as_array) {
+        Some(required) if variant.get("additionalProperties") == Some(&Value::Bool(false)) && required.len() == 1 => {
+            required.iter().filter_map(Value::as_str).map(|tag| format!("'{}'", tag)).collect()
+        },
+        _ => variant.get("type").and_then(Value::as_str).map(article).into_iter().collect(),
+    }
+}
+
+fn is_type(value: &Value, kind: &str, format: Option<&str>) -> bool {
+    match kind {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "string" => value.is_string(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        "number" => value.is_number(),
+        "integer" => match format {
+            Some("uint8") => value.as_u64().is_some_and(|n| n <= u8::MAX as u64),
+            Some("uint16") => value.as_u64().is_some_and(|n| n <= u16::MAX as u64),
+            Some("uint32") => value.as_u64().is_some_and(|n| n <= u32::MAX as u64),
+            _ => value.is_i64() || value.is_u64(),
+        },
+        _ => true,
+    }
+}
+
+fn article(kind: &str) -> String {
+    match kind {
+        "integer" | "array" | "object" => format!("an {}", kind.replace("array", "list").replace("object", "map")),
+        "null" => String::from("nothing"),
+        other => format!("a {}", other),
+    }
+}
+
+fn describe(value: &Value) -> String {
+    match value {
+        Value::Null => String::from("nothing"),
+        Value::Bool(b) => format!("the boolean {}", b),
+        Value::Number(n) => format!("the number {}", n),
+        Value::String(s) => format!("'{}'", s),
+        Value::Array(_) => String::from("a list"),
+        Value::Object(map) => match map.keys().next() {
+            Some(key) if map.len() == 1 => format!("'{}'", key),
+            _ => String::from("a map"),
+        },
+    }
+}
+
+struct Events(Vec<(Event, Marker)>);
+
+impl MarkedEventReceiver for Events {
+
+    fn on_event(&mut self, event: Event, marker: Marker) {
+        self.0.push((event, marker));
+    }
+}
+
+/// Maps the pointers of a YAML or JSON document to their location, mappings are located at their first key.
+fn index(source: &str, format: Format) -> HashMap<Pointer, Location> {
+    let mut index = HashMap::new();
+    if !matches!(format, Format::Yaml | Format::Json) {
+        return index;
+    }
+    let mut events = Events(Vec::new());
+    if Parser::new(source.chars()).load(&mut events, false).is_ok() {
+        let start = events.0.iter().position(|(event, _)| matches!(event, Event::MappingStart(_) | Event::SequenceStart(_)));
+        if let Some(mut position) = start {
+            index_node(&events.0, &mut position, &Pointer::default(), &mut index);
+        }
+    }
+    index
+}
+
+fn index_node(events: &[(Event, Marker)], position: &mut usize, pointer: &Pointer, index: &mut HashMap<Pointer, Location>) {
+    let location = |marker: &Marker| Location { line: marker.line(), column: marker.col() + 1 };
+    let (event, marker) = match events.get(*position) {
+        Some(event) => event,
+        None => return,
+    };
+    *position += 1;
+    match event {
+        Event::MappingStart(_) => while let Some((event, marker)) = events.get(*position) {
+            match event {
+                Event::MappingEnd => {
+                    *position += 1;
+                    return;
+                },
+                Event::Scalar(key, ..) => {
+                    *position += 1;
+                    index.entry(pointer.clone()).or_insert_with(|| location(marker));
+                    index.insert(pointer.key(key), location(marker));
+                    index_node(events, position, &pointer.key(key), index);
+                },
+                // complex keys are skipped together with their value
+                _ => {
+                    index_node(events, position, &Pointer::default().key(""), &mut HashMap::new());
+                    index_node(events, position, &Pointer::default().key(""), &mut HashMap::new());
+                },
+            }
+        },
+        Event::SequenceStart(_) => {
+            index.entry(pointer.clone()).or_insert_with(|| location(marker));
+            let mut i = 0;
+            while let Some((event, _)) = events.get(*position) {
+                if let Event::SequenceEnd = event {
+                    *position += 1;
+                    return;
+                }
+                index_node(events, position, &pointer.index(i), index);
+                i += 1;
+            }
+        },
+        _ => {
+            index.entry(pointer.clone()).or_insert_with(|| location(marker));
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::replay::Format;
+
+    use super::{Diagnostics, Location, Pointer, Severity};
+
+    #[test]
+    fn pointer() {
+        let pointer = Pointer::default().key("commands").index(2).key("repeat").key("commands").index(0);
+        assert_eq!("commands[2].repeat.commands[0]", pointer.to_string());
+        assert_eq!(Location { line: 2, column: 3 }, Location::at_offset("ab\ncd", 5));
+    }
+
+    #[test]
+    fn diagnostics() {
+        let diagnostics = Diagnostics::check(Path::new("./test/diagnostics.yaml"), Format::Yaml).expect("readable file");
+        let found: Vec<_> = diagnostics
+            .iter()
+            .map(|item| (item.severity, item.pointer.to_string(), item.location.map(|location| (location.line, location.column))))
+            .collect();
+        assert_eq!(vec![
+            (Severity::Warning, String::from("commands[1].sleep.milis"), Some((9, 9))),
+            (Severity::Error, String::from("commands[0].mouse"), Some((3, 5))),
+            (Severity::Error, String::from("commands[1].sleep"), Some((7, 5))),
+            (Severity::Error, String::from("commands[2].repeat.commands[0].keyboard"), Some((14, 13))),
+            (Severity::Error, String::from("commands[3].wait_until"), Some((16, 5))),
+        ], found);
+        assert_eq!((4, 1), (diagnostics.errors(), diagnostics.warnings()));
+        assert_eq!("\
+error: MouseRight is no keyboard key
+  --> ./test/diagnostics.yaml:14:13
+   |
+14 |           - keyboard:
+   |             ^
+   = at commands[2].repeat.commands[0].keyboard
+", diagnostics.render_item(diagnostics.iter().nth(3).expect("keyboard error")));
+    }
+
+    #[test]
+    fn outdated() {
+        let diagnostics = Diagnostics::check(Path::new("./test/commands.yaml"), Format::Yaml).expect("readable file");
+        let found: Vec<_> = diagnostics.iter().map(|item| (item.severity, item.message.as_str())).collect();
+        assert_eq!(vec![(Severity::Warning, "the replay was written in version 0 of the replay format, upgrade it with the migrate subcommand")], found);
+        assert_eq!(0, Diagnostics::check(Path::new("./test/lint.yaml"), Format::Yaml).expect("readable file").warnings());
+    }
+
+    #[test]
+    fn structure() {
+        let diagnostics = Diagnostics::check(Path::new("./test/structure.yaml"), Format::Yaml).expect("readable file");
+        let found: Vec<_> = diagnostics
+            .iter()
+            .map(|item| (item.pointer.to_string(), item.location.map(|location| (location.line, location.column))))
+            .collect();
+        assert_eq!(vec![
+            (String::from("commands[0].mouse.key"), Some((4, 9))),
+            (String::from("commands[1]"), Some((7, 5))),
+            (String::from("commands[2].sleep.millis"), Some((10, 9))),
+            (String::from("commands[3].keyboard"), Some((11, 5))),
+            (String::from("commands[4].repeat.loops"), Some((14, 9))),
+        ], found);
+        assert_eq!((5, 0), (diagnostics.errors(), diagnostics.warnings()));
+        let messages: Vec<_> = diagnostics.iter().map(|item| item.message.as_str()).collect();
+        assert!(messages[0].starts_with("'Mouseleft' is not valid here, expected one of 'MouseLeft', "), "{}", messages[0]);
+        assert!(messages[1].starts_with("'click' is not valid here, expected one of 'break', 'continue', 'mouse', "), "{}", messages[1]);
+        assert_eq!("expected an integer or a string but found a list", messages[2]);
+        assert_eq!("missing field 'key'", messages[3]);
+        assert_eq!("'Forever' is not valid here, expected one of 'Infinite', 'Amount', 'Duration'", messages[4]);
+    }
+
+    #[test]
+    fn parse_errors() {
+        vec![
+            (Some((4, 9)), "./test/invalid.yaml", Format::Yaml),
+            (Some((1, 1)), "./test/commands.yaml", Format::Macro),
+            (Some((1, 6)), "./test/procedures.yaml", Format::Toml),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, (expected, path, format))| {
+            let diagnostics = Diagnostics::check(Path::new(path), format).expect("readable file");
+            assert_eq!(1, diagnostics.errors(), "case {}", case);
+            assert_eq!(expected, diagnostics.iter().next().and_then(|item| item.location).map(|location| (location.line, location.column)), "case {}", case);
+        });
+    }
+}
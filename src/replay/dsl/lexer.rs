@@ -0,0 +1,212 @@
+use std::{iter::Peekable, str::Chars};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TokenKind {
+    /// Any run of characters without whitespace or punctuation, e.g. `click`, `500ms`, `Ctrl+S` or `#2ecc71`.
+    Word(String),
+    /// A double quoted string with its escapes resolved.
+    Text(String),
+    /// The source between balanced parentheses, which holds an expression.
+    Group(String),
+    Comma,
+    Equals,
+    Open,
+    Close,
+    Newline,
+    End,
+}
+
+impl TokenKind {
+
+    pub fn describe(&self) -> String {
+        match self {
+            TokenKind::Word(word) => format!("'{}'", word),
+            TokenKind::Text(text) => format!("\"{}\"", text),
+            TokenKind::Group(group) => format!("'({})'", group),
+            TokenKind::Comma => String::from("','"),
+            TokenKind::Equals => String::from("'='"),
+            TokenKind::Open => String::from("'{'"),
+            TokenKind::Close => String::from("'}'"),
+            TokenKind::Newline => String::from("the end of the line"),
+            TokenKind::End => String::from("the end of the file"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Token {
+
+    pub fn error(&self, message: &str) -> String {
+        format!("{}:{}: {}", self.line, self.column, message)
+    }
+}
+
+pub fn is_word_char(c: char) -> bool {
+    !c.is_whitespace() && !matches!(c, '{' | '}' | '(' | ')' | ',' | '=' | '"')
+}
+
+struct Lexer<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Lexer<'a> {
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        match c {
+            '\n' => {
+                self.line += 1;
+                self.column = 1;
+            },
+            _ => self.column += 1,
+        }
+        Some(c)
+    }
+
+    fn error(&self, line: usize, column: usize, message: &str) -> String {
+        format!("{}:{}: {}", line, column, message)
+    }
+
+    fn text(&mut self, line: usize, column: usize) -> Result<String, String> {
+        let mut text = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(text),
+                Some('\\') => match self.next() {
+                    Some('n') => text.push('\n'),
+                    Some('t') => text.push('\t'),
+                    Some(c @ ('"' | '\\')) => text.push(c),
+                    Some(c) => return Err(self.error(self.line, self.column - 2, &format!("unknown escape '\\{}'", c))),
+                    None => return Err(self.error(line, column, "unterminated string")),
+                },
+                Some('\n') | None => return Err(self.error(line, column, "unterminated string")),
+                Some(c) => text.push(c),
+            }
+        }
+    }
+
+    fn group(&mut self, line: usize, column: usize) -> Result<String, String> {
+        let mut group = String::new();
+        let mut depth = 0;
+        let mut quote = None;
+        loop {
+            let c = match self.next() {
+                Some('\n') | None => return Err(self.error(line, column, "unclosed '('")),
+                Some(c) => c,
+            };
+            match (quote, c) {
+                (Some(q), c) if c == q => quote = None,
+                (Some(_), _) => (),
+                (None, '"' | '\'') => quote = Some(c),
+                (None, '(') => depth += 1,
+                (None, ')') if depth == 0 => return Ok(group),
+                (None, ')') => depth -= 1,
+                _ => (),
+            }
+            group.push(c);
+        }
+    }
+}
+
+/// Splits the source into tokens, comments start with `//` and last until the end of the line.
+pub fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut lexer = Lexer { chars: source.chars().peekable(), line: 1, column: 1 };
+    let mut tokens = Vec::new();
+    while let Some(&c) = lexer.chars.peek() {
+        let (line, column) = (lexer.line, lexer.column);
+        if c == '/' && lexer.chars.clone().nth(1) == Some('/') {
+            while lexer.chars.peek().is_some_and(|&c| c != '\n') {
+                lexer.next();
+            }
+            continue;
+        }
+        let kind = match c {
+            '\n' => {
+                lexer.next();
+                TokenKind::Newline
+            },
+            c if c.is_whitespace() => {
+                lexer.next();
+                continue;
+            },
+            '{' | '}' | ',' | '=' | ')' => {
+                lexer.next();
+                match c {
+                    '{' => TokenKind::Open,
+                    '}' => TokenKind::Close,
+                    ',' => TokenKind::Comma,
+                    '=' => TokenKind::Equals,
+                    _ => return Err(lexer.error(line, column, "unmatched ')'")),
+                }
+            },
+            '"' => {
+                lexer.next();
+                TokenKind::Text(lexer.text(line, column)?)
+            },
+            '(' => {
+                lexer.next();
+                TokenKind::Group(lexer.group(line, column)?)
+            },
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = lexer.chars.peek().filter(|&&c| is_word_char(c)) {
+                    word.push(c);
+                    lexer.next();
+                }
+                TokenKind::Word(word)
+            },
+        };
+        tokens.push(Token { kind, line, column });
+    }
+    tokens.push(Token { kind: TokenKind::End, line: lexer.line, column: lexer.column });
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TokenKind, tokenize};
+
+    fn kinds(source: &str) -> Result<Vec<TokenKind>, String> {
+        tokenize(source).map(|tokens| tokens.into_iter().map(|token| token.kind).collect())
+    }
+
+    fn word(word: &str) -> TokenKind {
+        TokenKind::Word(word.into())
+    }
+
+    #[test]
+    fn tokens() {
+        assert_eq!(Ok(vec![
+            word("click"), word("left"), word("100"), TokenKind::Comma, TokenKind::Group("y + f(1, 2)".into()), word("x3"), TokenKind::Newline,
+            word("press"), word("Ctrl+S"), TokenKind::Newline,
+            word("repeat"), word("5"), TokenKind::Open, TokenKind::Newline,
+            word("screenshot"), TokenKind::Text("a \"b\"\n".into()), TokenKind::Close, TokenKind::End,
+        ]), kinds("click left 100,(y + f(1, 2)) x3 // three times\npress Ctrl+S\nrepeat 5 {\n  screenshot \"a \\\"b\\\"\\n\"}"));
+        assert_eq!(Ok(vec![word("set"), word("x"), TokenKind::Equals, word("a/b"), TokenKind::End]), kinds("set x = a/b"));
+        assert_eq!(Ok(vec![TokenKind::Group("')'".into()), TokenKind::End]), kinds("(')')"));
+    }
+
+    #[test]
+    fn positions() {
+        let tokens = tokenize("click left\n  sleep 500ms").unwrap();
+        assert_eq!((2, 3), (tokens[3].line, tokens[3].column));
+        assert_eq!((2, 9), (tokens[4].line, tokens[4].column));
+        vec![
+            ("1:7: unterminated string", "image \"a.png"),
+            ("2:4: unclosed '('", "\nif (a > 1 {"),
+            ("1:3: unmatched ')'", "a )"),
+            ("1:4: unknown escape '\\q'", "\"ab\\q\""),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, (expected, source))| assert_eq!(Err(String::from(expected)), kinds(source), "case {}", case));
+    }
+}
@@ -0,0 +1,151 @@
+//! A line oriented text format for macros, e.g.
+//!
+//! ```text
+//! repeat 5 {
+//!     click left 100,200 x3
+//!     press Ctrl+S
+//!     sleep 500ms
+//! }
+//! ```
+
+mod lexer;
+mod parser;
+mod printer;
+
+use crate::hardware::Key;
+
+use super::{Commands, diagnostic::{Location, ParseError}};
+
+pub use printer::print;
+
+/// Parses a macro, errors are prefixed with the line and column like `3:7: unknown command 'clik'`.
+pub fn parse(source: &str) -> Result<Commands, ParseError> {
+    lexer::tokenize(source)
+        .and_then(|tokens| parser::Parser::new(tokens).file())
+        .map_err(|e| located(&e))
+}
+
+/// Splits off the `line:column: ` prefix every error of the lexer and the parser starts with.
+fn located(error: &str) -> ParseError {
+    let mut parts = error.splitn(3, ':');
+    match (parts.next().and_then(|line| line.parse().ok()), parts.next().and_then(|column| column.parse().ok()), parts.next()) {
+        (Some(line), Some(column), Some(message)) => ParseError::new(message.trim_start(), Some(Location { line, column })),
+        _ => ParseError::new(error, None),
+    }
+}
+
+/// Resolves the name of a key ignoring its case, besides the names of replay files the short forms of the printer are accepted.
+pub(super) fn key(name: &str) -> Option<Key> {
+    match name.to_ascii_lowercase().as_str() {
+        "ctrl" | "control" => Some(Key::Control),
+        "alt" => Some(Key::Alt),
+        "shift" => Some(Key::Shift),
+        "esc" | "escape" => Some(Key::Escape),
+        "enter" | "return" => Some(Key::Enter),
+        "tab" => Some(Key::Tab),
+        "space" => Some(Key::Space),
+        "backspace" => Some(Key::Backspace),
+        "left" => Some(Key::KeyboardLeft),
+        "up" => Some(Key::KeyboardUp),
+        "right" => Some(Key::KeyboardRight),
+        "down" => Some(Key::KeyboardDown),
+        letter if letter.len() == 1 => letter.to_ascii_uppercase().parse().ok(),
+        _ => name.parse().ok(),
+    }
+}
+
+fn key_name(key: Key) -> String {
+    match key {
+        Key::Control => String::from("Ctrl"),
+        Key::KeyboardLeft => String::from("Left"),
+        Key::KeyboardUp => String::from("Up"),
+        Key::KeyboardRight => String::from("Right"),
+        Key::KeyboardDown => String::from("Down"),
+        key => format!("{:?}", key),
+    }
+}
+
+fn button(name: &str) -> Option<Key> {
+    match name.to_ascii_lowercase().as_str() {
+        "left" => Some(Key::MouseLeft),
+        "right" => Some(Key::MouseRight),
+        "middle" => Some(Key::MouseMiddle),
+        "x1" => Some(Key::MouseX1),
+        "x2" => Some(Key::MouseX2),
+        _ => None,
+    }
+}
+
+/// Names mouse buttons like the parser expects them and falls back to the key name for keyboard keys, which fail validation.
+fn button_name(key: Key) -> String {
+    match key {
+        Key::MouseLeft => String::from("left"),
+        Key::MouseRight => String::from("right"),
+        Key::MouseMiddle => String::from("middle"),
+        Key::MouseX1 => String::from("x1"),
+        Key::MouseX2 => String::from("x2"),
+        key => key_name(key),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{expression::Argument, hardware::Key, replay::{Command, Format}};
+
+    use super::{parse, print};
+
+    #[test]
+    fn round_trip() {
+        vec!["commands", "control_flow", "procedures", "parameters", "wait", "assertions", "variables"]
+            .into_iter()
+            .enumerate()
+            .for_each(|(case, name)| {
+                let commands = crate::replay::Commands::load(Path::new(&format!("./test/{}.yaml", name)), Format::Yaml).expect("valid replay");
+                let text = print(&commands);
+                assert_eq!(Ok(commands), parse(&text), "case {}\n{}", case, text);
+            });
+    }
+
+    #[test]
+    fn commands() {
+        let commands = parse("\
+// saves the document three times
+repeat 3 as i {
+    click left 100,(y + 20) x3
+    press Ctrl+Shift+s
+    sleep 2s
+}
+").expect("valid macro");
+        assert_eq!(1, commands.commands.len());
+        match &commands.commands[0] {
+            Command::RepeatCommand { commands, counter, .. } => {
+                assert_eq!(Some(String::from("i")), *counter);
+                assert!(matches!(&commands[0], Command::MouseCommand { key: Key::MouseLeft, y: Argument::Expression(y), .. } if y == "y + 20"));
+                assert!(matches!(&commands[1], Command::KeyboardCommand { key: Key::S, modifiers, .. } if *modifiers == vec![Key::Control, Key::Shift]));
+                assert!(matches!(&commands[2], Command::SleepCommand { millis: Argument::Literal(2000) }));
+            },
+            command => panic!("unexpected {:?}", command),
+        }
+        assert_eq!(Ok(crate::replay::Commands::load(Path::new("./test/commands.yaml"), Format::Yaml).expect("valid replay")), parse(include_str!("../../../test/commands.macro")));
+    }
+
+    #[test]
+    fn errors() {
+        vec![
+            ("1:1: unknown command 'clik'", "clik left 1,2"),
+            ("2:7: unknown mouse button 'top', expected left, right, middle, x1 or x2", "\nclick top 1,2"),
+            ("1:13: expected ',' but found the end of the file", "click left 1"),
+            ("1:7: unknown key 'Hyper'", "press Hyper+S"),
+            ("3:1: expected '}' but found the end of the file", "repeat 2 {\n    sleep 1s\n"),
+            ("1:13: expected the end of the line but found 'later'", "sleep 500ms later"),
+            ("1:7: invalid expression '1 +': unexpected end of the expression", "sleep (1 +)"),
+            ("1:7: duration '9999999999999999s' is too large", "sleep 9999999999999999s"),
+            ("1:16: expected a color like '#ff8000' but got 'red'", "wait pixel 1,2 red"),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, (expected, source))| assert_eq!(Err(String::from(expected)), parse(source).map_err(String::from), "case {}", case));
+    }
+}
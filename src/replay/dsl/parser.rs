@@ -0,0 +1,415 @@
+use std::{collections::BTreeMap, convert::TryFrom};
+
+use crate::{expression::{Argument, Template}, hardware::{Color, Key}, replay::{Command, Commands, Condition, Loops, OnTimeout, Parameter, command::Procedure, condition::{Region, default_confidence}, parameter::ParameterType}};
+
+use super::{button, key, lexer::{Token, TokenKind}};
+
+pub struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+
+    pub fn new(tokens: Vec<Token>) -> Self {
+        Self { tokens, position: 0 }
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position.min(self.tokens.len() - 1)]
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.peek().clone();
+        if token.kind != TokenKind::End {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn unexpected(token: &Token, expected: &str) -> String {
+        token.error(&format!("expected {} but found {}", expected, token.kind.describe()))
+    }
+
+    fn expect(&mut self, kind: TokenKind) -> Result<(), String> {
+        match self.next() {
+            token if token.kind == kind => Ok(()),
+            token => Err(Self::unexpected(&token, &kind.describe())),
+        }
+    }
+
+    fn word(&mut self, expected: &str) -> Result<(Token, String), String> {
+        let token = self.next();
+        match &token.kind {
+            TokenKind::Word(word) => Ok((token.clone(), word.clone())),
+            _ => Err(Self::unexpected(&token, expected)),
+        }
+    }
+
+    fn text(&mut self, expected: &str) -> Result<(Token, String), String> {
+        let token = self.next();
+        match &token.kind {
+            TokenKind::Text(text) => Ok((token.clone(), text.clone())),
+            _ => Err(Self::unexpected(&token, expected)),
+        }
+    }
+
+    /// Consumes the keyword if it comes next.
+    fn keyword(&mut self, keyword: &str) -> bool {
+        match &self.peek().kind {
+            TokenKind::Word(word) if word == keyword => {
+                self.next();
+                true
+            },
+            _ => false,
+        }
+    }
+
+    fn end_of_statement(&mut self) -> Result<(), String> {
+        match self.next() {
+            Token { kind: TokenKind::Newline | TokenKind::End, .. } => Ok(()),
+            token => Err(Self::unexpected(&token, "the end of the line")),
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek().kind == TokenKind::Newline {
+            self.next();
+        }
+    }
+
+    pub fn file(&mut self) -> Result<Commands, String> {
+        let mut commands = Commands::default();
+        // macros without a version header predate it
+        commands.version = 0;
+        loop {
+            self.skip_newlines();
+            if self.peek().kind == TokenKind::End {
+                return Ok(commands);
+            }
+            let (token, word) = self.word("a command")?;
+            match word.as_str() {
+                "version" => {
+                    let (token, version) = self.word("a version number")?;
+                    commands.version = version.parse().map_err(|_| Self::unexpected(&token, "a version number"))?;
+                },
+                "loops" => commands.loops = self.loops("the amount of loops")?,
+                "include" => commands.include.push(self.text("the path of the included file")?.1.into()),
+                "param" => {
+                    let (name, parameter) = self.parameter()?;
+                    commands.parameters.insert(name, parameter);
+                },
+                "var" => {
+                    let (_, name) = self.word("a variable name")?;
+                    self.expect(TokenKind::Equals)?;
+                    commands.variables.insert(name, self.argument("a value")?);
+                },
+                "proc" => {
+                    let (_, name) = self.word("a procedure name")?;
+                    let mut parameters = Vec::new();
+                    while let TokenKind::Word(_) = self.peek().kind {
+                        parameters.push(self.word("a parameter name")?.1);
+                    }
+                    let body = self.block()?;
+                    if commands.procedures.insert(name.clone(), Procedure { parameters, commands: body }).is_some() {
+                        return Err(token.error(&format!("procedure '{}' is already defined", name)));
+                    }
+                },
+                _ => {
+                    let command = self.command(&token, &word)?;
+                    commands.commands.push(command);
+                    continue;
+                },
+            }
+            self.end_of_statement()?;
+        }
+    }
+
+    fn parameter(&mut self) -> Result<(String, Parameter), String> {
+        let (_, name) = self.word("a parameter name")?;
+        let (token, kind) = self.word("a parameter type")?;
+        let kind = match kind.as_str() {
+            "number" => ParameterType::Number,
+            "string" => ParameterType::String,
+            "point" => ParameterType::Point,
+            "bool" => ParameterType::Bool,
+            other => return Err(token.error(&format!("unknown parameter type '{}', expected number, string, point or bool", other))),
+        };
+        let default = match self.peek().kind {
+            TokenKind::Equals => {
+                self.next();
+                match self.peek().kind {
+                    TokenKind::Text(_) => Some(Argument::Expression(self.text("a default value")?.1.into())),
+                    _ => Some(self.argument("a default value")?),
+                }
+            },
+            _ => None,
+        };
+        Ok((name, Parameter { kind, default }))
+    }
+
+    /// Parses the commands between braces, the line may continue after the closing one.
+    fn block(&mut self) -> Result<Vec<Command>, String> {
+        self.expect(TokenKind::Open)?;
+        let mut commands = Vec::new();
+        loop {
+            self.skip_newlines();
+            match self.peek().kind {
+                TokenKind::Close => {
+                    self.next();
+                    return Ok(commands);
+                },
+                TokenKind::End => return Err(Self::unexpected(self.peek(), "'}'")),
+                _ => {
+                    let (token, word) = self.word("a command")?;
+                    commands.push(self.command(&token, &word)?);
+                },
+            }
+        }
+    }
+
+    /// Parses the rest of a command statement including the end of its line.
+    fn command(&mut self, token: &Token, word: &str) -> Result<Command, String> {
+        let command = match word {
+            "click" => {
+                let key = self.button()?;
+                let (x, y) = self.point()?;
+                Command::MouseCommand { key, x, y, loops: self.times()? }
+            },
+            "press" => {
+                let (token, combination) = self.word("a key like 'Ctrl+S'")?;
+                let mut keys = combination
+                    .split('+')
+                    .map(|name| key(name).ok_or_else(|| token.error(&format!("unknown key '{}'", name))))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let key = keys.pop().ok_or_else(|| token.error("expected a key"))?;
+                Command::KeyboardCommand { key, modifiers: keys, loops: self.times()? }
+            },
+            "sleep" => Command::SleepCommand { millis: self.duration()? },
+            "label" => Command::LabelCommand { name: self.word("a label")?.1 },
+            "goto" => Command::GotoCommand { label: self.word("a label")?.1 },
+            "set" => {
+                let (_, name) = self.word("a variable name")?;
+                self.expect(TokenKind::Equals)?;
+                Command::SetCommand { name, value: self.argument("a value")? }
+            },
+            "increment" => {
+                let (_, name) = self.word("a variable name")?;
+                let by = match self.keyword("by") {
+                    true => self.argument("the increment")?,
+                    false => 1.into(),
+                };
+                Command::IncrementCommand { name, by }
+            },
+            "repeat" => {
+                let loops = self.loops("the amount of repetitions")?;
+                let counter = match self.keyword("as") {
+                    true => Some(self.word("a counter name")?.1),
+                    false => None,
+                };
+                Command::RepeatCommand { loops, counter, commands: self.block()? }
+            },
+            "if" => {
+                let condition = self.argument("a condition")?;
+                let then = self.block()?;
+                let otherwise = match self.keyword("else") {
+                    true => self.block()?,
+                    false => Vec::new(),
+                };
+                Command::IfCommand { condition, then, otherwise }
+            },
+            "break" => Command::BreakCommand,
+            "continue" => Command::ContinueCommand,
+            "wait" => {
+                let condition = self.condition()?;
+                let interval = match self.keyword("every") {
+                    true => self.duration()?,
+                    false => 100.into(),
+                };
+                let timeout = match self.keyword("timeout") {
+                    true => Some(self.duration()?),
+                    false => None,
+                };
+                let on_timeout = match self.keyword("else") {
+                    true => {
+                        let (token, word) = self.word("'goto'")?;
+                        if word != "goto" {
+                            return Err(Self::unexpected(&token, "'goto'"));
+                        }
+                        OnTimeout::Goto(self.word("a label")?.1)
+                    },
+                    false => OnTimeout::Fail,
+                };
+                Command::WaitUntilCommand { condition, interval, timeout, on_timeout }
+            },
+            "call" => {
+                let (_, name) = self.word("a procedure name")?;
+                let mut arguments = BTreeMap::new();
+                while let TokenKind::Word(_) = self.peek().kind {
+                    let (_, parameter) = self.word("a parameter name")?;
+                    self.expect(TokenKind::Equals)?;
+                    arguments.insert(parameter, self.argument("an argument")?);
+                }
+                Command::CallCommand { name, arguments }
+            },
+            "find" => {
+                let (_, image) = self.text("the path of an image")?;
+                let region = self.region()?;
+                let confidence = self.confidence()?;
+                let variable = match self.keyword("as") {
+                    true => Some(self.word("a variable name")?.1),
+                    false => None,
+                };
+                let click = match self.keyword("click") {
+                    true => Some(self.button()?),
+                    false => None,
+                };
+                Command::FindImageCommand { image: image.into(), region, confidence, variable, click }
+            },
+            "screenshot" => {
+                let (token, path) = self.text("the path of the screenshot")?;
+                let path = Template::try_from(path).map_err(|e| token.error(&e))?;
+                Command::ScreenshotCommand { path, region: self.region()? }
+            },
+            "assert" => {
+                let condition = self.condition()?;
+                let name = match self.keyword("named") {
+                    true => Some(self.text("the name of the assertion")?.1),
+                    false => None,
+                };
+                Command::AssertCommand { condition, name, abort: self.keyword("abort") }
+            },
+            other => return Err(token.error(&format!("unknown command '{}'", other))),
+        };
+        self.end_of_statement()?;
+        Ok(command)
+    }
+
+    fn argument(&mut self, expected: &str) -> Result<Argument, String> {
+        let token = self.next();
+        let argument = match &token.kind {
+            TokenKind::Word(word) => match word.parse::<i64>() {
+                Ok(n) => Argument::Literal(n),
+                Err(_) => Argument::Expression(word.clone().into()),
+            },
+            TokenKind::Group(group) => Argument::Expression(group.clone().into()),
+            _ => return Err(Self::unexpected(&token, expected)),
+        };
+        argument.validate().map_err(|e| token.error(&e))?;
+        Ok(argument)
+    }
+
+    fn point(&mut self) -> Result<(Argument, Argument), String> {
+        let x = self.argument("an x coordinate")?;
+        self.expect(TokenKind::Comma)?;
+        Ok((x, self.argument("a y coordinate")?))
+    }
+
+    /// Milliseconds given as number with an optional `ms` or `s` unit or as expression.
+    fn duration(&mut self) -> Result<Argument, String> {
+        if let TokenKind::Word(word) = &self.peek().kind {
+            let (digits, factor) = match (word.strip_suffix("ms"), word.strip_suffix('s')) {
+                (Some(digits), _) => (digits, 1),
+                (None, Some(digits)) => (digits, 1000),
+                _ => (word.as_str(), 1),
+            };
+            if let Ok(n) = digits.parse::<i64>() {
+                let token = self.next();
+                return n.checked_mul(factor)
+                    .map(Argument::Literal)
+                    .ok_or_else(|| token.error(&format!("duration {} is too large", token.kind.describe())));
+            }
+        }
+        self.argument("a duration like '500ms'")
+    }
+
+    fn loops(&mut self, expected: &str) -> Result<Loops, String> {
+        match self.keyword("forever") {
+            true => Ok(Loops::Infinite),
+            false => match self.keyword("for") {
+                true => Ok(Loops::Duration(self.duration()?)),
+                false => Ok(Loops::Amount(self.argument(expected)?)),
+            },
+        }
+    }
+
+    /// The optional repetitions of a click or key press, e.g. `x3`, `forever` or `for 10s`.
+    fn times(&mut self) -> Result<Loops, String> {
+        let amount = match &self.peek().kind {
+            TokenKind::Word(word) if word == "forever" || word == "for" => return self.loops("the amount of repetitions"),
+            TokenKind::Word(word) if word == "x" => {
+                self.next();
+                self.argument("the amount of repetitions")?
+            },
+            TokenKind::Word(word) if word.starts_with('x') && word[1..].parse::<i64>().is_ok() => {
+                let amount = word[1..].parse::<i64>().unwrap_or_default();
+                self.next();
+                Argument::Literal(amount)
+            },
+            _ => return Ok(Loops::default()),
+        };
+        Ok(Loops::Amount(amount))
+    }
+
+    fn button(&mut self) -> Result<Key, String> {
+        let (token, name) = self.word("a mouse button")?;
+        button(&name).ok_or_else(|| token.error(&format!("unknown mouse button '{}', expected left, right, middle, x1 or x2", name)))
+    }
+
+    fn region(&mut self) -> Result<Option<Region>, String> {
+        match self.keyword("in") {
+            true => {
+                let (x, y) = self.point()?;
+                self.expect(TokenKind::Comma)?;
+                let width = self.argument("a width")?;
+                self.expect(TokenKind::Comma)?;
+                Ok(Some(Region { x, y, width, height: self.argument("a height")? }))
+            },
+            false => Ok(None),
+        }
+    }
+
+    fn confidence(&mut self) -> Result<f64, String> {
+        match self.keyword("confidence") {
+            true => {
+                let (token, word) = self.word("a confidence between -1 and 1")?;
+                word.parse().map_err(|_| Self::unexpected(&token, "a confidence between -1 and 1"))
+            },
+            false => Ok(default_confidence()),
+        }
+    }
+
+    fn condition(&mut self) -> Result<Condition, String> {
+        let (token, kind) = self.word("a condition")?;
+        let condition = match kind.as_str() {
+            "pixel" => {
+                let (x, y) = self.point()?;
+                let (token, color) = self.word("a color like '#ff8000'")?;
+                let color = color.parse::<Color>().map_err(|e| token.error(&e))?;
+                let tolerance = match &self.peek().kind {
+                    TokenKind::Word(word) if word.starts_with('~') => {
+                        let (token, word) = self.word("a tolerance")?;
+                        word[1..].parse().map_err(|_| Self::unexpected(&token, "a tolerance like '~8'"))?
+                    },
+                    _ => 0,
+                };
+                Condition::Pixel { x, y, color, tolerance }
+            },
+            "key" => {
+                let (token, name) = self.word("a key")?;
+                Condition::KeyPressed(key(&name).ok_or_else(|| token.error(&format!("unknown key '{}'", name)))?)
+            },
+            "window" => Condition::WindowExists(self.text("a window title")?.1),
+            "focused" => Condition::WindowFocused(self.text("a window title")?.1),
+            "file" => Condition::FileExists(self.text("a path")?.1.into()),
+            "clipboard" => Condition::Clipboard(self.text("the clipboard text")?.1),
+            "image" => {
+                let (_, image) = self.text("the path of an image")?;
+                let region = self.region()?;
+                Condition::Image { image: image.into(), region, confidence: self.confidence()? }
+            },
+            other => return Err(token.error(&format!("unknown condition '{}'", other))),
+        };
+        Ok(condition)
+    }
+}
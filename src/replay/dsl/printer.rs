@@ -0,0 +1,184 @@
+use crate::{expression::Argument, replay::{Command, Commands, Condition, Loops, OnTimeout, Parameter, condition::{Region, default_confidence}, parameter::ParameterType}};
+
+use super::{button_name, key_name};
+
+const INDENT: &str = "    ";
+
+/// Prints the commands as macro which parses back into the same commands.
+pub fn print(commands: &Commands) -> String {
+    let mut sections = Vec::new();
+    let mut header = vec![format!("version {}", commands.version)];
+    if commands.loops != Loops::default() {
+        header.push(format!("loops {}", loops(&commands.loops)));
+    }
+    header.extend(commands.include.iter().map(|path| format!("include {}", text(&path.to_string_lossy()))));
+    header.extend(commands.parameters.iter().map(|(name, parameter)| format!("param {} {}", name, self::parameter(parameter))));
+    header.extend(commands.variables.iter().map(|(name, value)| format!("var {} = {}", name, argument(value))));
+    sections.push(header.join("\n"));
+    for (name, procedure) in &commands.procedures {
+        let signature = std::iter::once(name.as_str()).chain(procedure.parameters.iter().map(String::as_str)).collect::<Vec<_>>().join(" ");
+        sections.push(format!("proc {} {}", signature, block(&procedure.commands, 0)));
+    }
+    sections.push(commands.commands.iter().map(|command| self::command(command, 0)).collect::<Vec<_>>().join("\n"));
+    let mut text = sections.into_iter().filter(|section| !section.is_empty()).collect::<Vec<_>>().join("\n\n");
+    text.push('\n');
+    text
+}
+
+fn block(commands: &[Command], depth: usize) -> String {
+    let mut text = String::from("{\n");
+    for command in commands {
+        text += &format!("{}{}\n", INDENT.repeat(depth + 1), self::command(command, depth + 1));
+    }
+    format!("{}{}}}", text, INDENT.repeat(depth))
+}
+
+fn command(command: &Command, depth: usize) -> String {
+    match command {
+        Command::MouseCommand { key, loops, x, y } => format!("click {} {},{}{}", button_name(*key), argument(x), argument(y), times(loops)),
+        Command::KeyboardCommand { key, modifiers, loops } => {
+            let keys = modifiers.iter().chain(std::iter::once(key)).map(|key| key_name(*key)).collect::<Vec<_>>().join("+");
+            format!("press {}{}", keys, times(loops))
+        },
+        Command::SleepCommand { millis } => format!("sleep {}", duration(millis)),
+        Command::LabelCommand { name } => format!("label {}", name),
+        Command::GotoCommand { label } => format!("goto {}", label),
+        Command::SetCommand { name, value } => format!("set {} = {}", name, argument(value)),
+        Command::IncrementCommand { name, by } => match by {
+            Argument::Literal(1) => format!("increment {}", name),
+            by => format!("increment {} by {}", name, argument(by)),
+        },
+        Command::RepeatCommand { loops, counter, commands } => {
+            let counter = counter.as_ref().map(|counter| format!(" as {}", counter)).unwrap_or_default();
+            format!("repeat {}{} {}", self::loops(loops), counter, block(commands, depth))
+        },
+        Command::IfCommand { condition, then, otherwise } => match otherwise.is_empty() {
+            true => format!("if {} {}", argument(condition), block(then, depth)),
+            false => format!("if {} {} else {}", argument(condition), block(then, depth), block(otherwise, depth)),
+        },
+        Command::BreakCommand => String::from("break"),
+        Command::ContinueCommand => String::from("continue"),
+        Command::WaitUntilCommand { condition, interval, timeout, on_timeout } => {
+            let mut text = format!("wait {}", self::condition(condition));
+            if *interval != Argument::Literal(100) {
+                text += &format!(" every {}", duration(interval));
+            }
+            if let Some(timeout) = timeout {
+                text += &format!(" timeout {}", duration(timeout));
+            }
+            if let OnTimeout::Goto(label) = on_timeout {
+                text += &format!(" else goto {}", label);
+            }
+            text
+        },
+        Command::CallCommand { name, arguments } => std::iter::once(format!("call {}", name))
+            .chain(arguments.iter().map(|(name, value)| format!("{}={}", name, argument(value))))
+            .collect::<Vec<_>>()
+            .join(" "),
+        Command::FindImageCommand { image, region, confidence, variable, click } => {
+            let mut text = format!("find {}{}{}", self::text(&image.to_string_lossy()), self::region(region), self::confidence(*confidence));
+            if let Some(variable) = variable {
+                text += &format!(" as {}", variable);
+            }
+            if let Some(click) = click {
+                text += &format!(" click {}", button_name(*click));
+            }
+            text
+        },
+        Command::ScreenshotCommand { path, region } => format!("screenshot {}{}", text(&String::from(path.clone())), self::region(region)),
+        Command::AssertCommand { condition, name, abort } => {
+            let mut text = format!("assert {}", self::condition(condition));
+            if let Some(name) = name {
+                text += &format!(" named {}", self::text(name));
+            }
+            if *abort {
+                text += " abort";
+            }
+            text
+        },
+    }
+}
+
+fn condition(condition: &Condition) -> String {
+    match condition {
+        Condition::Pixel { x, y, color, tolerance } => match tolerance {
+            0 => format!("pixel {},{} {}", argument(x), argument(y), color),
+            tolerance => format!("pixel {},{} {} ~{}", argument(x), argument(y), color, tolerance),
+        },
+        Condition::KeyPressed(key) => format!("key {}", key_name(*key)),
+        Condition::WindowExists(title) => format!("window {}", text(title)),
+        Condition::WindowFocused(title) => format!("focused {}", text(title)),
+        Condition::FileExists(path) => format!("file {}", text(&path.to_string_lossy())),
+        Condition::Clipboard(content) => format!("clipboard {}", text(content)),
+        Condition::Image { image, region, confidence } => format!("image {}{}{}", text(&image.to_string_lossy()), self::region(region), self::confidence(*confidence)),
+    }
+}
+
+fn parameter(parameter: &Parameter) -> String {
+    let default = match (parameter.kind, &parameter.default) {
+        (_, None) => String::new(),
+        (ParameterType::String, Some(Argument::Expression(default))) => format!(" = {}", text(default)),
+        (_, Some(default)) => format!(" = {}", argument(default)),
+    };
+    format!("{}{}", parameter.kind.name(), default)
+}
+
+/// Leaves literals and plain variable names bare and wraps any other expression in parentheses.
+fn argument(argument: &Argument) -> String {
+    match argument {
+        Argument::Literal(n) => n.to_string(),
+        Argument::Expression(source) if is_name(source) => source.to_string(),
+        Argument::Expression(source) => format!("({})", source),
+    }
+}
+
+fn is_name(source: &str) -> bool {
+    let mut chars = source.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        && !matches!(source, "forever" | "for")
+}
+
+fn duration(millis: &Argument) -> String {
+    match millis {
+        Argument::Literal(n) => format!("{}ms", n),
+        millis => argument(millis),
+    }
+}
+
+fn loops(loops: &Loops) -> String {
+    match loops {
+        Loops::Infinite => String::from("forever"),
+        Loops::Amount(amount) => argument(amount),
+        Loops::Duration(millis) => format!("for {}", duration(millis)),
+    }
+}
+
+/// The optional repetitions of a click or key press, nothing for a single one.
+fn times(loops: &Loops) -> String {
+    match loops {
+        Loops::Amount(Argument::Literal(1)) => String::new(),
+        Loops::Amount(Argument::Literal(n)) => format!(" x{}", n),
+        Loops::Amount(amount) => format!(" x({})", amount),
+        loops => format!(" {}", self::loops(loops)),
+    }
+}
+
+fn region(region: &Option<Region>) -> String {
+    match region {
+        Some(Region { x, y, width, height }) => format!(" in {},{},{},{}", argument(x), argument(y), argument(width), argument(height)),
+        None => String::new(),
+    }
+}
+
+fn confidence(confidence: f64) -> String {
+    match confidence == default_confidence() {
+        true => String::new(),
+        false => format!(" confidence {}", confidence),
+    }
+}
+
+fn text(text: &str) -> String {
+    let escaped = text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n").replace('\t', "\\t");
+    format!("\"{}\"", escaped)
+}
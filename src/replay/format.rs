@@ -0,0 +1,163 @@
+use std::{path::Path, str::FromStr};
+
+use serde_json::Value;
+
+use super::{Commands, binary, diagnostic::{Location, ParseError}, dsl};
+
+/// The serialization formats of replay files.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Yaml,
+    Json,
+    Toml,
+    Ron,
+    /// The line oriented text format of the `dsl` module.
+    Macro,
+    /// The compact encoding of the `binary` module for long recordings.
+    Binary,
+}
+
+impl Format {
+
+    /// Detects the format by the file extension.
+    pub fn from_path(path: &Path) -> Option<Format> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "yaml" | "yml" => Some(Format::Yaml),
+            "json" => Some(Format::Json),
+            "toml" => Some(Format::Toml),
+            "ron" => Some(Format::Ron),
+            "macro" => Some(Format::Macro),
+            "clkb" => Some(Format::Binary),
+            _ => None,
+        }
+    }
+
+    /// Prefers the explicitly chosen format over the detected one and falls back to YAML.
+    pub fn resolve(explicit: Option<Format>, path: &Path) -> Format {
+        explicit.or_else(|| Format::from_path(path)).unwrap_or(Format::Yaml)
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Yaml => "yaml",
+            Format::Json => "json",
+            Format::Toml => "toml",
+            Format::Ron => "ron",
+            Format::Macro => "macro",
+            Format::Binary => "clkb",
+        }
+    }
+
+    pub fn parse(&self, text: &str) -> Result<Commands, ParseError> {
+        match self {
+            Format::Yaml => serde_yaml::from_str(text).map_err(|e| {
+                let location = e.location().map(|location| Location { line: location.line(), column: location.column() });
+                let message = e.to_string();
+                // the location is reported separately
+                let suffix = location.map(|location| format!(" at line {} column {}", location.line, location.column)).unwrap_or_default();
+                ParseError::new(message.strip_suffix(&suffix).unwrap_or(&message), location)
+            }),
+            Format::Json => serde_json::from_str(text).map_err(|e| {
+                let location = Location { line: e.line(), column: e.column().max(1) };
+                let message = e.to_string();
+                let suffix = format!(" at line {} column {}", e.line(), e.column());
+                ParseError::new(message.strip_suffix(&suffix).unwrap_or(&message), Some(location))
+            }),
+            Format::Toml => toml::from_str(text).map_err(|e| ParseError::new(e.message(), e.span().map(|span| Location::at_offset(text, span.start)))),
+            Format::Ron => ron::from_str(text).map_err(|e| ParseError::new(&e.code.to_string(), Some(Location { line: e.position.line, column: e.position.col }))),
+            Format::Macro => dsl::parse(text),
+            Format::Binary => Err(ParseError::new("binary replays cannot be parsed from text", None)),
+        }
+    }
+
+    /// Reads the document into a format independent tree, which only self describing formats allow.
+    pub fn document(&self, text: &str) -> Option<Result<Value, String>> {
+        match self {
+            Format::Yaml => Some(serde_yaml::from_str(text).map_err(|e| e.to_string())),
+            Format::Json => Some(serde_json::from_str(text).map_err(|e| e.to_string())),
+            Format::Toml => Some(toml::from_str(text).map_err(|e| e.to_string())),
+            Format::Ron | Format::Macro | Format::Binary => None,
+        }
+    }
+
+    pub fn render(&self, value: &Commands) -> Result<String, String> {
+        match self {
+            Format::Yaml => serde_yaml::to_string(value).map_err(|e| e.to_string()),
+            Format::Json => serde_json::to_string_pretty(value).map_err(|e| e.to_string()),
+            Format::Toml => toml::to_string_pretty(value).map_err(|e| e.to_string()),
+            Format::Ron => ron::ser::to_string_pretty(value, ron::ser::PrettyConfig::default()).map_err(|e| e.to_string()),
+            Format::Macro => Ok(dsl::print(value)),
+            Format::Binary => Err(String::from("binary replays cannot be printed, write them to a file instead")),
+        }
+    }
+
+    /// The content of a replay file in this format.
+    pub fn encode(&self, value: &Commands) -> Result<Vec<u8>, String> {
+        match self {
+            Format::Binary => binary::encode(value),
+            format => format.render(value).map(String::into_bytes),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "yaml" | "yml" => Ok(Format::Yaml),
+            "json" => Ok(Format::Json),
+            "toml" => Ok(Format::Toml),
+            "ron" => Ok(Format::Ron),
+            "macro" => Ok(Format::Macro),
+            "binary" | "clkb" => Ok(Format::Binary),
+            _ => Err(format!("unknown format '{}', expected yaml, json, toml, ron, macro or binary", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::replay::Commands;
+
+    use super::Format;
+
+    #[test]
+    fn detection() {
+        vec![
+            (Some(Format::Yaml), "replay.yml"),
+            (Some(Format::Json), "pipeline/replay.JSON"),
+            (Some(Format::Toml), "replay.toml"),
+            (Some(Format::Ron), "replay.ron"),
+            (Some(Format::Macro), "replay.macro"),
+            (Some(Format::Binary), "recording.clkb"),
+            (None, "replay.txt"),
+            (None, "replay"),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, (expected, path))| assert_eq!(expected, Format::from_path(Path::new(path)), "case {}", case));
+        assert_eq!(Format::Ron, Format::resolve(Some(Format::Ron), Path::new("replay.json")));
+        assert_eq!(Format::Yaml, Format::resolve(None, Path::new("replay.txt")));
+        assert_eq!(Ok(Format::Toml), "TOML".parse());
+        assert!("xml".parse::<Format>().is_err());
+        assert!(Format::Binary.parse("CLKB").is_err());
+        assert!(Format::Binary.render(&Commands::default()).is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        vec!["commands", "control_flow", "procedures", "parameters", "wait", "assertions"]
+            .into_iter()
+            .map(|name| Commands::load(Path::new(&format!("./test/{}.yaml", name)), Format::Yaml).expect("valid replay"))
+            .flat_map(|commands| vec![Format::Yaml, Format::Json, Format::Toml, Format::Ron, Format::Macro].into_iter().map(move |format| (commands.clone(), format)))
+            .enumerate()
+            .for_each(|(case, (commands, format))| {
+                let text = format.render(&commands).unwrap_or_else(|e| panic!("case {} {:?}: {}", case, format, e));
+                let parsed = format.parse(&text).unwrap_or_else(|e| panic!("case {} {:?}: {}\n{}", case, format, e, text));
+                assert_eq!(commands, parsed, "case {} {:?}", case, format);
+            });
+    }
+}
@@ -0,0 +1,160 @@
+use std::{ffi::OsString, fs::{self, File, OpenOptions}, io::Write, path::{Path, PathBuf}};
+
+use super::{Command, Commands, Format};
+
+const EXTENSION: &str = "journal";
+
+/// The commands of a running recording, each appended as line of JSON so a crash loses at most the last one.
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+}
+
+impl Journal {
+
+    /// The journal of a recording is written next to its out file, e.g. `session.yaml.journal`.
+    pub fn path(out_file: &Path) -> PathBuf {
+        let mut path = OsString::from(out_file.as_os_str());
+        path.push(format!(".{}", EXTENSION));
+        PathBuf::from(path)
+    }
+
+    /// The out file a journal belongs to, `None` if the file is no journal.
+    pub fn out_file(journal: &Path) -> Option<PathBuf> {
+        match journal.extension() {
+            Some(extension) if extension == EXTENSION => Some(journal.with_extension("")),
+            _ => None,
+        }
+    }
+
+    /// Fails if a journal already exists, which is left by a recording that did not finish.
+    pub fn create(out_file: &Path) -> Result<Self, String> {
+        let path = Self::path(out_file);
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|e| match path.exists() {
+                true => format!("the unfinished journal '{}' exists, rebuild its recording with the recover subcommand or delete it", path.display()),
+                false => format!("failed to create '{}' because '{:?}'", path.display(), e.kind()),
+            })?;
+        Ok(Self { path, file })
+    }
+
+    /// Writes the command in one piece and waits until it reached the disk.
+    pub fn append(&mut self, command: &Command) -> Result<(), String> {
+        let mut line = serde_json::to_string(command).map_err(|e| format!("failed to serialize the command: {}", e))?;
+        line.push('\n');
+        self.file
+            .write_all(line.as_bytes())
+            .and_then(|_| self.file.sync_data())
+            .map_err(|e| format!("failed to write '{}' because '{:?}'", self.path.display(), e.kind()))
+    }
+
+    /// Saves the recorded commands to the out file and removes the journal, which is kept if saving fails.
+    pub fn finish(self, out_file: &Path, format: Format) -> Result<(), String> {
+        let Self { path, file } = self;
+        drop(file);
+        let (commands, warnings) = recover(&path)?;
+        warnings.iter().for_each(|warning| warn!("{}", warning));
+        commands
+            .save(out_file, format)
+            .map_err(|e| format!("{}, the commands are kept in '{}'", e, path.display()))?;
+        retire(&path, &warnings)
+    }
+}
+
+/// Removes the journal of a saved recording, unless some of its lines could not be read and would be lost with it.
+pub fn retire(journal: &Path, warnings: &[String]) -> Result<(), String> {
+    match warnings.len() {
+        0 => fs::remove_file(journal).map_err(|e| format!("failed to remove '{}' because '{:?}'", journal.display(), e.kind())),
+        lines => {
+            warn!("'{}' is kept as {} of its lines could not be read", journal.display(), lines);
+            Ok(())
+        },
+    }
+}
+
+/// Rebuilds the replay from a journal and warns about every line which cannot be read, usually the last one
+/// being cut off by the crash.
+pub fn recover(journal: &Path) -> Result<(Commands, Vec<String>), String> {
+    let text = fs::read(journal).map_err(|e| format!("failed to open '{}' because '{:?}'", journal.display(), e.kind()))?;
+    let mut warnings = Vec::new();
+    let mut commands: Vec<_> = String::from_utf8_lossy(&text)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .filter_map(|(i, line)| {
+            serde_json::from_str(line)
+                .map_err(|e| warnings.push(format!("line {}: the command is left out as it cannot be read: {}", i + 1, e)))
+                .ok()
+        })
+        .collect();
+    Ok((Commands::from(&mut commands), warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::{self, OpenOptions}, io::Write, path::{Path, PathBuf}};
+
+    use crate::{expression::Argument, hardware::Key, replay::{Command, Commands, Format, Loops}};
+
+    use super::{Journal, recover, retire};
+
+    fn commands() -> Vec<Command> {
+        vec![
+            Command::MouseCommand { key: Key::MouseLeft, loops: Loops::default(), x: Argument::Literal(10), y: Argument::Literal(-20) },
+            Command::KeyboardCommand { key: Key::Enter, modifiers: Vec::new(), loops: Loops::default() },
+        ]
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(PathBuf::from("records/session.yaml.journal"), Journal::path(Path::new("records/session.yaml")));
+        vec![
+            (Some(PathBuf::from("records/session.yaml")), "records/session.yaml.journal"),
+            (Some(PathBuf::from("session")), "session.journal"),
+            (None, "session.yaml"),
+            (None, "journal"),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, (expected, journal))| assert_eq!(expected, Journal::out_file(Path::new(journal)), "case {}", case));
+    }
+
+    #[test]
+    fn crash() {
+        let directory = std::env::temp_dir().join(format!("clicker-bot-journal-crash-{}", std::process::id()));
+        fs::create_dir_all(&directory).expect("temporary directory");
+        let out_file = directory.join("session.yaml");
+        let mut journal = Journal::create(&out_file).expect("created journal");
+        commands().iter().for_each(|command| journal.append(command).expect("appended command"));
+        drop(journal);
+        // the write of the last command was cut off
+        let mut file = OpenOptions::new().append(true).open(Journal::path(&out_file)).expect("opened journal");
+        file.write_all(br#"{"mouse":{"key":"Mou"#).expect("appended line");
+        assert!(Journal::create(&out_file).is_err_and(|e| e.contains("recover subcommand")));
+        let (recovered, warnings) = recover(&Journal::path(&out_file)).expect("recovered journal");
+        assert_eq!(Commands::from(&mut commands()), recovered);
+        assert_eq!(1, warnings.len());
+        assert!(warnings[0].starts_with("line 3: the command is left out as it cannot be read"));
+        assert_eq!(Ok(()), retire(&Journal::path(&out_file), &warnings));
+        assert!(Journal::path(&out_file).exists());
+        assert_eq!(Ok(()), retire(&Journal::path(&out_file), &[]));
+        assert!(!Journal::path(&out_file).exists());
+        fs::remove_dir_all(&directory).expect("removed temporary directory");
+    }
+
+    #[test]
+    fn finish() {
+        let directory = std::env::temp_dir().join(format!("clicker-bot-journal-finish-{}", std::process::id()));
+        fs::create_dir_all(&directory).expect("temporary directory");
+        let out_file = directory.join("session.json");
+        let mut journal = Journal::create(&out_file).expect("created journal");
+        commands().iter().for_each(|command| journal.append(command).expect("appended command"));
+        journal.finish(&out_file, Format::Json).expect("finished recording");
+        assert_eq!(Ok(Commands::from(&mut commands())), Commands::load(&out_file, Format::Json));
+        assert!(!Journal::path(&out_file).exists());
+        fs::remove_dir_all(&directory).expect("removed temporary directory");
+    }
+}
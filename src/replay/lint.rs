@@ -0,0 +1,125 @@
+use crate::{eventgrid::Signal, expression::Argument, hardware::Key};
+
+use super::{Command, Commands, Loops, diagnostic::Pointer};
+
+/// Finds constructs which are valid but most likely mistakes, `screen` is the width and height coordinates are checked against.
+pub fn lint(commands: &Commands, screen: Option<(u32, u32)>) -> Vec<(Pointer, String)> {
+    let root = Pointer::default();
+    let mut warnings = Vec::new();
+    if commands.loops == Loops::Infinite && !pauses(&commands.commands) {
+        warnings.push((root.key("loops"), String::from("the replay loops infinitely without ever sleeping or waiting")));
+    }
+    lint_block(&commands.commands, &root.key("commands"), screen, &mut warnings);
+    for (name, procedure) in &commands.procedures {
+        lint_block(&procedure.commands, &root.key("procedures").key(name).key("commands"), screen, &mut warnings);
+    }
+    warnings
+}
+
+fn lint_block(commands: &[Command], pointer: &Pointer, screen: Option<(u32, u32)>, warnings: &mut Vec<(Pointer, String)>) {
+    let mut previous: Option<&Command> = None;
+    for (i, command) in commands.iter().enumerate() {
+        let pointer = pointer.index(i).key(command.name());
+        match command {
+            Command::MouseCommand { loops: Loops::Infinite, .. } | Command::KeyboardCommand { loops: Loops::Infinite, .. } => {
+                // a label can still be reached by a goto
+                let unreachable = commands[i + 1..].iter().take_while(|next| !matches!(next, Command::LabelCommand { .. })).count();
+                if unreachable > 0 {
+                    warnings.push((pointer.clone(), format!("the command repeats infinitely, the {} command(s) after it are never reached", unreachable)));
+                }
+            },
+            Command::RepeatCommand { loops: Loops::Infinite, commands, .. } if !pauses(commands) => {
+                warnings.push((pointer.clone(), String::from("the block repeats infinitely without ever sleeping or waiting")));
+            },
+            _ => (),
+        }
+        if let Command::MouseCommand { key, x, y, .. } = command {
+            if let Some((width, height)) = screen {
+                let outside = |coordinate: &Argument, size: u32| coordinate.literal().is_some_and(|value| value < 0 || value >= i64::from(size));
+                if outside(x, width) || outside(y, height) {
+                    warnings.push((pointer.clone(), format!("the position {},{} is outside of the {}x{} screen", x, y, width, height)));
+                }
+            }
+            if matches!(previous, Some(Command::MouseCommand { key: other, x: other_x, y: other_y, .. }) if other == key && other_x == x && other_y == y) {
+                warnings.push((pointer.clone(), format!("the command clicks {},{} like the one before, raise its loops instead", x, y)));
+            }
+        }
+        if let Command::KeyboardCommand { key, modifiers, .. } = command {
+            if let Some((hotkey, action)) = modifiers.iter().chain(std::iter::once(key)).find_map(|key| hotkey(*key).map(|action| (key, action))) {
+                warnings.push((pointer.clone(), format!("pressing {:?} triggers the {} hotkey", hotkey, action)));
+            }
+        }
+        command.blocks().into_iter().for_each(|(name, block)| lint_block(block, &pointer.key(name), screen, warnings));
+        previous = Some(command);
+    }
+}
+
+/// Whether the block or any block nested in it sleeps or waits, calls are assumed to do so.
+fn pauses(commands: &[Command]) -> bool {
+    commands.iter().any(|command| match command {
+        Command::SleepCommand { .. } | Command::WaitUntilCommand { .. } | Command::CallCommand { .. } => true,
+        command => command.blocks().into_iter().any(|(_, block)| pauses(block)),
+    })
+}
+
+/// The action of the hotkey listened for by the eventgrid while replaying, if the key is one.
+fn hotkey(key: Key) -> Option<&'static str> {
+    match Signal::from(key) {
+        Signal::Pause => Some("pause"),
+        Signal::Shutdown => Some("shutdown"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::replay::{Commands, Diagnostics, Format, dsl};
+
+    use super::lint;
+
+    #[test]
+    fn lints() {
+        let commands = Commands::load(Path::new("./test/lint.yaml"), Format::Yaml).expect("valid replay");
+        let found: Vec<_> = lint(&commands, Some((32, 24))).into_iter().map(|(pointer, _)| pointer.to_string()).collect();
+        assert_eq!(vec![
+            "loops",
+            "commands[1].mouse",
+            "commands[2].mouse",
+            "commands[2].mouse",
+            "commands[5].repeat.commands[0].keyboard",
+        ], found);
+        assert_eq!(4, lint(&commands, None).len());
+    }
+
+    #[test]
+    fn macros() {
+        vec![
+            (0, "repeat forever {\n    click left 1,1\n    sleep 1s\n}"),
+            (1, "repeat forever {\n    if (x > 1) {\n        click left 1,1\n    }\n}"),
+            (0, "click left 1,1 forever\nlabel end\nclick left 2,2"),
+            (1, "click left 1,1 forever\npress a"),
+            (1, "press Ctrl+Up"),
+            (0, "click left 1,1\nclick right 1,1\nclick left 1,1"),
+            (1, "click left (x),1\nclick left (x),1"),
+            (1, "click left -1,1"),
+            (0, "click left (x - 5),1"),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, (expected, source))| {
+            let commands = dsl::parse(source).expect("valid macro");
+            assert_eq!(expected, lint(&commands, Some((32, 24))).len(), "case {}", case);
+        });
+    }
+
+    #[test]
+    fn diagnostics() {
+        let diagnostics = Diagnostics::lint(Path::new("./test/lint.yaml"), Format::Yaml, Some((32, 24))).expect("readable file");
+        assert_eq!((0, 5), (diagnostics.errors(), diagnostics.warnings()));
+        let locations: Vec<_> = diagnostics.iter().filter_map(|item| item.location).map(|location| (location.line, location.column)).collect();
+        assert_eq!(vec![(2, 1), (8, 5), (12, 5), (12, 5), (27, 13)], locations);
+        assert_eq!(4, Diagnostics::lint(Path::new("./test/diagnostics.yaml"), Format::Yaml, Some((32, 24))).expect("readable file").errors());
+    }
+}
@@ -0,0 +1,146 @@
+use std::{convert::TryFrom, fs, path::{Path, PathBuf}};
+
+use serde_json::Value;
+
+use super::{Commands, Format, binary, diagnostic::ParseError};
+
+/// The version of the replay model, which is written into every saved replay.
+pub const VERSION: u32 = 1;
+
+/// Upgrades a document by one version, the migration at index `n` turns version `n` into `n + 1`.
+type Migration = fn(&mut Value) -> Result<(), String>;
+
+const MIGRATIONS: [Migration; VERSION as usize] = [unversioned];
+
+/// Replays written before the version header already match the model of version 1.
+fn unversioned(_: &mut Value) -> Result<(), String> {
+    Ok(())
+}
+
+/// Parses the content of a replay file of any known version, upgrades it to the current one and returns the
+/// version it was written in.
+///
+/// Migrations work on a format independent tree, RON and macro documents can only be turned into one through
+/// the current model, so they are only migrated as long as their version still parses. Binary replays did not
+/// exist before version 1 and have no migrations yet.
+pub fn upgrade(content: &[u8], format: Format) -> Result<(Commands, u32), ParseError> {
+    if format == Format::Binary {
+        return binary::decode(content).map(|commands| {
+            let version = commands.version;
+            (commands, version)
+        });
+    }
+    let text = std::str::from_utf8(content).map_err(|e| ParseError::new(&format!("the replay is no valid UTF-8: {}", e), None))?;
+    let parsed = match format.parse(text) {
+        Ok(commands) if commands.version == VERSION => return Ok((commands, VERSION)),
+        parsed => parsed,
+    };
+    let mut document = match (format.document(text), parsed) {
+        (Some(Ok(document)), _) if version(&document) != VERSION => document,
+        (_, Ok(commands)) => serde_json::to_value(&commands).map_err(|e| ParseError::new(&e.to_string(), None))?,
+        (_, Err(e)) => return Err(e),
+    };
+    let original = upgrade_document(&mut document)?;
+    serde_json::from_value(document)
+        .map(|commands| (commands, original))
+        .map_err(|e| ParseError::new(&format!("failed to migrate from version {}: {}", original, e), None))
+}
+
+/// Upgrades the format independent tree of a replay to the current version and returns the version it was written in.
+pub fn upgrade_document(document: &mut Value) -> Result<u32, ParseError> {
+    let original = version(document);
+    if original > VERSION {
+        let message = format!("the replay has version {} but this clicker-bot only supports versions up to {}", original, VERSION);
+        return Err(ParseError::new(&message, None));
+    }
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(original as usize) {
+        migration(document).map_err(|e| ParseError::new(&format!("failed to migrate from version {}: {}", version, e), None))?;
+        document
+            .as_object_mut()
+            .ok_or_else(|| ParseError::new("a replay must be a map", None))?
+            .insert(String::from("version"), Value::from(version + 1));
+    }
+    Ok(original)
+}
+
+/// Documents without a version header are version 0.
+fn version(document: &Value) -> u32 {
+    document
+        .get("version")
+        .and_then(Value::as_u64)
+        .map_or(0, |version| u32::try_from(version).unwrap_or(u32::MAX))
+}
+
+/// Rewrites an outdated replay in place after copying the original to `<file>.v<version>.bak`, which is returned.
+///
+/// Includes are migrated separately as they are files of their own.
+pub fn migrate(path: &Path, format: Format) -> Result<Option<PathBuf>, String> {
+    let content = fs::read(path).map_err(|e| format!("failed to open '{}' because '{:?}'", path.display(), e.kind()))?;
+    let (commands, version) = upgrade(&content, format)?;
+    if version == VERSION {
+        return Ok(None);
+    }
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".v{}.bak", version));
+    let backup = PathBuf::from(backup);
+    if backup.exists() {
+        return Err(format!("the backup '{}' already exists", backup.display()));
+    }
+    let migrated = format.encode(&commands)?;
+    if upgrade(&migrated, format).map(|(migrated, _)| migrated).as_ref() != Ok(&commands) {
+        return Err(String::from("the migrated replay does not read back the same, the file was left unchanged"));
+    }
+    fs::copy(path, &backup).map_err(|e| format!("failed to write the backup '{}' because '{:?}'", backup.display(), e.kind()))?;
+    fs::write(path, migrated).map_err(|e| format!("failed to write '{}' because '{:?}'", path.display(), e.kind()))?;
+    Ok(Some(backup))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use crate::replay::Format;
+
+    use super::{VERSION, migrate, upgrade};
+
+    #[test]
+    fn upgrade_versions() {
+        vec![
+            (Ok(0), "./test/commands.yaml", Format::Yaml),
+            (Ok(0), "./test/generated.json", Format::Json),
+            (Ok(0), "./test/generated.ron", Format::Ron),
+            (Ok(VERSION), "./test/commands.macro", Format::Macro),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, (expected, path, format))| {
+            let text = fs::read_to_string(path).expect("readable fixture");
+            let upgraded = upgrade(text.as_bytes(), format);
+            assert_eq!(expected, upgraded.as_ref().map(|(_, version)| *version).map_err(ToString::to_string), "case {}", case);
+            assert!(upgraded.is_ok_and(|(commands, _)| commands.version == VERSION), "case {}", case);
+        });
+        assert_eq!(
+            Err(String::from("the replay has version 99 but this clicker-bot only supports versions up to 1")),
+            upgrade(b"version: 99\ncommands: []", Format::Yaml).map(|_| ()).map_err(String::from),
+        );
+        assert!(upgrade(b"version: 1\ncommands: [jump]", Format::Yaml).is_err());
+        assert!(upgrade(&[0xFF, 0xFE], Format::Yaml).is_err_and(|e| e.message.contains("UTF-8")));
+    }
+
+    #[test]
+    fn migrate_file() {
+        let directory = std::env::temp_dir().join(format!("clicker-bot-migrate-{}", std::process::id()));
+        fs::create_dir_all(&directory).expect("temporary directory");
+        let path = directory.join("replay.yaml");
+        fs::copy("./test/control_flow.yaml", &path).expect("copied fixture");
+        let backup = migrate(&path, Format::Yaml).expect("migrated replay").expect("backup");
+        assert_eq!(directory.join("replay.yaml.v0.bak"), backup);
+        assert_eq!(fs::read_to_string("./test/control_flow.yaml").ok(), fs::read_to_string(&backup).ok());
+        assert!(fs::read_to_string(&path).is_ok_and(|text| text.contains("version: 1")));
+        assert_eq!(Ok(None), migrate(&path, Format::Yaml));
+        fs::copy("./test/control_flow.yaml", &path).expect("copied fixture");
+        assert!(migrate(&path, Format::Yaml).is_err_and(|e| e.contains("already exists")));
+        assert!(migrate(Path::new("./test/missing.yaml"), Format::Yaml).is_err());
+        fs::remove_dir_all(&directory).expect("removed temporary directory");
+    }
+}
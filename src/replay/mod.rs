@@ -1,7 +1,34 @@
+mod backend;
+mod binary;
 mod command;
+mod condition;
+mod convert;
+mod diagnostic;
+mod dsl;
+mod format;
+mod journal;
+mod lint;
+mod migration;
+mod parameter;
+mod position;
+mod program;
 mod recorder;
 mod replayer;
+mod report;
+mod schema;
+mod timeline;
 
 pub use command::{Command, Commands, Loops};
+pub use condition::{Condition, OnTimeout};
+pub use convert::{Dialect, Source, export, import};
+pub use diagnostic::Diagnostics;
+pub use format::Format;
+pub use journal::{Journal, recover, retire};
+pub use migration::migrate;
+pub use parameter::{Assignment, Parameter};
+pub use position::{Anchor, Call, Position};
+pub use program::{Instruction, Program};
 pub use recorder::Recorder;
-pub use replayer::Replayer;
+pub use replayer::{ReplayOptions, Replayer};
+pub use report::Report;
+pub use schema::schema;
@@ -0,0 +1,106 @@
+use std::str::FromStr;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::expression::{Argument, Scope, Value};
+
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParameterType {
+    Number,
+    String,
+    Point,
+    Bool,
+}
+
+impl ParameterType {
+
+    /// Converts an argument to a value of this type, strings are taken literally instead of being evaluated.
+    pub fn value(&self, argument: &Argument) -> Result<Value, String> {
+        let value = match (self, argument) {
+            (ParameterType::String, Argument::Expression(s)) => Value::Text(s.to_string()),
+            (ParameterType::String, Argument::Literal(n)) => Value::Text(n.to_string()),
+            (_, argument) => argument.evaluate(&Scope::default())?,
+        };
+        match value.type_name() == self.name() {
+            true => Ok(value),
+            false => Err(format!("expected a {} but got {} '{}'", self.name(), value.type_name(), value)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ParameterType::Number => "number",
+            ParameterType::String => "string",
+            ParameterType::Point => "point",
+            ParameterType::Bool => "bool",
+        }
+    }
+}
+
+/// A script input which can be overridden on the command line, parameters without default are required.
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub struct Parameter {
+    #[serde(rename = "type")]
+    pub kind: ParameterType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<Argument>,
+}
+
+impl Parameter {
+
+    pub fn default_value(&self) -> Option<Result<Value, String>> {
+        self.default.as_ref().map(|argument| self.kind.value(argument))
+    }
+}
+
+/// A `name=value` pair given on the command line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Assignment {
+    pub name: String,
+    pub value: String,
+}
+
+impl FromStr for Assignment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((name, value)) if !name.trim().is_empty() => Ok(Assignment { name: name.trim().into(), value: value.into() }),
+            _ => Err(format!("expected 'name=value' but got '{}'", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::expression::{Argument, Value};
+
+    use super::{Assignment, ParameterType};
+
+    #[test]
+    fn value() {
+        vec![
+            (Ok(Value::Number(50.0)), ParameterType::Number, Argument::Literal(50)),
+            (Ok(Value::Number(0.5)), ParameterType::Number, Argument::Expression("1 / 2".into())),
+            (Ok(Value::Text("main menu".into())), ParameterType::String, Argument::Expression("main menu".into())),
+            (Ok(Value::Text("12".into())), ParameterType::String, Argument::Literal(12)),
+            (Ok(Value::Point(3.0, 4.0)), ParameterType::Point, Argument::Expression("point(3, 4)".into())),
+            (Ok(Value::Bool(true)), ParameterType::Bool, Argument::Expression("true".into())),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, (expected, kind, argument))| assert_eq!(expected, kind.value(&argument), "case {}", case));
+        assert!(ParameterType::Number.value(&Argument::Expression("point(1, 2)".into())).is_err());
+        assert!(ParameterType::Point.value(&Argument::Expression("x".into())).is_err());
+    }
+
+    #[test]
+    fn parse_assignment() {
+        assert_eq!(Ok(Assignment { name: "count".into(), value: "50".into() }), "count=50".parse());
+        assert_eq!(Ok(Assignment { name: "title".into(), value: "a=b".into() }), "title=a=b".parse());
+        assert!("count".parse::<Assignment>().is_err());
+        assert!("=5".parse::<Assignment>().is_err());
+    }
+}
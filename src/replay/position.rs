@@ -0,0 +1,162 @@
+use std::{collections::BTreeMap, fs::File, io::{BufReader, BufWriter}, path::Path, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::expression::Value;
+
+use super::Program;
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Position {
+    pub iteration: usize,
+    pub command: usize,
+    /// The start and iteration of every entered `repeat` block, outermost first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub loops: Vec<(usize, usize)>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub variables: BTreeMap<String, Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub calls: Vec<Call>,
+}
+
+/// An active procedure call, outermost first.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Call {
+    /// The instruction to continue with after the procedure returned.
+    pub ret: usize,
+    /// The amount of entered `repeat` blocks when the procedure was called.
+    pub loops: usize,
+    /// The values the parameters had before the call, restored on return.
+    pub shadowed: Vec<(String, Option<Value>)>,
+}
+
+impl Position {
+
+    /// The position in the main script, which is the outermost call while inside a procedure.
+    pub fn entry(&self) -> usize {
+        self.calls.first().map_or(self.command, |call| call.ret - 1)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        File::open(path)
+            .map_err(|e| format!("failed to open '{}' because '{:?}'", path.to_string_lossy(), e.kind()))
+            .map(BufReader::new)
+            .and_then(|reader| serde_yaml::from_reader(reader).map_err(|e| format!("'{}' contains no valid position: {}", path.to_string_lossy(), e)))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        File::create(path)
+            .map_err(|e| format!("failed to create file: {:?}", e.kind()))
+            .map(BufWriter::new)
+            .and_then(|writer| serde_yaml::to_writer(writer, self).map_err(|e| e.to_string()))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Anchor {
+    Index(usize),
+    Label(String),
+}
+
+impl Anchor {
+
+    /// The index of the top level command the anchor refers to, commands nested in blocks are no entry points.
+    pub fn resolve(&self, program: &Program) -> Result<usize, String> {
+        match self {
+            Anchor::Index(index) if *index < program.entries() => Ok(*index),
+            Anchor::Index(index) => Err(format!("command index {} is out of range, the replay has {} top level commands", index, program.entries())),
+            Anchor::Label(label) => {
+                let pc = program.find_label(label).ok_or_else(|| format!("label '{}' does not exist", label))?;
+                program.entry_of(pc).ok_or_else(|| format!("label '{}' is inside a block and cannot be used as entry point", label))
+            },
+        }
+    }
+}
+
+impl FromStr for Anchor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<usize>() {
+            Ok(index) => Ok(Anchor::Index(index)),
+            Err(_) if s.is_empty() => Err(String::from("expected a command index or label")),
+            Err(_) => Ok(Anchor::Label(s.into())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{expression::Value, replay::{Commands, Program}};
+
+    use super::{Anchor, Position};
+
+    fn sample_program() -> Program {
+        let commands: Commands = serde_yaml::from_str("
+            loops: Infinite
+            commands:
+              - sleep:
+                    millis: 10
+              - label:
+                    name: tail
+              - sleep:
+                    millis: 20
+        ").expect("valid commands");
+        Program::compile(&commands).expect("valid program")
+    }
+
+    #[test]
+    fn parse_anchor() {
+        assert_eq!(Ok(Anchor::Index(12)), "12".parse());
+        assert_eq!(Ok(Anchor::Label("tail".into())), "tail".parse());
+        assert!("".parse::<Anchor>().is_err());
+    }
+
+    #[test]
+    fn resolve_nested() {
+        let commands: Commands = serde_yaml::from_str("
+            loops: Infinite
+            commands:
+              - repeat:
+                    loops:
+                        Amount: 2
+                    commands:
+                      - label:
+                            name: inner
+                      - sleep:
+                            millis: 10
+              - label:
+                    name: tail
+        ").expect("valid commands");
+        let program = Program::compile(&commands).expect("valid program");
+        assert_eq!(Ok(1), Anchor::Index(1).resolve(&program));
+        assert_eq!(Ok(1), Anchor::Label("tail".into()).resolve(&program));
+        assert!(Anchor::Index(2).resolve(&program).is_err());
+        assert!(Anchor::Label("inner".into()).resolve(&program).is_err());
+    }
+
+    #[test]
+    fn resolve() {
+        let program = sample_program();
+        vec![
+            (Ok(0), Anchor::Index(0)),
+            (Ok(2), Anchor::Index(2)),
+            (Ok(1), Anchor::Label("tail".into())),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, (expected, anchor))| assert_eq!(expected, anchor.resolve(&program), "case {}", case));
+        assert!(Anchor::Index(3).resolve(&program).is_err());
+        assert!(Anchor::Label("head".into()).resolve(&program).is_err());
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = std::env::temp_dir().join(format!("clicker-bot-position-{}.yaml", std::process::id()));
+        let mut position = Position { iteration: 3, command: 7, loops: vec![(2, 4)], ..Position::default() };
+        position.variables.insert("origin".into(), Value::Point(10.0, 20.0));
+        assert!(position.save(&path).is_ok());
+        assert_eq!(Ok(position), Position::load(&path));
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,481 @@
+use std::{collections::HashMap, time::Instant};
+
+use crate::expression::{Argument, Scope};
+
+use super::{Command, Commands, Loops, OnTimeout};
+
+/// A flat list of instructions the nested `Commands` get compiled to, blocks are expressed by jumps.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Instruction {
+    Execute(Command),
+    /// Waits for the condition of a `wait_until` command and continues at `timeout` if it never holds.
+    Wait { command: Command, timeout: Option<usize> },
+    Loop { loops: Loops, counter: Option<String>, end: usize },
+    EndLoop { start: usize },
+    Branch { condition: Argument, otherwise: usize },
+    Jump { target: usize },
+    Break { end: Option<usize> },
+    Continue { end: Option<usize> },
+    Goto { target: usize, unwind: usize },
+    Call { target: usize, arguments: Vec<(String, Argument)> },
+    Return,
+}
+
+/// The main script is followed by the compiled procedures, each ending with a `Return`.
+#[derive(Clone, Debug)]
+pub struct Program {
+    commands: Commands,
+    instructions: Vec<Instruction>,
+    main: usize,
+    /// The first instruction of every top level command of the main script.
+    entries: Vec<usize>,
+    labels: HashMap<String, usize>,
+}
+
+impl Program {
+
+    pub fn compile(commands: &Commands) -> Result<Self, String> {
+        let mut compiler = Compiler::default();
+        compiler.block(commands.iter_commands().as_slice(), false)?;
+        let main = compiler.instructions.len();
+        for (name, procedure) in commands.procedures() {
+            compiler.procedures.insert(name.clone(), (compiler.instructions.len(), &procedure.parameters));
+            compiler.procedure = Some(name.clone());
+            compiler.block(&procedure.commands, false)?;
+            compiler.instructions.push(Instruction::Return);
+        }
+        compiler.link()?;
+        Ok(Self {
+            commands: commands.clone(),
+            instructions: compiler.instructions,
+            main,
+            entries: compiler.entries,
+            labels: compiler.labels
+                .into_iter()
+                .filter(|((procedure, _), _)| procedure.is_none())
+                .map(|((_, label), (pc, _))| (label, pc))
+                .collect(),
+        })
+    }
+
+    pub fn iter_loop<'a>(&self, scope: &Scope, clock: &'a dyn Fn() -> Instant) -> Result<Box<dyn Iterator<Item = usize> + 'a>, String> {
+        self.commands.iter_loop(scope, clock)
+    }
+
+    /// How often the whole program is repeated.
+    pub fn loops(&self) -> &Loops {
+        &self.commands.loops
+    }
+
+    /// Whether any command, including the ones in procedures, reads pixels or captures the screen.
+    pub fn reads_screen(&self) -> bool {
+        self.commands.walk().into_iter().any(Command::reads_screen)
+    }
+
+    pub fn scope(&self) -> Result<Scope, String> {
+        self.commands.scope()
+    }
+
+    pub fn get(&self, pc: usize) -> Option<&Instruction> {
+        self.instructions.get(pc)
+    }
+
+    /// The amount of instructions of the main script, without the procedures.
+    pub fn len(&self) -> usize {
+        self.main
+    }
+
+    /// Finds labels of the main script, labels inside procedures can not be used as entry points.
+    pub fn find_label(&self, label: &str) -> Option<usize> {
+        self.labels.get(label).copied()
+    }
+
+    /// The amount of top level commands of the main script, nested commands are not counted.
+    pub fn entries(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The index of the top level command starting at the instruction, `None` for instructions inside a block.
+    pub fn entry_of(&self, pc: usize) -> Option<usize> {
+        self.entries.binary_search(&pc).ok()
+    }
+
+    /// The first and the last instruction of a top level command, including its nested blocks.
+    pub fn span(&self, entry: usize) -> Option<(usize, usize)> {
+        let first = *self.entries.get(entry)?;
+        let next = self.entries.get(entry + 1).copied().unwrap_or(self.main);
+        Some((first, next - 1))
+    }
+}
+
+type Path = Vec<(usize, bool)>;
+
+/// Labels are scoped to the main script or the procedure they are defined in.
+type Label = (Option<String>, String);
+
+#[derive(Default)]
+struct Compiler<'a> {
+    instructions: Vec<Instruction>,
+    entries: Vec<usize>,
+    labels: HashMap<Label, (usize, Path)>,
+    gotos: Vec<(usize, Label, Path)>,
+    procedures: HashMap<String, (usize, &'a Vec<String>)>,
+    calls: Vec<(usize, String)>,
+    loops: Vec<Vec<usize>>,
+    path: Path,
+    blocks: usize,
+    /// The name of the procedure being compiled, `None` while compiling the main script.
+    procedure: Option<String>,
+}
+
+impl Compiler<'_> {
+
+    fn block(&mut self, commands: &[Command], is_loop: bool) -> Result<(), String> {
+        self.blocks += 1;
+        self.path.push((self.blocks, is_loop));
+        let result = commands.iter().try_for_each(|command| self.command(command));
+        self.path.pop();
+        result
+    }
+
+    fn command(&mut self, command: &Command) -> Result<(), String> {
+        let pc = self.instructions.len();
+        if self.procedure.is_none() && self.path.len() == 1 {
+            self.entries.push(pc);
+        }
+        match command {
+            Command::RepeatCommand { loops, counter, commands } => {
+                self.instructions.push(Instruction::Loop { loops: loops.clone(), counter: counter.clone(), end: 0 });
+                self.loops.push(Vec::new());
+                self.block(commands, true)?;
+                let end = self.instructions.len();
+                self.instructions.push(Instruction::EndLoop { start: pc });
+                self.instructions[pc] = Instruction::Loop { loops: loops.clone(), counter: counter.clone(), end };
+                for exit in self.loops.pop().unwrap_or_default() {
+                    self.instructions[exit] = match self.instructions[exit] {
+                        Instruction::Break { .. } => Instruction::Break { end: Some(end) },
+                        _ => Instruction::Continue { end: Some(end) },
+                    };
+                }
+            },
+            Command::IfCommand { condition, then, otherwise } => {
+                self.instructions.push(Instruction::Branch { condition: condition.clone(), otherwise: 0 });
+                self.block(then, false)?;
+                let otherwise_pc = match otherwise.is_empty() {
+                    true => self.instructions.len(),
+                    false => {
+                        let jump = self.instructions.len();
+                        self.instructions.push(Instruction::Jump { target: 0 });
+                        let otherwise_pc = self.instructions.len();
+                        self.block(otherwise, false)?;
+                        self.instructions[jump] = Instruction::Jump { target: self.instructions.len() };
+                        otherwise_pc
+                    },
+                };
+                self.instructions[pc] = Instruction::Branch { condition: condition.clone(), otherwise: otherwise_pc };
+            },
+            Command::BreakCommand | Command::ContinueCommand => {
+                if let (None, Some(procedure)) = (self.loops.last(), &self.procedure) {
+                    return Err(format!("{:?} outside of a repeat block in procedure '{}'", command, procedure));
+                }
+                self.instructions.push(match command {
+                    Command::BreakCommand => Instruction::Break { end: None },
+                    _ => Instruction::Continue { end: None },
+                });
+                if let Some(exits) = self.loops.last_mut() {
+                    exits.push(pc);
+                }
+            },
+            Command::CallCommand { name, arguments } => {
+                self.instructions.push(Instruction::Call {
+                    target: 0,
+                    arguments: arguments.iter().map(|(name, argument)| (name.clone(), argument.clone())).collect(),
+                });
+                self.calls.push((pc, name.clone()));
+            },
+            Command::WaitUntilCommand { on_timeout: OnTimeout::Goto(label), .. } => {
+                self.instructions.push(Instruction::Wait { command: command.clone(), timeout: Some(pc + 2) });
+                self.instructions.push(Instruction::Jump { target: pc + 3 });
+                self.instructions.push(Instruction::Goto { target: 0, unwind: 0 });
+                self.gotos.push((pc + 2, (self.procedure.clone(), label.clone()), self.path.clone()));
+            },
+            Command::WaitUntilCommand { .. } => {
+                self.instructions.push(Instruction::Wait { command: command.clone(), timeout: None });
+            },
+            Command::GotoCommand { label } => {
+                self.instructions.push(Instruction::Goto { target: 0, unwind: 0 });
+                self.gotos.push((pc, (self.procedure.clone(), label.clone()), self.path.clone()));
+            },
+            Command::LabelCommand { name } => {
+                if self.labels.insert((self.procedure.clone(), name.clone()), (pc, self.path.clone())).is_some() {
+                    return Err(match &self.procedure {
+                        Some(procedure) => format!("label '{}' is defined more than once in procedure '{}'", name, procedure),
+                        None => format!("label '{}' is defined more than once", name),
+                    });
+                }
+                self.instructions.push(Instruction::Execute(command.clone()));
+            },
+            _ => self.instructions.push(Instruction::Execute(command.clone())),
+        }
+        Ok(())
+    }
+
+    /// Resolves the gotos, which may only jump to labels in the same or an enclosing block, and the calls.
+    fn link(&mut self) -> Result<(), String> {
+        for (pc, (procedure, label), path) in &self.gotos {
+            let (target, label_path) = match self.labels.get(&(procedure.clone(), label.clone())) {
+                Some(found) => found,
+                None if self.labels.keys().any(|(_, other)| other == label) => return Err(format!("goto '{}' leaves or enters a procedure", label)),
+                None => return Err(format!("goto refers to the unknown label '{}'", label)),
+            };
+            if !path.starts_with(label_path) {
+                return Err(format!("goto '{}' jumps into a nested block", label));
+            }
+            let unwind = path[label_path.len()..].iter().filter(|(_, is_loop)| *is_loop).count();
+            self.instructions[*pc] = Instruction::Goto { target: *target, unwind };
+        }
+        for (pc, name) in &self.calls {
+            if let Instruction::Call { arguments, .. } = &self.instructions[*pc] {
+                let (target, parameters) = self.procedures
+                    .get(name)
+                    .ok_or_else(|| format!("call refers to the unknown procedure '{}'", name))?;
+                if let Some(parameter) = parameters.iter().find(|p| !arguments.iter().any(|(name, _)| name == *p)) {
+                    return Err(format!("call of '{}' misses the argument '{}'", name, parameter));
+                }
+                if let Some((argument, _)) = arguments.iter().find(|(name, _)| !parameters.contains(name)) {
+                    return Err(format!("procedure '{}' has no parameter '{}'", name, argument));
+                }
+                let arguments = arguments.clone();
+                self.instructions[*pc] = Instruction::Call { target: *target, arguments };
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::replay::{Command, Commands, Loops};
+
+    use super::{Instruction, Program};
+
+    fn compile(yaml: &str) -> Result<Program, String> {
+        let commands: Commands = serde_yaml::from_str(yaml).map_err(|e| e.to_string())?;
+        Program::compile(&commands)
+    }
+
+    #[test]
+    fn flat() {
+        let program = compile("
+            loops: Infinite
+            commands:
+              - sleep:
+                    millis: 10
+              - label:
+                    name: tail
+              - sleep:
+                    millis: 20
+        ").expect("valid program");
+        assert_eq!(3, program.len());
+        assert_eq!(Some(1), program.find_label("tail"));
+        assert!(matches!(program.get(2), Some(Instruction::Execute(Command::SleepCommand { .. }))));
+    }
+
+    #[test]
+    fn control_flow() {
+        let program = compile("
+            loops:
+                Amount: 1
+            commands:
+              - label:
+                    name: top
+              - repeat:
+                    loops:
+                        Amount: 3
+                    counter: i
+                    commands:
+                      - if:
+                            condition: i == 1
+                            then:
+                              - continue
+                            else:
+                              - break
+                      - goto:
+                            label: top
+              - break
+        ").expect("valid program");
+        assert_eq!(9, program.len());
+        assert_eq!(3, program.entries());
+        assert_eq!(vec![Some((0, 0)), Some((1, 7)), Some((8, 8)), None], (0..4).map(|entry| program.span(entry)).collect::<Vec<_>>());
+        assert_eq!((Some(1), None), (program.entry_of(1), program.entry_of(2)));
+        assert_eq!(Some(&Instruction::Loop { loops: Loops::Amount(3.into()), counter: Some("i".into()), end: 7 }), program.get(1));
+        assert_eq!(Some(&Instruction::Branch { condition: "i == 1".into(), otherwise: 5 }), program.get(2));
+        assert_eq!(Some(&Instruction::Continue { end: Some(7) }), program.get(3));
+        assert_eq!(Some(&Instruction::Jump { target: 6 }), program.get(4));
+        assert_eq!(Some(&Instruction::Break { end: Some(7) }), program.get(5));
+        assert_eq!(Some(&Instruction::Goto { target: 0, unwind: 1 }), program.get(6));
+        assert_eq!(Some(&Instruction::EndLoop { start: 1 }), program.get(7));
+        assert_eq!(Some(&Instruction::Break { end: None }), program.get(8));
+    }
+
+    #[test]
+    fn wait_until() {
+        let program = compile("
+            loops: Infinite
+            commands:
+              - label:
+                    name: retry
+              - wait_until:
+                    condition:
+                        window_exists: Editor
+                    timeout: 5000
+                    on_timeout:
+                        goto: retry
+              - keyboard:
+                    key: A
+        ").expect("valid program");
+        assert_eq!(5, program.len());
+        assert!(matches!(program.get(1), Some(Instruction::Wait { timeout: Some(3), .. })));
+        assert_eq!(Some(&Instruction::Jump { target: 4 }), program.get(2));
+        assert_eq!(Some(&Instruction::Goto { target: 0, unwind: 0 }), program.get(3));
+    }
+
+    #[test]
+    fn procedures() {
+        let program = compile("
+            loops: Infinite
+            procedures:
+                press:
+                    parameters:
+                      - times
+                    commands:
+                      - label:
+                            name: inner
+                      - sleep:
+                            millis: times
+            commands:
+              - call:
+                    name: press
+                    arguments:
+                        times: 2
+        ").expect("valid program");
+        assert_eq!(1, program.len());
+        assert_eq!(None, program.find_label("inner"));
+        assert_eq!(Some(&Instruction::Call { target: 1, arguments: vec![("times".into(), 2.into())] }), program.get(0));
+        assert_eq!(Some(&Instruction::Return), program.get(3));
+    }
+
+    #[test]
+    fn label_scopes() {
+        let program = compile("
+            loops: Infinite
+            procedures:
+                first:
+                    commands:
+                      - label:
+                            name: retry
+                      - goto:
+                            label: retry
+                second:
+                    commands:
+                      - label:
+                            name: retry
+                      - goto:
+                            label: retry
+            commands:
+              - label:
+                    name: retry
+              - call:
+                    name: first
+              - call:
+                    name: second
+              - goto:
+                    label: retry
+        ").expect("valid program");
+        assert_eq!(Some(0), program.find_label("retry"));
+        assert_eq!(Some(&Instruction::Goto { target: 0, unwind: 0 }), program.get(3));
+        assert_eq!(Some(&Instruction::Goto { target: 4, unwind: 0 }), program.get(5));
+        assert_eq!(Some(&Instruction::Goto { target: 7, unwind: 0 }), program.get(8));
+    }
+
+    #[test]
+    fn invalid() {
+        vec![
+            "
+            loops: Infinite
+            commands:
+              - goto:
+                    label: missing
+            ",
+            "
+            loops: Infinite
+            commands:
+              - label:
+                    name: twice
+              - label:
+                    name: twice
+            ",
+            "
+            loops: Infinite
+            commands:
+              - goto:
+                    label: inner
+              - repeat:
+                    loops: Infinite
+                    commands:
+                      - label:
+                            name: inner
+            ",
+            "
+            loops: Infinite
+            commands:
+              - call:
+                    name: missing
+            ",
+            "
+            loops: Infinite
+            procedures:
+                press:
+                    parameters:
+                      - times
+                    commands: []
+            commands:
+              - call:
+                    name: press
+            ",
+            "
+            loops: Infinite
+            procedures:
+                press:
+                    commands:
+                      - break
+            commands: []
+            ",
+            "
+            loops: Infinite
+            procedures:
+                press:
+                    commands:
+                      - goto:
+                            label: main
+            commands:
+              - label:
+                    name: main
+            ",
+            "
+            loops: Infinite
+            procedures:
+                press:
+                    commands:
+                      - label:
+                            name: twice
+                      - label:
+                            name: twice
+            commands: []
+            ",
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, yaml)| assert!(compile(yaml).is_err(), "case {}", case));
+    }
+}
@@ -1,71 +1,77 @@
-use std::{fs::File, io::BufWriter, path::PathBuf, sync::mpsc::Receiver};
+use std::{path::PathBuf, sync::mpsc::Receiver};
 
 use bindings::Windows::Win32::{Foundation::POINT, UI::WindowsAndMessaging::GetCursorPos};
 
-use crate::{eventgrid::Signal, hardware::Key, replay::{Command, Loops, command::Commands}};
+use crate::{eventgrid::Signal, hardware::Key, replay::{Command, Format, Loops}};
 
+use super::journal::Journal;
+
+/// Appends every record to a journal right away and turns it into the out file once the recording stops.
 pub struct Recorder {
     recording: bool,
-    records: Vec<Command>,
+    records: usize,
     out_file: PathBuf,
+    format: Format,
     rx: Receiver<Signal>,
 }
 
 impl Recorder {
     
-    pub fn new(out_file: PathBuf, rx: Receiver<Signal>) -> Self {
+    pub fn new(out_file: PathBuf, format: Format, rx: Receiver<Signal>) -> Self {
         Self {
             recording: false,
-            records: Vec::with_capacity(128),
+            records: 0,
             out_file,
+            format,
             rx,
         }
     }
 
-    pub fn start(&mut self) {
+    /// Records until the shutdown, fails if the journal or the out file cannot be written.
+    pub fn start(&mut self) -> Result<(), String> {
+        let mut journal = Journal::create(&self.out_file)?;
         info!("start recording or pause it later by pressing UP");
         while let Ok(signal) = self.rx.recv() {
             match signal {
-                Signal::Input(key) if self.recording => self.record(key),
+                Signal::Input(key) if self.recording => self.record(key, &mut journal),
                 Signal::Input(key) => warn!("discarding {:?} because recorder is paused", key),
                 Signal::Pause => self.recording = !self.recording,
-                Signal::Shutdown => break,
+                Signal::Shutdown | Signal::Finished => break,
             }
         }
         info!("recording stopped");
-        self.flush();
+        self.flush(journal)
     }
 
-    fn record(&mut self, key: Key) {
-        if key.is_mouse() {
+    fn record(&mut self, key: Key, journal: &mut Journal) {
+        let command = if key.is_mouse() {
             let position= &mut POINT { x: 0, y: 0 };
             unsafe { GetCursorPos(position); }
-            self.records.push(Command::MouseCommand{
+            info!("{:?} at ({}|{}) recorded", key, position.x, position.y);
+            Command::MouseCommand{
                 key,
                 loops: Loops::default(),
-                x: position.x,
-                y: position.y,
-            });
-            info!("{:?} at ({}|{}) recorded", key, position.x, position.y);
+                x: position.x.into(),
+                y: position.y.into(),
+            }
         } else {
-            self.records.push(Command::KeyboardCommand{ 
+            info!("{:?} recorded", key);
+            Command::KeyboardCommand{ 
                 key,
+                modifiers: Vec::new(),
                 loops: Loops::default(), 
-            });
-            info!("{:?} recorded", key);
+            }
+        };
+        match journal.append(&command) {
+            Ok(_) => self.records += 1,
+            Err(what) => error!("{:?} is lost: {}", key, what),
         }
     }
 
-    fn flush(&mut self) {
-        info!("writing {} records to {}", self.records.len(), self.out_file.to_string_lossy());
-        let commands = Commands::from(&mut self.records);        
-        match File::create(self.out_file.as_path())
-            .map_err(|e| format!("failed to create file: {:?}", e.kind()))
-            .and_then(|file| Ok(BufWriter::new(file)))
-            .and_then(|writer| serde_yaml::to_writer(writer, &commands).map_err(|e| e.to_string())) 
-        {
-            Ok(_) => info!("writing finished"),
-            Err(what) => error!("{}", what),
-        }            
+    fn flush(&mut self, journal: Journal) -> Result<(), String> {
+        info!("writing {} records to {}", self.records, self.out_file.to_string_lossy());
+        journal.finish(&self.out_file, self.format)?;
+        info!("writing finished");
+        Ok(())
     }
 }
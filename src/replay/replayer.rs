@@ -1,32 +1,116 @@
-use std::{sync::{Arc, atomic::{AtomicBool, Ordering}, mpsc::Receiver}, thread, time::Duration};
+use std::{path::{Path, PathBuf}, sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}, mpsc::{Receiver, Sender, channel}}, thread, time::{Duration, Instant, SystemTime}};
 
 use bindings::Windows::Win32::Foundation::POINT;
 
-use crate::{eventgrid::Signal, hardware::{Key, Mouse}};
+use crate::{eventgrid::Signal, expression::{Scope, Value}, hardware::{Image, Key, Screen}, lifecycle::{CancellationToken, Lifecycle}};
 
-use super::Commands;
+use super::{Call, Command, Condition, Instruction, Position, Program, Report, backend::{Backend, DryRun, Hardware}, command::{BUILTIN_VARIABLES, Limit}, condition::{self, Region}, report::Verdict, timeline::Timeline};
+
+const MAX_CALL_DEPTH: usize = 100;
+
+#[derive(Clone, Debug, Default)]
+pub struct ReplayOptions {
+    pub debug: bool,
+    pub from: usize,
+    pub to: Option<usize>,
+    pub resume: Option<Position>,
+    pub checkpoint: Option<PathBuf>,
+    /// The directory receiving a screenshot whenever a command or assertion fails.
+    pub failure_screenshots: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Step {
+    Execute,
+    Skip,
+    Cancelled,
+}
+
+enum Flow {
+    Next,
+    EndIteration,
+    Stop,
+}
+
+struct Frame {
+    start: usize,
+    iteration: usize,
+    limit: Limit,
+}
+
+struct State {
+    pc: usize,
+    iteration: usize,
+    frames: Vec<Frame>,
+    calls: Vec<Call>,
+}
+
+impl State {
+
+    fn position(&self, scope: &Scope) -> Position {
+        Position {
+            iteration: self.iteration,
+            command: self.pc,
+            loops: self.frames.iter().map(|frame| (frame.start, frame.iteration)).collect(),
+            variables: scope
+                .iter()
+                .filter(|(name, _)| !BUILTIN_VARIABLES.contains(&name.as_str()))
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+            calls: self.calls.clone(),
+        }
+    }
+}
 
 pub struct Replayer {
-    commands: Commands,
+    program: Program,
+    options: ReplayOptions,
+    screen: Arc<dyn Screen>,
+    tx: Sender<Signal>,
     rx: Receiver<Signal>,
     paused: Arc<AtomicBool>,
+    report: Arc<Mutex<Report>>,
+}
+
+struct Playback {
+    program: Program,
+    options: ReplayOptions,
+    screen: Arc<dyn Screen>,
+    backend: Arc<dyn Backend>,
+    paused: Arc<AtomicBool>,
+    steps: Receiver<Step>,
+    report: Arc<Mutex<Report>>,
 }
 
 impl Replayer {
 
-    pub fn new(commands: Commands, rx: Receiver<Signal>) -> Self {
+    pub fn new(program: Program, options: ReplayOptions, screen: Arc<dyn Screen>, tx: Sender<Signal>, rx: Receiver<Signal>) -> Self {
         Self {
-            commands,
+            program,
+            options,
+            screen,
+            tx,
             rx,
             paused: Arc::new(AtomicBool::new(true)),
+            report: Arc::new(Mutex::new(Report::default())),
         }
     }
 
-    pub fn start(&mut self) {
-        self.worker();
+    /// The assertions executed so far, complete once the replay worker stopped.
+    pub fn report(&self) -> Report {
+        self.report.lock().map(|report| report.clone()).unwrap_or_default()
+    }
+
+    pub fn start(&mut self, lifecycle: &mut Lifecycle) {
+        let steps = self.worker(lifecycle);
         info!("replay prepared, start/pause with UP");
+        if self.options.debug {
+            info!("debug mode, while paused execute the next command with RIGHT or skip it with LEFT");
+        }
         while let Ok(event) = self.rx.recv() {
             match event {
+                Signal::Input(Key::KeyboardRight) if self.options.debug => self.step(&steps, Step::Execute),
+                Signal::Input(Key::KeyboardLeft) if self.options.debug => self.step(&steps, Step::Skip),
                 Signal::Input(_) => (),
                 Signal::Pause => {
                     match self.paused.load(Ordering::Acquire) {
@@ -41,48 +125,1001 @@ impl Replayer {
                     }
                 },
                 Signal::Shutdown => break,
+                Signal::Finished => {
+                    info!("replay finished");
+                    break;
+                },
             }
         }
         info!("shutting down replayer")
     }
 
-    fn worker(&self) {
-        let paused = self.paused.clone();
-        let commands = self.commands.clone();
-        thread::spawn(move || {
-            commands
-                .iter_loop()
-                .flat_map(|_| commands.iter_commands())
-                .for_each(|command| {
-                    info!("replaying {:?}", command);
-                    for _i in command.iter() {
-                        while paused.load(Ordering::Relaxed) {
-                            thread::sleep(Duration::from_millis(20));
+    fn step(&self, steps: &Sender<Step>, step: Step) {
+        match self.paused.load(Ordering::Acquire) {
+            true => {
+                if steps.send(step).is_err() {
+                    warn!("replay worker is gone, discarding {:?}", step);
+                }
+            },
+            false => warn!("stepping is only possible while paused"),
+        }
+    }
+
+    fn worker(&self, lifecycle: &mut Lifecycle) -> Sender<Step> {
+        let (steps_tx, steps_rx) = channel();
+        let playback = Playback {
+            program: self.program.clone(),
+            options: self.options.clone(),
+            screen: self.screen.clone(),
+            backend: Arc::new(Hardware),
+            paused: self.paused.clone(),
+            steps: steps_rx,
+            report: self.report.clone(),
+        };
+        let tx = self.tx.clone();
+        lifecycle.spawn("replayer", move |token| {
+            if let Err(what) = playback.replay(&token) {
+                error!("replay aborted: {}", what);
+                if let Ok(mut report) = playback.report.lock() {
+                    report.abort(&what);
+                }
+            }
+            if !token.is_cancelled() && tx.send(Signal::Finished).is_err() {
+                error!("failed to publish the end of the replay");
+            }
+        });
+        steps_tx
+    }
+
+    /// Walks the program like a replay started right away, but every action only ends up on the returned timeline.
+    pub fn dry_run(program: Program, options: ReplayOptions, screen: Arc<dyn Screen>) -> (Timeline, Report) {
+        Self::simulate(program, options, screen, Arc::new(DryRun::default()))
+    }
+
+    fn simulate(program: Program, options: ReplayOptions, screen: Arc<dyn Screen>, backend: Arc<DryRun>) -> (Timeline, Report) {
+        let (_, steps) = channel();
+        let playback = Playback {
+            program,
+            options,
+            screen,
+            backend: backend.clone(),
+            paused: Arc::new(AtomicBool::new(false)),
+            steps,
+            report: Arc::new(Mutex::new(Report::default())),
+        };
+        if let Err(what) = playback.replay(&CancellationToken::new()) {
+            error!("dry run aborted: {}", what);
+            if let Ok(mut report) = playback.report.lock() {
+                report.abort(&what);
+            }
+        }
+        let report = playback.report.lock().map(|report| report.clone()).unwrap_or_default();
+        (backend.timeline(), report)
+    }
+}
+
+impl Playback {
+
+    /// Runs the program and returns the variables as they were when the replay ended.
+    fn replay(&self, token: &CancellationToken) -> Result<Scope, String> {
+        let mut scope = self.program.scope()?;
+        let last = match self.options.to.or_else(|| self.program.len().checked_sub(1)) {
+            Some(last) => last,
+            None => return Ok(scope),
+        };
+        let mut resume = self.options.resume.clone();
+        if let Some(position) = &resume {
+            position.variables.iter().for_each(|(name, value)| scope.set(name, value.clone()));
+        }
+        let first = resume.as_ref().map_or(0, |position| position.iteration);
+        let clock = || self.backend.now();
+        for iteration in self.program.iter_loop(&scope, &clock)?.skip(first) {
+            scope.set("iteration", Value::from(iteration as i64));
+            let mut state = match resume.take() {
+                Some(position) => self.restore(&position, &scope)?,
+                None => State { pc: self.options.from, iteration, frames: Vec::new(), calls: Vec::new() },
+            };
+            while (state.pc <= last || !state.calls.is_empty()) && !token.is_cancelled() {
+                let instruction = match self.program.get(state.pc) {
+                    Some(instruction) => instruction,
+                    None => break,
+                };
+                if !self.backend.step(self.unbounded(&state), token) {
+                    break;
+                }
+                let flow = self.step(instruction, &mut state, &mut scope, token)
+                    .inspect_err(|_| self.save_failure_screenshot(state.pc))?;
+                match flow {
+                    Flow::Next => (),
+                    Flow::EndIteration => break,
+                    Flow::Stop => return Ok(scope),
+                }
+            }
+            if token.is_cancelled() {
+                break;
+            }
+        }
+        Ok(scope)
+    }
+
+    fn restore(&self, position: &Position, scope: &Scope) -> Result<State, String> {
+        let frames = position.loops
+            .iter()
+            .map(|&(start, iteration)| match self.program.get(start) {
+                Some(Instruction::Loop { loops, .. }) => Ok(Frame { start, iteration, limit: loops.limit(scope, self.backend.now())? }),
+                _ => Err(format!("the checkpoint expects a repeat block at #{}", start)),
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(State { pc: position.command, iteration: position.iteration, frames, calls: position.calls.clone() })
+    }
+
+    /// Whether the replay is inside a loop which does not repeat a fixed amount of times.
+    fn unbounded(&self, state: &State) -> bool {
+        !self.program.loops().is_bounded() || state.frames.iter().any(|frame| !matches!(frame.limit, Limit::Amount(_)))
+    }
+
+    fn step(&self, instruction: &Instruction, state: &mut State, scope: &mut Scope, token: &CancellationToken) -> Result<Flow, String> {
+        let pc = state.pc;
+        match instruction {
+            Instruction::Execute(command) => {
+                let clock = || self.backend.now();
+                let unbounded = self.unbounded(state) || matches!(command,
+                    Command::MouseCommand { loops, .. } | Command::KeyboardCommand { loops, .. } if !loops.is_bounded());
+                for repetition in command.iter(scope, &clock)? {
+                    if repetition > 0 && !self.backend.step(unbounded, token) {
+                        return Ok(Flow::Stop);
+                    }
+                    scope.set("repetition", Value::from(repetition as i64));
+                    if self.options.debug {
+                        self.trace(state, command, repetition);
+                    }
+                    match self.await_turn(state, scope, token) {
+                        Step::Execute => self.execute(state.pc, command, scope, token)
+                            .map_err(|e| format!("command #{} failed: {}", state.pc, e))?,
+                        Step::Skip => {
+                            info!("skipping #{}", state.pc);
+                            break;
+                        },
+                        Step::Cancelled => return Ok(Flow::Stop),
+                    }
+                    self.backend.sleep(Duration::from_millis(1), token);
+                }
+                state.pc += 1;
+            },
+            Instruction::Wait { command, timeout } => {
+                if self.options.debug {
+                    self.trace(state, command, 0);
+                }
+                match self.await_turn(state, scope, token) {
+                    Step::Execute => (),
+                    Step::Skip => {
+                        info!("skipping #{}", state.pc);
+                        state.pc += 1;
+                        return Ok(Flow::Next);
+                    },
+                    Step::Cancelled => return Ok(Flow::Stop),
+                }
+                let met = self.wait(command, scope, token).map_err(|e| format!("command #{} failed: {}", state.pc, e))?;
+                match (met, timeout) {
+                    (true, _) => state.pc += 1,
+                    (false, _) if token.is_cancelled() => return Ok(Flow::Stop),
+                    (false, Some(target)) => {
+                        info!("#{} timed out", state.pc);
+                        state.pc = *target;
+                    },
+                    (false, None) => return Err(format!("command #{} timed out waiting for {:?}", state.pc, command)),
+                }
+            },
+            Instruction::Loop { loops, counter, end } => {
+                let limit = loops.limit(scope, self.backend.now())?;
+                match limit.allows(0, self.backend.now()) {
+                    true => {
+                        if let Some(counter) = counter {
+                            scope.set(counter, Value::from(0));
                         }
-                        match command {
-                            super::Command::MouseCommand { key, x, y , ..} =>  {
-                                let point = POINT{ x: *x, y: *y, };
-                                match key {
-                                    Key::MouseLeft => {
-                                        Mouse::LeftDown.trigger(&point);
-                                        Mouse::LeftUp.trigger(&point);
-                                    },
-                                    Key::MouseRight => {
-                                        Mouse::RightDown.trigger(&point);
-                                        Mouse::RightUp.trigger(&point);
-                                    },
-                                    _ => error!("faulty/unimplemented mouse command {:?}", key),
-                                }
-                            },
-                            super::Command::KeyboardCommand { key , ..} => {
-                                key.press();
-                                key.release();
-                            },
-                            super::Command::SleepCommand { millis } => thread::sleep(Duration::from_millis(*millis)),
+                        state.frames.push(Frame { start: state.pc, iteration: 0, limit });
+                        state.pc += 1;
+                    },
+                    false => state.pc = end + 1,
+                }
+            },
+            Instruction::EndLoop { start } => {
+                match state.frames.last_mut().filter(|frame| frame.start == *start) {
+                    Some(frame) if frame.limit.allows(frame.iteration + 1, self.backend.now()) => {
+                        frame.iteration += 1;
+                        if let Some(Instruction::Loop { counter: Some(counter), .. }) = self.program.get(*start) {
+                            scope.set(counter, Value::from(frame.iteration as i64));
                         }
-                        thread::sleep(Duration::from_millis(1));
+                        state.pc = start + 1;
+                    },
+                    Some(_) => {
+                        state.frames.pop();
+                        state.pc += 1;
+                    },
+                    None => state.pc += 1,
+                }
+            },
+            Instruction::Branch { condition, otherwise } => {
+                state.pc = match condition.evaluate(scope)?.as_bool()? {
+                    true => state.pc + 1,
+                    false => *otherwise,
+                };
+            },
+            Instruction::Jump { target } => state.pc = *target,
+            Instruction::Break { end: Some(end) } => {
+                if let Some(Instruction::EndLoop { start }) = self.program.get(*end) {
+                    if state.frames.last().map(|frame| frame.start) == Some(*start) {
+                        state.frames.pop();
                     }
-                });
+                }
+                state.pc = end + 1;
+            },
+            Instruction::Break { end: None } => return Ok(Flow::Stop),
+            Instruction::Continue { end: Some(end) } => state.pc = *end,
+            Instruction::Continue { end: None } => return Ok(Flow::EndIteration),
+            Instruction::Goto { target, unwind } => {
+                let kept = state.frames.len().saturating_sub(*unwind);
+                state.frames.truncate(kept);
+                state.pc = *target;
+            },
+            Instruction::Call { target, arguments } => {
+                if state.calls.len() >= MAX_CALL_DEPTH {
+                    return Err(format!("procedure calls at #{} nest deeper than {} levels", state.pc, MAX_CALL_DEPTH));
+                }
+                let values = arguments
+                    .iter()
+                    .map(|(name, argument)| argument.evaluate(scope).map(|value| (name, value)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let shadowed = values
+                    .into_iter()
+                    .map(|(name, value)| {
+                        let previous = scope.remove(name);
+                        scope.set(name, value);
+                        (name.clone(), previous)
+                    })
+                    .collect();
+                state.calls.push(Call { ret: state.pc + 1, loops: state.frames.len(), shadowed });
+                state.pc = *target;
+            },
+            Instruction::Return => {
+                let call = state.calls.pop().ok_or_else(|| format!("return at #{} outside of a procedure", state.pc))?;
+                for (name, previous) in call.shadowed {
+                    match previous {
+                        Some(value) => scope.set(&name, value),
+                        None => {
+                            scope.remove(&name);
+                        },
+                    }
+                }
+                state.frames.truncate(call.loops);
+                state.pc = call.ret;
+            },
+        }
+        match instruction {
+            Instruction::Execute(_) | Instruction::Wait { .. } => Ok(Flow::Next),
+            _ if state.pc <= pc => Ok(self.back_edge(pc, state, scope, token)),
+            _ => Ok(Flow::Next),
+        }
+    }
+
+    /// Jumping back pauses like a command does, so loops which skip all their commands can still be paused,
+    /// stepped through and cancelled and do not spin.
+    fn back_edge(&self, pc: usize, state: &State, scope: &Scope, token: &CancellationToken) -> Flow {
+        if self.options.debug {
+            info!("#{} jumps back to #{}", pc, state.pc);
+        }
+        if self.await_turn(state, scope, token) == Step::Cancelled || !self.backend.sleep(Duration::from_millis(1), token) {
+            return Flow::Stop;
+        }
+        Flow::Next
+    }
+
+    /// Polls the condition of a `wait_until` command until the timeout passed, time spent paused does not count.
+    fn wait(&self, command: &Command, scope: &Scope, token: &CancellationToken) -> Result<bool, String> {
+        let (condition, interval, timeout) = match command {
+            Command::WaitUntilCommand { condition, interval, timeout, .. } => (condition, interval, timeout),
+            other => return Err(format!("{:?} is no wait command", other)),
+        };
+        let interval = Duration::from_millis(interval.evaluate(scope)?.as_u64()?.max(1));
+        let timeout = timeout.as_ref().map(|millis| millis.evaluate(scope)?.as_u64().map(Duration::from_millis)).transpose()?;
+        let template = condition.load_template()?;
+        self.backend.wait(condition, timeout);
+        let started = self.backend.now();
+        let mut paused = Duration::from_millis(0);
+        loop {
+            // a dry run has to stop polling a condition which never holds
+            if !self.backend.step(timeout.is_none(), token) {
+                return Ok(false);
+            }
+            let polled = self.backend.now();
+            let is_paused = self.paused.load(Ordering::Relaxed);
+            if !is_paused {
+                if condition.is_met_with(scope, self.screen.as_ref(), template.as_ref())? {
+                    return Ok(true);
+                }
+                let waited = polled.duration_since(started).saturating_sub(paused);
+                if timeout.is_some_and(|timeout| waited >= timeout) {
+                    return Ok(false);
+                }
+            }
+            if !self.backend.sleep(interval, token) {
+                return Ok(false);
+            }
+            if is_paused {
+                paused += self.backend.now().duration_since(polled);
+            }
+        }
+    }
+
+    fn trace(&self, state: &State, command: &Command, repetition: usize) {
+        let loops = std::iter::once(state.iteration)
+            .chain(state.frames.iter().map(|frame| frame.iteration))
+            .map(|iteration| iteration.to_string())
+            .collect::<Vec<_>>()
+            .join("/");
+        let upcoming = self.program
+            .get(state.pc + 1)
+            .map_or_else(|| String::from("end of loop"), |next| format!("#{} {:?}", state.pc + 1, next));
+        info!("[loop {}] #{} {:?} (repetition {}), up next: {}", loops, state.pc, command, repetition, upcoming);
+    }
+
+    fn await_turn(&self, state: &State, scope: &Scope, token: &CancellationToken) -> Step {
+        if self.paused.load(Ordering::Relaxed) {
+            self.save_checkpoint(state, scope);
+        }
+        while self.paused.load(Ordering::Relaxed) && !token.is_cancelled() {
+            if let Ok(step) = self.steps.try_recv() {
+                return step;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        // steps left over from the last pause must not be taken by the next one
+        let stale = self.steps.try_iter().count();
+        if stale > 0 {
+            info!("discarding {} step(s) requested before resuming", stale);
+        }
+        match token.is_cancelled() {
+            true => Step::Cancelled,
+            false => Step::Execute,
+        }
+    }
+
+    fn save_checkpoint(&self, state: &State, scope: &Scope) {
+        if let Some(path) = &self.options.checkpoint {
+            let position = state.position(scope);
+            match position.save(path) {
+                Ok(_) => info!("saved position #{} of loop {} to {}", position.command, position.iteration, path.to_string_lossy()),
+                Err(what) => error!("failed to save checkpoint: {}", what),
+            }
+        }
+    }
+
+    fn execute(&self, pc: usize, command: &Command, scope: &mut Scope, token: &CancellationToken) -> Result<(), String> {
+        match command {
+            Command::MouseCommand { key, x, y , ..} =>  {
+                let point = POINT{ x: x.evaluate(scope)?.as_i32()?, y: y.evaluate(scope)?.as_i32()?, };
+                if let Err(what) = self.backend.click(*key, &point) {
+                    error!("faulty/unimplemented mouse command: {}", what);
+                }
+            },
+            Command::KeyboardCommand { key, modifiers, .. } => self.backend.press(*key, modifiers),
+            Command::SleepCommand { millis } => {
+                self.backend.sleep(Duration::from_millis(millis.evaluate(scope)?.as_u64()?), token);
+            },
+            Command::LabelCommand { .. } => (),
+            Command::SetCommand { name, value } => {
+                let value = value.evaluate(scope)?;
+                scope.set(name, value);
+            },
+            Command::IncrementCommand { name, by } => {
+                let current = scope.get(name).ok_or_else(|| format!("variable '{}' is not set", name))?;
+                let value = current.add(&by.evaluate(scope)?)?;
+                scope.set(name, value);
+            },
+            Command::FindImageCommand { image, region, confidence, variable, click } => {
+                let template = Image::load(image)?;
+                let (center, score) = condition::locate(self.screen.as_ref(), &template, region.as_ref(), *confidence, scope)?
+                    .ok_or_else(|| format!("'{}' is not on the screen", image.to_string_lossy()))?;
+                info!("found '{}' at ({}|{}) with a confidence of {:.3}", image.to_string_lossy(), center.x, center.y, score);
+                if let Some(name) = variable {
+                    scope.set(name, Value::Point(center.x as f64, center.y as f64));
+                }
+                if let Some(key) = click {
+                    self.backend.click(*key, &center)?;
+                }
+            },
+            Command::AssertCommand { condition, name, abort } => self.assert(pc, condition, name.as_deref(), *abort, scope)?,
+            Command::ScreenshotCommand { path, region } => {
+                let path = PathBuf::from(path.render(scope)?);
+                self.save_screenshot(&path, region.as_ref(), scope)?;
+            },
+            other => return Err(format!("{:?} cannot be executed directly", other)),
+        }
+        Ok(())
+    }
+
+    /// Records the verdict of an assertion, which only fails the command if it aborts the replay.
+    fn assert(&self, pc: usize, condition: &Condition, name: Option<&str>, abort: bool, scope: &Scope) -> Result<(), String> {
+        let started = Instant::now();
+        let failure = match condition.is_met(scope, self.screen.as_ref()) {
+            Ok(true) => None,
+            Ok(false) => Some(format!("{:?} does not hold", condition)),
+            Err(what) => Some(what),
+        };
+        let name = name.map_or_else(|| format!("#{} {:?}", pc, condition), String::from);
+        match &failure {
+            Some(what) => warn!("assertion '{}' failed: {}", name, what),
+            None => info!("assertion '{}' passed", name),
+        }
+        if let Ok(mut report) = self.report.lock() {
+            report.record(Verdict { name, failure: failure.clone(), duration: started.elapsed() });
+        }
+        match failure {
+            Some(what) if abort => Err(what),
+            Some(_) => {
+                self.save_failure_screenshot(pc);
+                Ok(())
+            },
+            None => Ok(()),
+        }
+    }
+
+    fn save_screenshot(&self, path: &Path, region: Option<&Region>, scope: &Scope) -> Result<(), String> {
+        let (_, _, image) = condition::capture(self.screen.as_ref(), region, scope)?;
+        self.backend.save(path, &image)?;
+        info!("saved screenshot {}", path.to_string_lossy());
+        Ok(())
+    }
+
+    fn save_failure_screenshot(&self, pc: usize) {
+        if let Some(directory) = &self.options.failure_screenshots {
+            let timestamp = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+            let path = directory.join(format!("failure-{}-{}.png", timestamp.as_secs(), pc));
+            if let Err(what) = self.save_screenshot(&path, None, &Scope::default()) {
+                error!("failed to save a screenshot of the failure: {}", what);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}, mpsc::channel}, time::Duration};
+
+    use std::{path::Path, time::Instant};
+
+    use bindings::Windows::Win32::Foundation::POINT;
+
+    use crate::{expression::Value, hardware::{Image, ImageScreen, Key}, lifecycle::CancellationToken, replay::{Commands, Condition, Position, Program, Report, backend::save_screenshot}};
+
+    use super::{Backend, DryRun, Playback, ReplayOptions, Replayer, State, Step};
+
+    /// Replays on the dry run backend, tests must never send input to the machine running them.
+    fn playback(yaml: &str, options: ReplayOptions) -> (Playback, std::sync::mpsc::Sender<Step>) {
+        playback_with(yaml, options, Arc::new(DryRun::default()))
+    }
+
+    fn playback_with(yaml: &str, options: ReplayOptions, backend: Arc<dyn Backend>) -> (Playback, std::sync::mpsc::Sender<Step>) {
+        let (tx, rx) = channel();
+        let commands: Commands = serde_yaml::from_str(yaml).expect("valid commands");
+        let playback = Playback {
+            program: Program::compile(&commands).expect("valid program"),
+            options,
+            screen: Arc::new(ImageScreen::load("./test/screen.png".as_ref()).expect("valid image")),
+            backend,
+            paused: Arc::new(AtomicBool::new(false)),
+            steps: rx,
+            report: Arc::new(Mutex::new(Report::default())),
+        };
+        (playback, tx)
+    }
+
+    #[test]
+    fn await_turn() {
+        let checkpoint = std::env::temp_dir().join(format!("clicker-bot-checkpoint-{}.yaml", std::process::id()));
+        let (playback, tx) = playback("
+            loops: Infinite
+            variables:
+                x: 4
+            commands: []
+        ", ReplayOptions { checkpoint: Some(checkpoint.clone()), ..ReplayOptions::default() });
+        let token = CancellationToken::new();
+        let scope = playback.program.scope().unwrap();
+        let state = State { pc: 5, iteration: 2, frames: Vec::new(), calls: Vec::new() };
+        assert_eq!(Step::Execute, playback.await_turn(&state, &scope, &token));
+        assert!(!checkpoint.exists());
+        playback.paused.store(true, Ordering::Relaxed);
+        tx.send(Step::Skip).unwrap();
+        assert_eq!(Step::Skip, playback.await_turn(&state, &scope, &token));
+        assert_eq!(Ok(state.position(&scope)), Position::load(&checkpoint));
+        assert_eq!(Some(&Value::Number(4.0)), Position::load(&checkpoint).unwrap().variables.get("x"));
+        tx.send(Step::Execute).unwrap();
+        tx.send(Step::Skip).unwrap();
+        tx.send(Step::Skip).unwrap();
+        assert_eq!(Step::Execute, playback.await_turn(&state, &scope, &token));
+        playback.paused.store(false, Ordering::Relaxed);
+        assert_eq!(Step::Execute, playback.await_turn(&state, &scope, &token));
+        assert!(playback.steps.try_recv().is_err());
+        token.cancel();
+        assert_eq!(Step::Cancelled, playback.await_turn(&state, &scope, &token));
+        let _ = std::fs::remove_file(checkpoint);
+    }
+
+    #[test]
+    fn skipping_loop() {
+        let checkpoint = std::env::temp_dir().join(format!("clicker-bot-skipping-{}.yaml", std::process::id()));
+        let (playback, _tx) = playback("
+            commands:
+              - repeat:
+                    loops: Infinite
+                    commands:
+                      - if:
+                            condition: 1 > 2
+                            then:
+                              - sleep:
+                                    millis: 10
+        ", ReplayOptions { checkpoint: Some(checkpoint.clone()), ..ReplayOptions::default() });
+        playback.paused.store(true, Ordering::Relaxed);
+        let token = CancellationToken::new();
+        let cancel = token.clone();
+        let (done_tx, done_rx) = channel();
+        let worker = std::thread::spawn(move || done_tx.send(playback.replay(&token).is_ok()));
+        // the loop has no command to pause at, only jumping back lets it stop there
+        let paused = (0..100).any(|_| {
+            std::thread::sleep(Duration::from_millis(10));
+            checkpoint.exists()
+        });
+        cancel.cancel();
+        assert!(paused);
+        assert_eq!(Ok(true), done_rx.recv_timeout(Duration::from_secs(5)));
+        worker.join().expect("finished worker").expect("sent result");
+        let _ = std::fs::remove_file(checkpoint);
+    }
+
+    #[test]
+    fn control_flow() {
+        let (playback, _tx) = playback("
+            loops:
+                Amount: 2
+            variables:
+                sum: 0
+                skipped: 0
+                jumps: 0
+            commands:
+              - repeat:
+                    loops:
+                        Amount: 5
+                    counter: i
+                    commands:
+                      - if:
+                            condition: i == 1
+                            then:
+                              - increment:
+                                    name: skipped
+                              - continue
+                      - if:
+                            condition: i == 3
+                            then:
+                              - break
+                      - increment:
+                            name: sum
+                            by: i
+              - if:
+                    condition: jumps < 1
+                    then:
+                      - increment:
+                            name: jumps
+                      - goto:
+                            label: tail
+                    else:
+                      - break
+              - increment:
+                    name: sum
+                    by: 100
+              - label:
+                    name: tail
+        ", ReplayOptions::default());
+        let scope = playback.replay(&CancellationToken::new()).expect("successful replay");
+        vec![
+            ("sum", Value::Number(4.0)),
+            ("skipped", Value::Number(2.0)),
+            ("jumps", Value::Number(1.0)),
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, (name, value))| assert_eq!(Some(&value), scope.get(name), "case {}", case));
+    }
+
+    #[test]
+    fn wait_until() {
+        let (failing, _tx) = playback("
+            variables:
+                found: 0
+                timeouts: 0
+            commands:
+              - wait_until:
+                    condition:
+                        file_exists: ./test/commands.yaml
+              - increment:
+                    name: found
+              - wait_until:
+                    condition:
+                        file_exists: ./test/missing.yaml
+                    interval: 10
+                    timeout: 30
+                    on_timeout:
+                        goto: timeout
+              - increment:
+                    name: found
+              - label:
+                    name: timeout
+              - increment:
+                    name: timeouts
+              - wait_until:
+                    condition:
+                        file_exists: ./test/missing.yaml
+                    interval: 10
+                    timeout: 20
+              - increment:
+                    name: found
+        ", ReplayOptions::default());
+        let result = failing.replay(&CancellationToken::new());
+        assert!(matches!(&result, Err(e) if e.contains("timed out")), "{:?}", result.map(|_| ()));
+        let (jumping, _tx) = playback("
+            variables:
+                found: 0
+            commands:
+              - wait_until:
+                    condition:
+                        file_exists: ./test/missing.yaml
+                    interval: 10
+                    timeout: 30
+                    on_timeout:
+                        goto: timeout
+              - increment:
+                    name: found
+              - label:
+                    name: timeout
+        ", ReplayOptions::default());
+        let scope = jumping.replay(&CancellationToken::new()).expect("successful replay");
+        assert_eq!(Some(&Value::Number(0.0)), scope.get("found"));
+    }
+
+    /// A dry run which writes screenshots and whose clock keeps moving by `busy` while the replay works, like
+    /// checking a condition takes time.
+    #[derive(Default)]
+    struct Simulated {
+        dry_run: DryRun,
+        busy: Duration,
+        worked: Mutex<Duration>,
+    }
+
+    impl Backend for Simulated {
+
+        fn click(&self, key: Key, point: &POINT) -> Result<(), String> {
+            self.dry_run.click(key, point)
+        }
+
+        fn press(&self, key: Key, modifiers: &[Key]) {
+            self.dry_run.press(key, modifiers)
+        }
+
+        fn sleep(&self, duration: Duration, token: &CancellationToken) -> bool {
+            self.dry_run.sleep(duration, token)
+        }
+
+        fn wait(&self, condition: &Condition, timeout: Option<Duration>) {
+            self.dry_run.wait(condition, timeout)
+        }
+
+        fn save(&self, path: &Path, image: &Image) -> Result<(), String> {
+            self.dry_run.save(path, image).and_then(|_| save_screenshot(path, image))
+        }
+
+        fn now(&self) -> Instant {
+            let mut worked = self.worked.lock().unwrap();
+            *worked += self.busy;
+            self.dry_run.now() + *worked
+        }
+
+        fn step(&self, unbounded: bool, token: &CancellationToken) -> bool {
+            self.dry_run.step(unbounded, token)
+        }
+    }
+
+    #[test]
+    fn slow_wait() {
+        let backend = Arc::new(Simulated { busy: Duration::from_millis(400), ..Simulated::default() });
+        let (playback, _tx) = playback_with("
+            commands:
+              - wait_until:
+                    condition:
+                        file_exists: ./test/missing.yaml
+                    interval: 100
+                    timeout: 1000
+                    on_timeout:
+                        goto: end
+              - label:
+                    name: end
+        ", ReplayOptions::default(), backend.clone());
+        assert!(playback.replay(&CancellationToken::new()).is_ok());
+        // the time spent checking counts towards the timeout, not only the intervals slept
+        let elapsed = backend.dry_run.timeline().elapsed();
+        assert!(elapsed < Duration::from_millis(300), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn wait_for_pixel() {
+        let (playback, _tx) = playback("
+            variables:
+                lights: 0
+            commands:
+              - wait_until:
+                    condition:
+                        pixel:
+                            x: 5
+                            y: 5
+                            color: \"#30cc70\"
+                            tolerance: 4
+                    timeout: 0
+              - increment:
+                    name: lights
+              - wait_until:
+                    condition:
+                        pixel:
+                            x: 6
+                            y: 5
+                            color: \"#2ecc71\"
+                    interval: 5
+                    timeout: 10
+                    on_timeout:
+                        goto: end
+              - increment:
+                    name: lights
+              - label:
+                    name: end
+        ", ReplayOptions::default());
+        let scope = playback.replay(&CancellationToken::new()).expect("successful replay");
+        assert_eq!(Some(&Value::Number(1.0)), scope.get("lights"));
+    }
+
+    #[test]
+    fn find_image() {
+        let (finding, _tx) = playback("
+            commands:
+              - find_image:
+                    image: ./test/button.png
+                    variable: button
+              - find_image:
+                    image: ./test/button.png
+                    region: { x: 15, y: 8, width: 12, height: 8 }
+                    confidence: 0.99
+                    variable: framed
+        ", ReplayOptions::default());
+        let scope = finding.replay(&CancellationToken::new()).expect("successful replay");
+        assert_eq!(Some(&Value::Point(23.0, 12.0)), scope.get("button"));
+        assert_eq!(Some(&Value::Point(23.0, 12.0)), scope.get("framed"));
+        vec![
+            "image: ./test/button.png, region: { x: 0, y: 0, width: 12, height: 8 }",
+            "image: ./test/button.png, region: { x: 30, y: 20, width: 12, height: 8 }",
+            "image: ./test/missing.png",
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, option)| {
+            let (failing, _tx) = playback(&format!("commands: [ {{ find_image: {{ {} }} }} ]", option), ReplayOptions::default());
+            assert!(failing.replay(&CancellationToken::new()).is_err(), "case {}", case);
         });
     }
+
+    #[test]
+    fn assertions() {
+        let (asserting, _tx) = playback("
+            commands:
+              - assert:
+                    condition:
+                        pixel: { x: 5, y: 5, color: \"#2ecc71\" }
+                    name: green
+              - assert:
+                    condition:
+                        image: { image: ./test/button.png }
+              - assert:
+                    condition:
+                        file_exists: ./test/missing.yaml
+                    name: missing
+              - assert:
+                    condition:
+                        pixel: { x: 99, y: 5, color: \"#2ecc71\" }
+                    abort: true
+              - set:
+                    name: unreachable
+                    value: 1
+        ", ReplayOptions::default());
+        assert!(asserting.replay(&CancellationToken::new()).is_err());
+        let report = asserting.report.lock().unwrap().clone();
+        assert_eq!(2, report.failures());
+        assert_eq!("2 of 4 assertions passed", report.summary());
+        let junit = report.to_junit("smoke");
+        assert!(junit.contains("<testcase name=\"green\" classname=\"smoke\""), "{}", junit);
+        assert!(junit.contains("<testcase name=\"missing\""), "{}", junit);
+    }
+
+    #[test]
+    fn screenshots() {
+        let directory = std::env::temp_dir().join(format!("clicker-bot-screenshots-{}", std::process::id()));
+        let (shooting, _tx) = playback_with(&format!("
+            loops:
+                Amount: 2
+            commands:
+              - screenshot:
+                    path: {}/shot-{{iteration}}.png
+                    region: {{ x: 20, y: 10, width: 6, height: 4 }}
+              - wait_until:
+                    condition:
+                        file_exists: {}/missing.png
+                    timeout: 0
+        ", directory.to_string_lossy(), directory.to_string_lossy()), ReplayOptions { failure_screenshots: Some(directory.clone()), ..Default::default() }, Arc::new(Simulated::default()));
+        assert!(shooting.replay(&CancellationToken::new()).is_err());
+        assert_eq!(Image::load("./test/button.png".as_ref()), Image::load(&directory.join("shot-0.png")));
+        assert!(!directory.join("shot-1.png").exists());
+        let failures: Vec<_> = std::fs::read_dir(&directory)
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with("failure-"))
+            .collect();
+        assert_eq!(1, failures.len());
+        assert!(failures[0].ends_with("-1.png"), "{}", failures[0]);
+        assert_eq!(Ok((32, 24)), Image::load(&directory.join(&failures[0])).map(|image| (image.width(), image.height())));
+        let _ = std::fs::remove_dir_all(directory);
+    }
+
+    #[test]
+    fn procedures() {
+        let (playback, _tx) = playback("
+            loops:
+                Amount: 1
+            variables:
+                total: 0
+                n: 100
+            procedures:
+                sum:
+                    parameters:
+                      - n
+                    commands:
+                      - if:
+                            condition: n > 0
+                            then:
+                              - increment:
+                                    name: total
+                                    by: n
+                              - call:
+                                    name: sum
+                                    arguments:
+                                        n: n - 1
+            commands:
+              - call:
+                    name: sum
+                    arguments:
+                        n: 4
+        ", ReplayOptions::default());
+        let scope = playback.replay(&CancellationToken::new()).expect("successful replay");
+        assert_eq!(Some(&Value::Number(10.0)), scope.get("total"));
+        assert_eq!(Some(&Value::Number(100.0)), scope.get("n"));
+    }
+
+    #[test]
+    fn dry_run() {
+        let commands = |yaml: &str| {
+            let commands: Commands = serde_yaml::from_str(yaml).expect("valid commands");
+            Program::compile(&commands).expect("valid program")
+        };
+        let screen = Arc::new(ImageScreen::load("./test/screen.png".as_ref()).expect("valid image"));
+        let (timeline, report) = Replayer::dry_run(commands("
+            loops:
+                Amount: 2
+            commands:
+              - mouse:
+                    key: MouseLeft
+                    loops:
+                        Amount: 2
+                    x: 10
+                    y: \"5 + iteration\"
+              - keyboard:
+                    key: S
+                    modifiers: [Control]
+              - sleep:
+                    millis: 500
+              - screenshot:
+                    path: shots/{iteration}.png
+        "), ReplayOptions::default(), screen.clone());
+        assert!(report.is_success());
+        assert_eq!(Some(Duration::from_millis(1010)), timeline.duration());
+        assert_eq!("\
+00:00:00.000 click MouseLeft at (10|5)
+00:00:00.001 click MouseLeft at (10|5)
+00:00:00.002 press Control+S
+00:00:00.504 screenshot shots/0.png
+00:00:00.505 click MouseLeft at (10|6)
+00:00:00.506 click MouseLeft at (10|6)
+00:00:00.507 press Control+S
+00:00:01.009 screenshot shots/1.png", timeline.render());
+        assert!(!std::path::Path::new("shots").exists());
+        let (timeline, _) = Replayer::dry_run(commands("
+            commands:
+              - repeat:
+                    loops:
+                        Duration: 60000
+                    commands:
+                      - sleep:
+                            millis: 1000
+        "), ReplayOptions::default(), screen.clone());
+        assert!(timeline.duration().is_some_and(|duration| duration >= Duration::from_secs(60) && duration < Duration::from_secs(62)), "{:?}", timeline.duration());
+        let (timeline, report) = Replayer::simulate(commands("
+            loops: Infinite
+            commands:
+              - mouse:
+                    key: MouseLeft
+                    x: 1
+                    y: 1
+        "), ReplayOptions::default(), screen.clone(), Arc::new(DryRun::new(1000)));
+        assert!(report.is_success());
+        assert_eq!(None, timeline.duration());
+        assert!(timeline.summary().ends_with("infinite"), "{}", timeline.summary());
+        // an endless loop is detected even if it never sleeps or executes a command
+        let (timeline, _) = Replayer::simulate(commands("
+            commands:
+              - repeat:
+                    loops: Infinite
+                    commands:
+                      - if:
+                            condition: 1 > 2
+                            then:
+                              - sleep:
+                                    millis: 10
+        "), ReplayOptions::default(), screen.clone(), Arc::new(DryRun::new(1000)));
+        assert!(timeline.summary().ends_with("infinite"), "{}", timeline.summary());
+        // long replays which end are no endless ones, even if they use up the budget
+        let (timeline, _) = Replayer::simulate(commands("
+            loops:
+                Amount: 2000
+            commands:
+              - mouse:
+                    key: MouseLeft
+                    x: 1
+                    y: 1
+        "), ReplayOptions::default(), screen.clone(), Arc::new(DryRun::new(1000)));
+        assert!(timeline.summary().ends_with("at least 00:00:01.000"), "{}", timeline.summary());
+        let (timeline, _) = Replayer::dry_run(commands("
+            loops:
+                Amount: 200000
+            commands:
+              - mouse:
+                    key: MouseLeft
+                    x: 1
+                    y: 1
+        "), ReplayOptions::default(), screen.clone());
+        assert_eq!(200_000, timeline.len());
+        assert_eq!(Some(Duration::from_secs(200)), timeline.duration());
+        // a condition which never holds is polled until the budget is used up
+        let (timeline, _) = Replayer::simulate(commands("
+            commands:
+              - wait_until:
+                    condition:
+                        file_exists: ./test/missing.yaml
+                    interval: 50
+        "), ReplayOptions::default(), screen.clone(), Arc::new(DryRun::new(1000)));
+        assert_eq!("00:00:00.000 wait until FileExists(\"./test/missing.yaml\")", timeline.render());
+        assert!(timeline.summary().ends_with("infinite"), "{}", timeline.summary());
+        let (timeline, report) = Replayer::dry_run(commands("
+            commands:
+              - wait_until:
+                    condition:
+                        file_exists: ./test/missing.yaml
+                    timeout: 1000
+        "), ReplayOptions::default(), screen);
+        assert!(!report.is_success());
+        assert_eq!("00:00:00.000 wait until FileExists(\"./test/missing.yaml\") for at most 1000ms", timeline.render());
+        assert!(timeline.duration().is_some_and(|duration| duration >= Duration::from_secs(1)), "{:?}", timeline.duration());
+    }
 }
@@ -0,0 +1,131 @@
+use std::{fs, path::Path, time::Duration};
+
+/// The outcome of a single executed assertion.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Verdict {
+    pub name: String,
+    pub failure: Option<String>,
+    pub duration: Duration,
+}
+
+/// Collects the assertions of a replay and the error which aborted it, if any.
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    verdicts: Vec<Verdict>,
+    error: Option<String>,
+}
+
+impl Report {
+
+    pub fn record(&mut self, verdict: Verdict) {
+        self.verdicts.push(verdict);
+    }
+
+    pub fn abort(&mut self, error: &str) {
+        self.error = Some(error.into());
+    }
+
+    pub fn failures(&self) -> usize {
+        self.verdicts.iter().filter(|verdict| verdict.failure.is_some()).count()
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.error.is_none() && self.failures() == 0
+    }
+
+    pub fn summary(&self) -> String {
+        let summary = format!("{} of {} assertions passed", self.verdicts.len() - self.failures(), self.verdicts.len());
+        match &self.error {
+            Some(error) => format!("{}, replay aborted: {}", summary, error),
+            None => summary,
+        }
+    }
+
+    /// Renders the report as JUnit XML, an abort is reported as an additional test case with an error.
+    pub fn to_junit(&self, suite: &str) -> String {
+        let errors = self.error.iter().count();
+        let tests = self.verdicts.len() + errors;
+        let time: f64 = self.verdicts.iter().map(|verdict| verdict.duration.as_secs_f64()).sum();
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml += &format!("<testsuites tests=\"{}\" failures=\"{}\" errors=\"{}\">\n", tests, self.failures(), errors);
+        xml += &format!("  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">\n", escape(suite), tests, self.failures(), errors, time);
+        for verdict in &self.verdicts {
+            let testcase = format!("    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"", escape(&verdict.name), escape(suite), verdict.duration.as_secs_f64());
+            match &verdict.failure {
+                Some(failure) => xml += &format!("{}>\n      <failure message=\"{}\"/>\n    </testcase>\n", testcase, escape(failure)),
+                None => xml += &format!("{}/>\n", testcase),
+            }
+        }
+        if let Some(error) = &self.error {
+            xml += &format!("    <testcase name=\"replay\" classname=\"{}\">\n      <error message=\"{}\"/>\n    </testcase>\n", escape(suite), escape(error));
+        }
+        xml += "  </testsuite>\n</testsuites>\n";
+        xml
+    }
+
+    pub fn save_junit(&self, suite: &str, path: &Path) -> Result<(), String> {
+        fs::write(path, self.to_junit(suite)).map_err(|e| format!("failed to write '{}' because '{:?}'", path.to_string_lossy(), e.kind()))
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '&' => String::from("&amp;"),
+            '<' => String::from("&lt;"),
+            '>' => String::from("&gt;"),
+            '"' => String::from("&quot;"),
+            '\'' => String::from("&apos;"),
+            '\n' => String::from("&#10;"),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Report, Verdict};
+
+    fn verdict(name: &str, failure: Option<&str>) -> Verdict {
+        Verdict { name: name.into(), failure: failure.map(String::from), duration: Duration::from_millis(250) }
+    }
+
+    #[test]
+    fn success() {
+        let mut report = Report::default();
+        assert!(report.is_success());
+        report.record(verdict("title", None));
+        assert!(report.is_success());
+        report.record(verdict("logo", Some("not found")));
+        assert!(!report.is_success());
+        assert_eq!("1 of 2 assertions passed", report.summary());
+        let mut aborted = Report::default();
+        aborted.abort("command #2 failed");
+        assert!(!aborted.is_success());
+        assert_eq!("0 of 0 assertions passed, replay aborted: command #2 failed", aborted.summary());
+    }
+
+    #[test]
+    fn to_junit() {
+        let mut report = Report::default();
+        report.record(verdict("title", None));
+        report.record(verdict("<logo> & \"text\"", Some("expected 'a'")));
+        report.abort("timed out");
+        assert_eq!("\
+<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<testsuites tests=\"3\" failures=\"1\" errors=\"1\">
+  <testsuite name=\"login\" tests=\"3\" failures=\"1\" errors=\"1\" time=\"0.500\">
+    <testcase name=\"title\" classname=\"login\" time=\"0.250\"/>
+    <testcase name=\"&lt;logo&gt; &amp; &quot;text&quot;\" classname=\"login\" time=\"0.250\">
+      <failure message=\"expected &apos;a&apos;\"/>
+    </testcase>
+    <testcase name=\"replay\" classname=\"login\">
+      <error message=\"timed out\"/>
+    </testcase>
+  </testsuite>
+</testsuites>
+", report.to_junit("login"));
+    }
+}
@@ -0,0 +1,30 @@
+use schemars::schema_for;
+
+use super::Commands;
+
+/// The JSON Schema of replay files, derived from the types they are deserialized into.
+pub fn schema() -> Result<String, String> {
+    serde_json::to_string_pretty(&schema_for!(Commands)).map_err(|e| format!("failed to serialize the schema: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use crate::hardware::Key;
+
+    use super::schema;
+
+    #[test]
+    fn definitions() {
+        let schema: Value = serde_json::from_str(&schema().expect("valid schema")).expect("valid JSON");
+        vec!["Command", "Condition", "Loops", "Key", "Argument", "Color", "Template"]
+            .into_iter()
+            .enumerate()
+            .for_each(|(case, name)| assert!(schema["definitions"][name].is_object(), "case {}", case));
+        let keys = schema["definitions"]["Key"]["enum"].as_array().expect("key names");
+        assert!(keys.iter().all(|key| key.as_str().is_some_and(|key| key.parse::<Key>().is_ok())));
+        assert!(keys.contains(&Value::from("MouseX2")) && keys.contains(&Value::from("Escape")));
+        assert_eq!(Value::from("^#[0-9a-fA-F]{6}$"), schema["definitions"]["Color"]["pattern"]);
+    }
+}
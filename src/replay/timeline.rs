@@ -0,0 +1,90 @@
+use std::time::Duration;
+
+/// The actions of a dry run, each at the time it would happen after the start of the replay.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Timeline {
+    actions: Vec<(Duration, String)>,
+    elapsed: Duration,
+    /// Set if the dry run was stopped inside a loop which never ends on its own.
+    truncated: bool,
+    /// Set if the dry run was stopped in a replay which ends, but takes too long to simulate.
+    cut_short: bool,
+}
+
+impl Timeline {
+
+    pub fn record(&mut self, action: String) {
+        self.actions.push((self.elapsed, action));
+    }
+
+    pub fn advance(&mut self, duration: Duration) {
+        self.elapsed += duration;
+    }
+
+    pub fn truncate(&mut self) {
+        self.truncated = true;
+    }
+
+    pub fn cut_short(&mut self) {
+        self.cut_short = true;
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn len(&self) -> usize {
+        self.actions.len()
+    }
+
+    /// The run time of the replay, `None` if it never ends or the dry run did not reach its end.
+    pub fn duration(&self) -> Option<Duration> {
+        Some(self.elapsed).filter(|_| !self.truncated && !self.cut_short)
+    }
+
+    pub fn render(&self) -> String {
+        self.actions
+            .iter()
+            .map(|(at, action)| format!("{} {}", timestamp(*at), action))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn summary(&self) -> String {
+        match self.duration() {
+            Some(duration) => format!("{} events, estimated run time {}", self.len(), timestamp(duration)),
+            None if self.truncated => format!("{} events within {} before the dry run was stopped, estimated run time infinite", self.len(), timestamp(self.elapsed)),
+            None => format!("{} events before the dry run reached its limit of instructions, estimated run time at least {}", self.len(), timestamp(self.elapsed)),
+        }
+    }
+}
+
+/// Formats the time like `01:02:03.456`.
+fn timestamp(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!("{:02}:{:02}:{:02}.{:03}", seconds / 3600, seconds / 60 % 60, seconds % 60, duration.subsec_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Timeline;
+
+    #[test]
+    fn summary() {
+        let mut timeline = Timeline::default();
+        timeline.record(String::from("click MouseLeft at (1|2)"));
+        timeline.advance(Duration::from_millis(3_723_456));
+        timeline.record(String::from("press A"));
+        assert_eq!("00:00:00.000 click MouseLeft at (1|2)\n01:02:03.456 press A", timeline.render());
+        assert_eq!("2 events, estimated run time 01:02:03.456", timeline.summary());
+        let mut cut = timeline.clone();
+        cut.cut_short();
+        assert_eq!(None, cut.duration());
+        assert!(cut.summary().ends_with("estimated run time at least 01:02:03.456"), "{}", cut.summary());
+        timeline.truncate();
+        assert_eq!(None, timeline.duration());
+        assert!(timeline.summary().ends_with("estimated run time infinite"));
+    }
+}
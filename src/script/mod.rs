@@ -0,0 +1,190 @@
+use std::{convert::TryFrom, path::Path, sync::{Arc, atomic::{AtomicBool, Ordering}, mpsc::{Receiver, Sender}}, thread, time::Duration};
+
+use bindings::Windows::Win32::Foundation::POINT;
+use rhai::{AST, Dynamic, Engine, EvalAltResult, Map};
+
+use crate::{eventgrid::Signal, hardware::{Desktop, Key, Mouse, Screen}, lifecycle::{CancellationToken, Lifecycle}};
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+pub fn compile(path: &Path) -> Result<AST, String> {
+    Engine::new()
+        .compile_file(path.into())
+        .map_err(|e| format!("'{}' contains an invalid script: {}", path.to_string_lossy(), e))
+}
+
+pub struct ScriptRunner {
+    ast: AST,
+    tx: Sender<Signal>,
+    rx: Receiver<Signal>,
+    paused: Arc<AtomicBool>,
+    failed: Arc<AtomicBool>,
+}
+
+impl ScriptRunner {
+
+    pub fn new(ast: AST, tx: Sender<Signal>, rx: Receiver<Signal>) -> Self {
+        Self {
+            ast,
+            tx,
+            rx,
+            paused: Arc::new(AtomicBool::new(true)),
+            failed: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Whether the script aborted with an error, known once the script worker stopped.
+    pub fn failed(&self) -> bool {
+        self.failed.load(Ordering::Acquire)
+    }
+
+    pub fn start(&mut self, lifecycle: &mut Lifecycle) {
+        self.worker(lifecycle);
+        info!("script prepared, start/pause with UP");
+        while let Ok(event) = self.rx.recv() {
+            match event {
+                Signal::Input(_) => (),
+                Signal::Pause => {
+                    match self.paused.load(Ordering::Acquire) {
+                        true => {
+                            self.paused.store(false, Ordering::Release);
+                            info!("resume");
+                        }
+                        false => {
+                            self.paused.store(true, Ordering::Release);
+                            info!("pause");
+                        }
+                    }
+                },
+                Signal::Shutdown => break,
+                Signal::Finished => {
+                    info!("script finished");
+                    break;
+                },
+            }
+        }
+        info!("shutting down script runner")
+    }
+
+    fn worker(&self, lifecycle: &mut Lifecycle) {
+        let ast = self.ast.clone();
+        let paused = self.paused.clone();
+        let failed = self.failed.clone();
+        let tx = self.tx.clone();
+        lifecycle.spawn("script", move |token| {
+            let engine = engine(&token, &paused);
+            if let Err(what) = engine.run_ast(&ast) {
+                if !token.is_cancelled() {
+                    error!("script aborted: {}", what);
+                    failed.store(true, Ordering::Release);
+                }
+            }
+            if !token.is_cancelled() && tx.send(Signal::Finished).is_err() {
+                error!("failed to publish the end of the script");
+            }
+        });
+    }
+}
+
+/// Creates an engine exposing the hardware functions, which halts while paused and terminates once cancelled.
+fn engine(token: &CancellationToken, paused: &Arc<AtomicBool>) -> Engine {
+    let mut engine = Engine::new();
+    let (progress_token, progress_paused) = (token.clone(), paused.clone());
+    engine.on_progress(move |_| {
+        while progress_paused.load(Ordering::Relaxed) && !progress_token.is_cancelled() {
+            thread::sleep(Duration::from_millis(20));
+        }
+        match progress_token.is_cancelled() {
+            true => Some(Dynamic::UNIT),
+            false => None,
+        }
+    });
+    engine.on_print(|s| info!("{}", s));
+    engine.register_fn("press", |key: &str| -> ScriptResult<()> {
+        let key = parse_key(key)?;
+        key.press();
+        key.release();
+        Ok(())
+    });
+    engine.register_fn("click", |x: i64, y: i64| click(Key::MouseLeft, x, y));
+    engine.register_fn("click", |x: i64, y: i64, button: &str| click(parse_key(button)?, x, y));
+    engine.register_fn("move", |x: i64, y: i64| -> ScriptResult<()> {
+        Mouse::Move.trigger(&point(x, y)?);
+        Ok(())
+    });
+    let sleep_token = token.clone();
+    engine.register_fn("sleep", move |millis: i64| {
+        sleep_token.sleep(Duration::from_millis(millis.max(0) as u64));
+    });
+    engine.register_fn("cursor_position", || -> ScriptResult<Map> {
+        let position = Mouse::current_position().map_err(|_| "failed to retrieve mouse position")?;
+        Ok(map(vec![("x", position.x.into()), ("y", position.y.into())]))
+    });
+    engine.register_fn("pixel", |x: i64, y: i64| -> ScriptResult<Map> {
+        let position = point(x, y)?;
+        let color = Desktop.pixel(position.x, position.y)?;
+        Ok(map(vec![("red", color.red.into()), ("green", color.green.into()), ("blue", color.blue.into())]))
+    });
+    engine.register_fn("is_pressed", |key: &str| parse_key(key).map(|key| key.is_pressed()));
+    engine
+}
+
+fn parse_key(key: &str) -> ScriptResult<Key> {
+    key.parse().map_err(|e: String| e.into())
+}
+
+fn point(x: i64, y: i64) -> ScriptResult<POINT> {
+    match (i32::try_from(x), i32::try_from(y)) {
+        (Ok(x), Ok(y)) => Ok(POINT { x, y }),
+        _ => Err(format!("({}|{}) is out of range", x, y).into()),
+    }
+}
+
+fn click(key: Key, x: i64, y: i64) -> ScriptResult<()> {
+    Mouse::click(key, &point(x, y)?).map_err(|e| e.into())
+}
+
+fn map(entries: Vec<(&str, i64)>) -> Map {
+    entries
+        .into_iter()
+        .map(|(key, value)| (key.into(), Dynamic::from(value)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, atomic::AtomicBool};
+
+    use crate::lifecycle::CancellationToken;
+
+    use super::{compile, engine};
+
+    #[test]
+    fn compile_files() {
+        assert!(compile("./test/script.rhai".as_ref()).is_ok());
+        assert!(compile("./test/commands.yaml".as_ref()).is_err());
+        assert!(compile("./test/missing.rhai".as_ref()).is_err());
+    }
+
+    #[test]
+    fn cancel() {
+        let token = CancellationToken::new();
+        let engine = engine(&token, &Arc::new(AtomicBool::new(false)));
+        assert_eq!(Ok(6), engine.eval::<i64>("let n = 0; for i in 0..4 { n += i; } n").map_err(|e| e.to_string()));
+        token.cancel();
+        assert!(engine.run("loop { }").is_err());
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        let engine = engine(&CancellationToken::new(), &Arc::new(AtomicBool::new(false)));
+        vec![
+            "press(\"NoSuchKey\")",
+            "click(10, 20, \"A\")",
+            "move(10000000000, 0)",
+        ]
+        .into_iter()
+        .enumerate()
+        .for_each(|(case, script)| assert!(engine.run(script).is_err(), "case {}", case));
+    }
+}